@@ -0,0 +1,748 @@
+// rustsend-cli/src/main.rs
+
+//! Headless command-line front end: runs the file server or talks to one
+//! without the Tauri GUI, e.g. from CI boxes and SSH sessions.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rustsend_core::bisync::{self, BisyncAction, SyncState};
+use rustsend_core::client::{
+    ConflictMode, RemoteChangeKind, RemoteClient, RemoteDirEntry, SearchKind, SearchQuery,
+    UploadMode,
+};
+use rustsend_core::connection::{self, ConnectOptions};
+use rustsend_core::file_meta::PreservePolicy;
+use rustsend_core::hot_folder::{self, FolderWatcher};
+use rustsend_core::i18n::{self, Locale};
+use rustsend_core::listing::{ListOptions, SortKey};
+use rustsend_core::protocol;
+use rustsend_core::server_starter::{self, ServerOptions};
+use rustsend_core::symlink::SymlinkPolicy;
+use rustsend_core::sync::{self, SyncAction, SyncOptions};
+use rustsend_core::transfer::{self, TransferKind, TransferQueue, TransferState};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "rustsend",
+    version,
+    about = "RustSend file transfer (headless CLI)"
+)]
+struct Cli {
+    /// Server address used by the client subcommands
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "RUSTSEND_SERVER",
+        default_value = "http://127.0.0.1:50051"
+    )]
+    server: String,
+
+    /// Access token: sent by the client subcommands and, for `serve`,
+    /// required from every client
+    #[arg(long, global = true, env = "RUSTSEND_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Seconds to wait for the server to accept the connection
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        default_value_t = connection::DEFAULT_CONNECT_TIMEOUT.as_secs()
+    )]
+    connect_timeout: u64,
+
+    /// Seconds to wait for a request to be answered (0 = no limit; uploads are never limited)
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        default_value_t = connection::DEFAULT_REQUEST_TIMEOUT.as_secs()
+    )]
+    timeout: u64,
+
+    /// Send and receive file data uncompressed even if the server supports compression
+    #[arg(long, global = true)]
+    no_compression: bool,
+
+    /// File metadata to keep: a comma-separated list of mtime, atime, mode
+    /// and xattrs, or all / none. Sent with uploads, restored on downloads
+    /// and, for `serve`, restored on received files
+    #[arg(long, global = true, value_name = "LIST", default_value = "mtime,mode")]
+    preserve: PreservePolicy,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the file server in the foreground
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = server_starter::DEFAULT_LISTEN_ADDR)]
+        listen: SocketAddr,
+        /// Directory served as the sandbox root (defaults to the home directory)
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Refuse uploads that would grow the share beyond this size
+        /// (bytes, or with a K, M, G or T suffix)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        quota: Option<u64>,
+        /// Allocate the full size of each upload before receiving it (Linux)
+        #[arg(long)]
+        preallocate: bool,
+        /// Index the chunks of every file in the share so clients can send
+        /// deduplicated uploads (`send --dedup`); the index is kept in memory
+        #[arg(long)]
+        index_chunks: bool,
+    },
+    /// List a remote directory
+    Ls {
+        #[arg(default_value = "/")]
+        path: String,
+        /// Sort key; names compare case-insensitively
+        #[arg(long, value_enum, default_value_t = SortArg::Name)]
+        sort: SortArg,
+        /// Sort in descending order
+        #[arg(short, long)]
+        reverse: bool,
+        /// Leave out entries whose name starts with a '.'
+        #[arg(long)]
+        no_hidden: bool,
+        /// List directories before files
+        #[arg(long)]
+        dirs_first: bool,
+        /// Only names matching this glob (or, without wildcards, containing it)
+        #[arg(long, default_value = "")]
+        pattern: String,
+    },
+    /// Upload local files into a remote directory
+    Send {
+        /// Local files to upload
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Remote target directory
+        #[arg(short, long, default_value = "/")]
+        to: String,
+        /// What to do when the target file already exists
+        #[arg(long, value_enum, default_value_t = ConflictArg::Overwrite)]
+        on_conflict: ConflictArg,
+        /// Only send the parts that differ from an existing remote copy
+        #[arg(long)]
+        delta: bool,
+        /// Only send chunks that no file on the server already contains
+        #[arg(long, conflicts_with = "delta")]
+        dedup: bool,
+    },
+    /// Download a remote file
+    Get {
+        /// Remote file path
+        path: String,
+        /// Local destination file or directory
+        #[arg(default_value = ".")]
+        dest: PathBuf,
+    },
+    /// Make a remote directory match a local one (one-way)
+    SyncPush {
+        /// Local source directory
+        local: PathBuf,
+        /// Remote target directory
+        #[arg(default_value = "/")]
+        remote: String,
+        /// Also delete remote files and directories that do not exist locally
+        #[arg(long)]
+        mirror: bool,
+        /// Compare files of equal size by SHA-256 instead of modification time
+        #[arg(long)]
+        checksum: bool,
+        /// Only print the plan, change nothing
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Only send the parts that differ from an existing remote copy
+        #[arg(long)]
+        delta: bool,
+        /// Only send chunks that no file on the server already contains
+        #[arg(long, conflicts_with = "delta")]
+        dedup: bool,
+        /// Number of transfers running at the same time
+        #[arg(short, long, default_value_t = transfer::DEFAULT_CONCURRENCY)]
+        jobs: usize,
+        /// What to do with symlinks: send what they point to, recreate them
+        /// as links (targets inside the directory only) or leave them out
+        #[arg(long, value_enum, default_value_t = SymlinkArg::Skip)]
+        symlinks: SymlinkArg,
+    },
+    /// Synchronize a local and a remote directory in both directions
+    Sync {
+        /// Local directory
+        local: PathBuf,
+        /// Remote directory
+        #[arg(default_value = "/")]
+        remote: String,
+        /// Only print the plan, change nothing
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Number of transfers running at the same time
+        #[arg(short, long, default_value_t = transfer::DEFAULT_CONCURRENCY)]
+        jobs: usize,
+    },
+    /// Upload every file written into a local directory until interrupted
+    Watch {
+        /// Local directory to watch
+        dir: PathBuf,
+        /// Remote target directory
+        #[arg(short, long, default_value = "/")]
+        to: String,
+        /// Also watch subdirectories, keeping their structure on the server
+        #[arg(short, long)]
+        recursive: bool,
+        /// What to do when the target file already exists
+        #[arg(long, value_enum, default_value_t = ConflictArg::Overwrite)]
+        on_conflict: ConflictArg,
+        /// Only send the parts that differ from an existing remote copy
+        #[arg(long)]
+        delta: bool,
+        /// Only send chunks that no file on the server already contains
+        #[arg(long, conflicts_with = "delta")]
+        dedup: bool,
+    },
+    /// Print changes inside a remote directory as they happen
+    WatchRemote {
+        /// Remote directory to watch
+        #[arg(default_value = "/")]
+        path: String,
+        /// Also report changes in subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Show the size and free space of the server's file system
+    Df {
+        /// Remote path whose file system is reported
+        #[arg(default_value = "/")]
+        path: String,
+    },
+    /// Sum the sizes of the files under a remote path
+    Du {
+        #[arg(default_value = "/")]
+        path: String,
+    },
+    /// Find remote files and directories by name, type, size and age
+    Find {
+        /// Remote directory to search
+        #[arg(default_value = "/")]
+        root: String,
+        /// Case-insensitive glob (`*.jpg`) or, without wildcards, a part of
+        /// the name; matches everything when left out
+        #[arg(default_value = "")]
+        pattern: String,
+        /// Only entries of this type; symlinks are never followed
+        #[arg(long = "type", value_enum, default_value_t = KindArg::Any)]
+        kind: KindArg,
+        /// Only files of at least this size, e.g. 10M
+        #[arg(long, value_parser = parse_size)]
+        min_size: Option<u64>,
+        /// Only files of at most this size
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only entries modified at or after this time (Unix seconds)
+        #[arg(long)]
+        modified_after: Option<i64>,
+        /// Only entries modified at or before this time (Unix seconds)
+        #[arg(long)]
+        modified_before: Option<i64>,
+        /// Stop after this many matches (the server caps it as well)
+        #[arg(short = 'l', long)]
+        limit: Option<u32>,
+    },
+    /// Show the server's version and capabilities
+    Info,
+}
+
+fn upload_mode(delta: bool, dedup: bool) -> UploadMode {
+    if delta {
+        UploadMode::Delta
+    } else if dedup {
+        UploadMode::Dedup
+    } else {
+        UploadMode::Full
+    }
+}
+
+/// Parses a byte count such as `500000`, `750M` or `2G` (binary units).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown size unit \"{}\"", unit)),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size \"{}\"", s))?;
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size \"{}\" is too large", s))
+}
+
+fn print_entry(entry: &RemoteDirEntry) {
+    let suffix = if entry.is_dir { "/" } else { "" };
+    match (entry.is_symlink, &entry.symlink_target) {
+        (true, Some(target)) => println!("{}{} -> {}", entry.name, suffix, target),
+        // The server hides targets outside its share.
+        (true, None) => println!("{}{} -> ?", entry.name, suffix),
+        (false, _) => println!("{}{}", entry.name, suffix),
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConflictArg {
+    Fail,
+    Overwrite,
+    SkipIfIdentical,
+    Rename,
+    OverwriteIfNewer,
+}
+
+impl From<ConflictArg> for ConflictMode {
+    fn from(arg: ConflictArg) -> Self {
+        match arg {
+            ConflictArg::Fail => ConflictMode::Fail,
+            ConflictArg::Overwrite => ConflictMode::Overwrite,
+            ConflictArg::SkipIfIdentical => ConflictMode::SkipIfIdentical,
+            ConflictArg::Rename => ConflictMode::Rename,
+            ConflictArg::OverwriteIfNewer => ConflictMode::OverwriteIfNewer,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SymlinkArg {
+    Follow,
+    Preserve,
+    Skip,
+}
+
+impl From<SymlinkArg> for SymlinkPolicy {
+    fn from(arg: SymlinkArg) -> Self {
+        match arg {
+            SymlinkArg::Follow => SymlinkPolicy::Follow,
+            SymlinkArg::Preserve => SymlinkPolicy::Preserve,
+            SymlinkArg::Skip => SymlinkPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortArg {
+    Name,
+    Size,
+    Mtime,
+}
+
+impl From<SortArg> for SortKey {
+    fn from(arg: SortArg) -> Self {
+        match arg {
+            SortArg::Name => SortKey::Name,
+            SortArg::Size => SortKey::Size,
+            SortArg::Mtime => SortKey::Mtime,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KindArg {
+    Any,
+    File,
+    Dir,
+    Symlink,
+}
+
+impl From<KindArg> for SearchKind {
+    fn from(arg: KindArg) -> Self {
+        match arg {
+            KindArg::Any => SearchKind::Any,
+            KindArg::File => SearchKind::File,
+            KindArg::Dir => SearchKind::Dir,
+            KindArg::Symlink => SearchKind::Symlink,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // Like the rest of the CLI, transfer and error messages are in English.
+    i18n::set_locale(Locale::En);
+    // The server logs at info by default; client commands stay quiet unless RUST_LOG is set.
+    let default_level = match cli.command {
+        Command::Serve { .. } => "info",
+        _ => "warn",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .init();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl Cli {
+    fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            token: self.token.clone(),
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            request_timeout: (self.timeout > 0).then(|| Duration::from_secs(self.timeout)),
+            compression: !self.no_compression,
+            preserve: self.preserve,
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = cli.connect_options();
+    match cli.command {
+        Command::Serve {
+            listen,
+            root,
+            quota,
+            preallocate,
+            index_chunks,
+        } => {
+            let root = root
+                .or_else(dirs::home_dir)
+                .ok_or("could not determine the home directory, pass --root")?;
+            let server_options = ServerOptions {
+                preserve: cli.preserve,
+                quota,
+                preallocate,
+                chunk_index: index_chunks,
+                token: cli.token,
+            };
+            server_starter::start_server(listen, root, server_options).await
+        }
+        Command::Ls {
+            path,
+            sort,
+            reverse,
+            no_hidden,
+            dirs_first,
+            pattern,
+        } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let list_options = ListOptions {
+                show_hidden: !no_hidden,
+                sort: sort.into(),
+                descending: reverse,
+                dirs_first,
+                pattern,
+            };
+            if !remote
+                .capabilities()
+                .supports(protocol::FEATURE_LIST_PAGING)
+            {
+                let entries = remote.list_dir_with(path, &list_options).await?;
+                entries.iter().for_each(print_entry);
+                return Ok(());
+            }
+            // Pages are printed as they arrive, so huge directories start right away.
+            let mut listing = remote.list_dir_stream(path, &list_options, 0).await?;
+            while let Some(page) = listing.next().await? {
+                page.entries.iter().for_each(print_entry);
+            }
+            Ok(())
+        }
+        Command::Send {
+            files,
+            to,
+            on_conflict,
+            delta,
+            dedup,
+        } => {
+            let mode = upload_mode(delta, dedup);
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let mut failed = 0;
+            for file in &files {
+                let result = remote
+                    .upload(file, to.clone(), on_conflict.into(), mode)
+                    .await;
+                match result {
+                    Ok(status) if status.success => {
+                        println!("{}: {}", file.display(), status.message)
+                    }
+                    Ok(status) => {
+                        failed += 1;
+                        eprintln!("{}: {}", file.display(), status.message);
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        eprintln!("{}: {}", file.display(), e);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} of {} uploads failed", failed, files.len()).into());
+            }
+            Ok(())
+        }
+        Command::Get { path, dest } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let (saved, bytes) = remote.download_file(path, &dest).await?;
+            println!("{} ({} bytes)", saved.display(), bytes);
+            Ok(())
+        }
+        Command::SyncPush {
+            local,
+            remote: remote_dir,
+            mirror,
+            checksum,
+            dry_run,
+            delta,
+            dedup,
+            jobs,
+            symlinks,
+        } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let sync_options = SyncOptions {
+                compare_hash: checksum,
+                mirror,
+                mode: upload_mode(delta, dedup),
+                symlinks: symlinks.into(),
+            };
+            let plan = sync::plan_push(&remote, &local, &remote_dir, sync_options).await?;
+            for item in &plan.items {
+                let action = match item.action {
+                    SyncAction::Upload => "upload",
+                    SyncAction::Delete => "delete",
+                    SyncAction::Skip if dry_run => "skip",
+                    SyncAction::Skip => continue,
+                };
+                let suffix = match &item.symlink_target {
+                    Some(target) => format!(" -> {}", target),
+                    None if item.is_dir => "/".to_string(),
+                    None => String::new(),
+                };
+                println!("{:<7}{}{} ({:?})", action, item.path, suffix, item.reason);
+            }
+            println!(
+                "{} to upload ({} bytes), {} to delete, {} skipped",
+                plan.upload_count, plan.upload_bytes, plan.delete_count, plan.skip_count
+            );
+            if dry_run {
+                return Ok(());
+            }
+
+            let queue = TransferQueue::new(jobs);
+            let report = sync::execute(&plan, &remote, &queue).await;
+            for failure in &report.failed {
+                eprintln!("{}: {}", failure.path, failure.message);
+            }
+            println!("{} uploaded, {} deleted", report.uploaded, report.deleted);
+            if !report.failed.is_empty() {
+                return Err(format!("{} sync operations failed", report.failed.len()).into());
+            }
+            Ok(())
+        }
+        Command::Sync {
+            local,
+            remote: remote_dir,
+            dry_run,
+            jobs,
+        } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let mut state = SyncState::open(remote.url(), &local, &remote_dir);
+            let plan = bisync::plan(&remote, &local, &remote_dir, &state).await?;
+            for item in &plan.items {
+                let action = match item.action {
+                    BisyncAction::Upload => "upload",
+                    BisyncAction::Download => "download",
+                    BisyncAction::DeleteLocal => "delete local",
+                    BisyncAction::DeleteRemote => "delete remote",
+                    BisyncAction::KeepBoth => "conflict",
+                    BisyncAction::Skip if dry_run => "skip",
+                    BisyncAction::Skip => continue,
+                };
+                match &item.conflict_copy {
+                    Some(copy) => println!(
+                        "{:<14}{} -> {} ({:?})",
+                        action, item.path, copy, item.reason
+                    ),
+                    None => println!("{:<14}{} ({:?})", action, item.path, item.reason),
+                }
+            }
+            println!(
+                "{} to upload, {} to download, {} to delete locally, {} to delete remotely, {} conflicts",
+                plan.upload_count,
+                plan.download_count,
+                plan.delete_local_count,
+                plan.delete_remote_count,
+                plan.conflict_count
+            );
+            if dry_run {
+                return Ok(());
+            }
+
+            let queue = TransferQueue::new(jobs);
+            let report = bisync::execute(&plan, &remote, &queue, &mut state).await?;
+            for failure in &report.failed {
+                eprintln!("{}: {}", failure.path, failure.message);
+            }
+            println!(
+                "{} uploaded, {} downloaded, {} deleted locally, {} deleted remotely, {} conflicts kept",
+                report.uploaded,
+                report.downloaded,
+                report.deleted_local,
+                report.deleted_remote,
+                report.conflicts
+            );
+            if !report.failed.is_empty() {
+                return Err(format!("{} sync operations failed", report.failed.len()).into());
+            }
+            Ok(())
+        }
+        Command::Watch {
+            dir,
+            to,
+            recursive,
+            on_conflict,
+            delta,
+            dedup,
+        } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let mut watcher = FolderWatcher::start(&dir, recursive)?;
+            let queue = TransferQueue::default();
+            println!("Watching {} -> {}", dir.display(), to);
+            while let Some(file) = watcher.next().await {
+                let kind = TransferKind::Upload {
+                    target_dir: hot_folder::target_dir_for(&dir, &to, &file),
+                    local_path: file,
+                    conflict_policy: on_conflict.into(),
+                    mode: upload_mode(delta, dedup),
+                };
+                let handle = queue.enqueue(remote.clone(), kind);
+                tokio::spawn(async move {
+                    let info = handle.finished().await;
+                    if let TransferKind::Upload { local_path, .. } = &info.kind {
+                        let message = info.message.as_deref().unwrap_or_default();
+                        match info.state {
+                            TransferState::Completed => {
+                                println!("{}: {}", local_path.display(), message)
+                            }
+                            _ => eprintln!("{}: {}", local_path.display(), message),
+                        }
+                    }
+                });
+            }
+            Err("the folder watch stopped".into())
+        }
+        Command::WatchRemote { path, recursive } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let mut watch = remote.watch_dir(path.clone(), recursive).await?;
+            println!("Watching remote {}", path);
+            while let Some(changes) = watch.next().await? {
+                for change in changes {
+                    match change.kind {
+                        RemoteChangeKind::Created => println!("created   {}", change.path),
+                        RemoteChangeKind::Modified => println!("modified  {}", change.path),
+                        RemoteChangeKind::Deleted => println!("deleted   {}", change.path),
+                        RemoteChangeKind::Renamed => println!(
+                            "renamed   {} -> {}",
+                            change.path,
+                            change.new_path.unwrap_or_default()
+                        ),
+                    }
+                }
+            }
+            Err("the server ended the watch".into())
+        }
+        Command::Df { path } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let space = remote.disk_usage(path).await?;
+            println!("total:     {}", space.total);
+            println!("free:      {}", space.free);
+            println!("available: {}", space.available);
+            if let Some(quota) = space.quota {
+                println!("quota:     {} ({} used)", quota, space.quota_used);
+            }
+            Ok(())
+        }
+        Command::Du { path } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let mut scan = remote.dir_usage(path.clone()).await?;
+            while let Some(usage) = scan.next().await? {
+                if usage.complete {
+                    println!(
+                        "{} bytes in {} files and {} directories: {}",
+                        usage.bytes, usage.files, usage.dirs, path
+                    );
+                    if usage.errors > 0 {
+                        eprintln!("{} directories could not be read", usage.errors);
+                    }
+                    return Ok(());
+                }
+                eprintln!("... {} bytes in {} files", usage.bytes, usage.files);
+            }
+            Err("the server ended the summary without a result".into())
+        }
+        Command::Find {
+            root,
+            pattern,
+            kind,
+            min_size,
+            max_size,
+            modified_after,
+            modified_before,
+            limit,
+        } => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let query = SearchQuery {
+                pattern,
+                kind: kind.into(),
+                min_size,
+                max_size,
+                modified_after,
+                modified_before,
+                max_results: limit,
+            };
+            let mut search = remote.search(root, query).await?;
+            while let Some(found) = search.next().await? {
+                let suffix = if found.entry.is_dir { "/" } else { "" };
+                match (found.entry.is_symlink, &found.entry.symlink_target) {
+                    (true, Some(target)) => println!("{}{} -> {}", found.path, suffix, target),
+                    (true, None) => println!("{}{} -> ?", found.path, suffix),
+                    (false, _) => println!("{}{}", found.path, suffix),
+                }
+            }
+            Ok(())
+        }
+        Command::Info => {
+            let remote = RemoteClient::connect_with(cli.server, options).await?;
+            let info = remote.capabilities();
+            println!("server:           {}", remote.url());
+            println!("device name:      {}", info.device_name);
+            println!("app version:      {}", info.app_version);
+            println!("protocol version: {}", info.protocol_version);
+            println!("features:         {}", info.features.join(", "));
+            println!("max chunk size:   {}", info.max_chunk_size);
+            Ok(())
+        }
+    }
+}
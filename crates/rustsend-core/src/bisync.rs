@@ -0,0 +1,849 @@
+// rustsend-core/src/bisync.rs
+
+//! Two-way folder sync between a local directory and a remote directory.
+//!
+//! A state file per (server, local directory, remote directory) pair records
+//! the size and mtime of every file on both sides as of the last sync, so a
+//! run can tell which side changed. A file changed on one side is copied to
+//! the other. A file changed on both sides is a conflict: the local version
+//! keeps the name and the remote one is kept next to it, on both sides, as
+//! `name (conflict <device>).ext`. A deletion only propagates while the other
+//! side still holds the last-synced version; a copy changed in the meantime
+//! is restored instead.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::client::{ConflictMode, RemoteClient, UploadMode};
+use crate::error::{Error, Result};
+use crate::symlink::SymlinkPolicy;
+use crate::sync::{self, ancestors, join_remote, Entry, SyncFailure};
+use crate::transfer::{TransferHandle, TransferInfo, TransferKind, TransferQueue, TransferState};
+use crate::{checksum, i18n, protocol};
+
+// --- Sync State ---
+
+/// A file's size and mtime on one side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Version {
+    size: u64,
+    mtime: i64,
+}
+
+impl From<&Entry> for Version {
+    fn from(entry: &Entry) -> Self {
+        Version {
+            size: entry.size,
+            mtime: entry.mtime,
+        }
+    }
+}
+
+/// Both sides of a file as of the last sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncedFile {
+    local: Version,
+    remote: Version,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    files: BTreeMap<String, SyncedFile>,
+}
+
+/// Last-synced versions of one sync pair, persisted as JSON in the user's
+/// data directory.
+#[derive(Debug)]
+pub struct SyncState {
+    path: PathBuf,
+    files: BTreeMap<String, SyncedFile>,
+}
+
+impl SyncState {
+    /// `<data dir>/rustsend/sync-state/<hash of the pair>.json`
+    pub fn default_path(server: &str, local_dir: &Path, remote_dir: &str) -> PathBuf {
+        let local_dir = local_dir
+            .canonicalize()
+            .unwrap_or_else(|_| local_dir.to_path_buf());
+        let mut hasher = Sha256::new();
+        for part in [
+            server.as_bytes(),
+            local_dir.as_os_str().as_encoded_bytes(),
+            remote_dir.trim_matches('/').as_bytes(),
+        ] {
+            hasher.update(part);
+            hasher.update([0]);
+        }
+        let digest = checksum::to_hex(&hasher.finalize());
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("rustsend")
+            .join("sync-state")
+            .join(format!("{}.json", &digest[..16]))
+    }
+
+    /// Loads the state of a pair; a missing or corrupt file yields an empty
+    /// state, which makes the next run compare contents instead of deleting.
+    pub fn open(server: &str, local_dir: &Path, remote_dir: &str) -> Self {
+        let path = Self::default_path(server, local_dir, remote_dir);
+        let files = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<StateFile>(&bytes) {
+                Ok(file) => file.files,
+                Err(e) => {
+                    warn!("Ignoring corrupt sync state {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Could not read sync state {}: {}", path.display(), e);
+                }
+                BTreeMap::new()
+            }
+        };
+        SyncState { path, files }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of files recorded as synced.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Writes to a temp file and renames it over the old one.
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        let file = StateFile {
+            files: self.files.clone(),
+        };
+        let json = serde_json::to_vec(&file)
+            .map_err(|e| Error::InvalidArgument(format!("cannot serialize sync state: {}", e)))?;
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, json).map_err(|e| Error::io(&temp, e))?;
+        std::fs::rename(&temp, &self.path).map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+// --- Plan ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BisyncAction {
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    /// Keep the local version under the name and the remote one as a
+    /// conflict copy, on both sides.
+    KeepBoth,
+    Skip,
+}
+
+/// Why an item got its action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BisyncReason {
+    LocalNew,
+    LocalChanged,
+    RemoteNew,
+    RemoteChanged,
+    LocalDeleted,
+    RemoteDeleted,
+    /// Changed on both sides since the last sync.
+    BothChanged,
+    /// Created on both sides with different content.
+    BothNew,
+    Unchanged,
+    /// Changed on both sides, but to the same content.
+    Identical,
+    /// A file on one side is a directory on the other; left alone.
+    TypeConflict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BisyncItem {
+    /// Path relative to both sync roots, with `/` separators.
+    pub path: String,
+    pub action: BisyncAction,
+    pub reason: BisyncReason,
+    /// Bytes to transfer (both versions for `KeepBoth`).
+    pub size: u64,
+    /// Name the remote version is kept under for `KeepBoth`.
+    pub conflict_copy: Option<String>,
+    /// Versions seen by `plan`, i.e. the ones transferred.
+    #[serde(skip)]
+    local: Option<Version>,
+    #[serde(skip)]
+    remote: Option<Version>,
+}
+
+/// Result of comparing both trees with the last-synced state; nothing has
+/// been changed yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct BisyncPlan {
+    pub local_dir: PathBuf,
+    pub remote_dir: String,
+    pub items: Vec<BisyncItem>,
+    pub upload_count: usize,
+    pub download_count: usize,
+    pub delete_local_count: usize,
+    pub delete_remote_count: usize,
+    pub conflict_count: usize,
+    pub skip_count: usize,
+}
+
+/// Outcome of `execute`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BisyncReport {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts: usize,
+    pub failed: Vec<SyncFailure>,
+    pub cancelled: usize,
+}
+
+/// Compares `local_dir` and `remote_dir` with the last-synced `state`.
+///
+/// Refuses to propagate deletions when one side has no files at all, since
+/// that is far more likely an unmounted disk or a wrong path than a user
+/// emptying the folder on purpose.
+pub async fn plan(
+    remote: &RemoteClient,
+    local_dir: &Path,
+    remote_dir: &str,
+    state: &SyncState,
+) -> Result<BisyncPlan> {
+    let remote_dir = remote_dir.trim_matches('/').to_string();
+    let root = local_dir.to_path_buf();
+    let local = tokio::task::spawn_blocking(move || sync::walk_local(&root, SymlinkPolicy::Skip))
+        .await
+        .map_err(|e| Error::io(local_dir, std::io::Error::other(e)))??
+        .entries;
+    let remote_entries = sync::walk_remote(remote, &remote_dir, SymlinkPolicy::Skip).await?;
+    info!(
+        "Two-way sync plan: {} local, {} remote and {} synced entries",
+        local.len(),
+        remote_entries.len(),
+        state.len()
+    );
+
+    let files = |entries: &BTreeMap<String, Entry>| -> BTreeSet<String> {
+        entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_dir)
+            .map(|(path, _)| path.clone())
+            .collect()
+    };
+    let local_files = files(&local);
+    let remote_files = files(&remote_entries);
+    let paths = sync_paths(&local_files, &remote_files, state.files.keys());
+
+    let device = remote.capabilities().device_name.clone();
+    let mut conflict_names = HashSet::new();
+    let mut items = Vec::new();
+    for path in paths {
+        let l = local.get(path);
+        let r = remote_entries.get(path);
+        let synced = state.files.get(path);
+        if l.is_some_and(|l| l.is_dir) || r.is_some_and(|r| r.is_dir) {
+            if l.is_some_and(|l| !l.is_dir) || r.is_some_and(|r| !r.is_dir) {
+                items.push(item(
+                    path,
+                    BisyncAction::Skip,
+                    BisyncReason::TypeConflict,
+                    0,
+                ));
+            }
+            continue;
+        }
+
+        let (local_version, remote_version) = (l.map(Version::from), r.map(Version::from));
+        // Deleted on both sides; dropped from the state after the run.
+        let Some((mut action, mut reason)) = decide(local_version, remote_version, synced) else {
+            continue;
+        };
+        let local_size = local_version.map_or(0, |v| v.size);
+        let remote_size = remote_version.map_or(0, |v| v.size);
+        if action == BisyncAction::KeepBoth
+            && local_size == remote_size
+            && same_content(remote, local_dir, &remote_dir, path).await?
+        {
+            (action, reason) = (BisyncAction::Skip, BisyncReason::Identical);
+        }
+        let size = match action {
+            BisyncAction::Download | BisyncAction::DeleteRemote => remote_size,
+            BisyncAction::KeepBoth => local_size + remote_size,
+            _ => local_size,
+        };
+        let mut entry = item(path, action, reason, size);
+        entry.local = local_version;
+        entry.remote = remote_version;
+        if action == BisyncAction::KeepBoth {
+            let name = conflict_name(path, &device, |candidate| {
+                local.contains_key(candidate)
+                    || remote_entries.contains_key(candidate)
+                    || conflict_names.contains(candidate)
+            });
+            conflict_names.insert(name.clone());
+            entry.conflict_copy = Some(name);
+        }
+        items.push(entry);
+    }
+
+    let count = |action| items.iter().filter(|i| i.action == action).count();
+    let plan = BisyncPlan {
+        local_dir: local_dir.to_path_buf(),
+        remote_dir,
+        upload_count: count(BisyncAction::Upload),
+        download_count: count(BisyncAction::Download),
+        delete_local_count: count(BisyncAction::DeleteLocal),
+        delete_remote_count: count(BisyncAction::DeleteRemote),
+        conflict_count: count(BisyncAction::KeepBoth),
+        skip_count: count(BisyncAction::Skip),
+        items,
+    };
+    refuse_emptied_side(&plan, local_files.is_empty(), remote_files.is_empty())?;
+    Ok(plan)
+}
+
+/// Every file path to decide on: present on either side or at the last sync.
+/// Transfer temp files are left out, also when an older run recorded one, so
+/// their disappearance is never taken for a deletion.
+fn sync_paths<'a>(
+    local: &'a BTreeSet<String>,
+    remote: &'a BTreeSet<String>,
+    synced: impl Iterator<Item = &'a String>,
+) -> BTreeSet<&'a String> {
+    local
+        .iter()
+        .chain(remote)
+        .chain(synced)
+        .filter(|path| !protocol::is_partial(path))
+        .collect()
+}
+
+/// Action for a file from its versions on both sides and at the last sync;
+/// None when it is gone from both. `KeepBoth` still needs a content check,
+/// since both sides may have changed to the same bytes.
+fn decide(
+    local: Option<Version>,
+    remote: Option<Version>,
+    synced: Option<&SyncedFile>,
+) -> Option<(BisyncAction, BisyncReason)> {
+    Some(match (local, remote) {
+        (Some(l), Some(r)) => {
+            let local_changed = synced.is_none_or(|s| s.local != l);
+            let remote_changed = synced.is_none_or(|s| s.remote != r);
+            match (local_changed, remote_changed) {
+                (false, false) => (BisyncAction::Skip, BisyncReason::Unchanged),
+                (true, false) => (BisyncAction::Upload, BisyncReason::LocalChanged),
+                (false, true) => (BisyncAction::Download, BisyncReason::RemoteChanged),
+                (true, true) if synced.is_some() => {
+                    (BisyncAction::KeepBoth, BisyncReason::BothChanged)
+                }
+                (true, true) => (BisyncAction::KeepBoth, BisyncReason::BothNew),
+            }
+        }
+        (Some(l), None) => match synced {
+            Some(s) if s.local == l => (BisyncAction::DeleteLocal, BisyncReason::RemoteDeleted),
+            Some(_) => (BisyncAction::Upload, BisyncReason::LocalChanged),
+            None => (BisyncAction::Upload, BisyncReason::LocalNew),
+        },
+        (None, Some(r)) => match synced {
+            Some(s) if s.remote == r => (BisyncAction::DeleteRemote, BisyncReason::LocalDeleted),
+            Some(_) => (BisyncAction::Download, BisyncReason::RemoteChanged),
+            None => (BisyncAction::Download, BisyncReason::RemoteNew),
+        },
+        (None, None) => return None,
+    })
+}
+
+/// Fails if `plan` would delete files on one side because the other side
+/// has no files at all.
+fn refuse_emptied_side(plan: &BisyncPlan, local_empty: bool, remote_empty: bool) -> Result<()> {
+    if local_empty && plan.delete_remote_count > 0 {
+        return Err(Error::InvalidArgument(format!(
+            "{} has no files; refusing to delete {} remote files",
+            plan.local_dir.display(),
+            plan.delete_remote_count
+        )));
+    }
+    if remote_empty && plan.delete_local_count > 0 {
+        return Err(Error::InvalidArgument(format!(
+            "remote directory /{} has no files; refusing to delete {} local files",
+            plan.remote_dir, plan.delete_local_count
+        )));
+    }
+    Ok(())
+}
+
+fn item(path: &str, action: BisyncAction, reason: BisyncReason, size: u64) -> BisyncItem {
+    BisyncItem {
+        path: path.to_string(),
+        action,
+        reason,
+        size,
+        conflict_copy: None,
+        local: None,
+        remote: None,
+    }
+}
+
+async fn same_content(
+    remote: &RemoteClient,
+    local_dir: &Path,
+    remote_dir: &str,
+    path: &str,
+) -> Result<bool> {
+    let local_hash = sync::hash_local(&local_dir.join(path)).await?;
+    let remote_hash = remote.hash_file(join_remote(remote_dir, path)).await?;
+    Ok(local_hash == remote_hash)
+}
+
+/// `dir/report.txt` -> `dir/report (conflict laptop).txt`, numbered when
+/// that name is taken.
+fn conflict_name(path: &str, device: &str, taken: impl Fn(&str) -> bool) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (dir, name),
+        None => ("", path),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let device: String = device
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let device = if device.is_empty() { "remote" } else { &device };
+    (1..)
+        .map(|n| {
+            let label = if n == 1 {
+                format!("conflict {}", device)
+            } else {
+                format!("conflict {} {}", device, n)
+            };
+            join_remote(dir, &format!("{} ({}){}", stem, label, ext))
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("unbounded range")
+}
+
+// --- Execution ---
+
+/// Carries out `plan` and records the new state. Deletions and downloads run
+/// first, so a conflict's remote version is saved before the local one
+/// replaces it on the server; uploads follow.
+pub async fn execute(
+    plan: &BisyncPlan,
+    remote: &RemoteClient,
+    queue: &TransferQueue,
+    state: &mut SyncState,
+) -> Result<BisyncReport> {
+    let mut report = BisyncReport::default();
+    let mut failed: HashSet<&str> = HashSet::new();
+    let remote_path = |path: &str| join_remote(&plan.remote_dir, path);
+    let upload = |path: &str| {
+        let target_dir = match path.rsplit_once('/') {
+            Some((parent, _)) => remote_path(parent),
+            None => plan.remote_dir.clone(),
+        };
+        TransferKind::Upload {
+            local_path: plan.local_dir.join(path),
+            target_dir,
+            conflict_policy: ConflictMode::Overwrite,
+            mode: UploadMode::Full,
+        }
+    };
+
+    // Phase 1: deletions and downloads.
+    let mut pending: Vec<(&BisyncItem, TransferHandle)> = Vec::new();
+    for item in &plan.items {
+        let kind = match item.action {
+            BisyncAction::DeleteLocal => {
+                match delete_local(&plan.local_dir, &item.path, state) {
+                    Ok(()) => report.deleted_local += 1,
+                    Err(e) => {
+                        warn!("Not deleting {}: {}", item.path, e);
+                        failed.insert(&item.path);
+                        report.failed.push(SyncFailure {
+                            path: item.path.clone(),
+                            error_code: Some(e.code()),
+                            message: i18n::error_message(&e),
+                        });
+                    }
+                }
+                continue;
+            }
+            BisyncAction::DeleteRemote => TransferKind::Delete {
+                remote_path: remote_path(&item.path),
+                recursive: false,
+            },
+            BisyncAction::Download => TransferKind::Download {
+                remote_path: remote_path(&item.path),
+                local_path: plan.local_dir.join(&item.path),
+            },
+            BisyncAction::KeepBoth => TransferKind::Download {
+                remote_path: remote_path(&item.path),
+                local_path: plan
+                    .local_dir
+                    .join(item.conflict_copy.as_deref().unwrap_or_default()),
+            },
+            BisyncAction::Upload | BisyncAction::Skip => continue,
+        };
+        pending.push((item, queue.enqueue(remote.clone(), kind)));
+    }
+    for (item, handle) in std::mem::take(&mut pending) {
+        if report.record(item, handle.finished().await) {
+            match item.action {
+                BisyncAction::DeleteRemote => report.deleted_remote += 1,
+                BisyncAction::Download => report.downloaded += 1,
+                _ => {}
+            }
+        } else {
+            failed.insert(&item.path);
+        }
+    }
+
+    // Phase 2: uploads, including both versions of each conflict whose
+    // remote version was saved.
+    for item in &plan.items {
+        match item.action {
+            BisyncAction::Upload => {
+                pending.push((item, queue.enqueue(remote.clone(), upload(&item.path))));
+            }
+            BisyncAction::KeepBoth if !failed.contains(item.path.as_str()) => {
+                let copy = item.conflict_copy.as_deref().unwrap_or_default();
+                pending.push((item, queue.enqueue(remote.clone(), upload(copy))));
+                pending.push((item, queue.enqueue(remote.clone(), upload(&item.path))));
+            }
+            _ => {}
+        }
+    }
+    for (item, handle) in pending {
+        if report.record(item, handle.finished().await) {
+            if item.action == BisyncAction::Upload {
+                report.uploaded += 1;
+            }
+        } else {
+            failed.insert(&item.path);
+        }
+    }
+    report.conflicts = plan
+        .items
+        .iter()
+        .filter(|item| item.action == BisyncAction::KeepBoth)
+        .filter(|item| !failed.contains(item.path.as_str()))
+        .count();
+
+    prune_dirs(plan, remote).await;
+    record_state(plan, remote, state, &failed).await?;
+    info!(
+        "Two-way sync finished: {} uploaded, {} downloaded, {} deleted locally, {} deleted remotely, {} conflicts, {} failed",
+        report.uploaded,
+        report.downloaded,
+        report.deleted_local,
+        report.deleted_remote,
+        report.conflicts,
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+impl BisyncReport {
+    /// Records a failed or cancelled transfer; returns whether it completed.
+    fn record(&mut self, item: &BisyncItem, info: TransferInfo) -> bool {
+        match info.state {
+            TransferState::Completed => return true,
+            TransferState::Cancelled => self.cancelled += 1,
+            _ => self.failed.push(SyncFailure {
+                path: item.path.clone(),
+                error_code: info.error_code,
+                message: info.message.unwrap_or_default(),
+            }),
+        }
+        false
+    }
+}
+
+/// Deletes a local file, but only if it is still the last-synced version.
+fn delete_local(local_dir: &Path, path: &str, state: &SyncState) -> Result<()> {
+    let full = local_dir.join(path);
+    let metadata = std::fs::symlink_metadata(&full).map_err(|e| Error::io(&full, e))?;
+    let current = Version {
+        size: metadata.len(),
+        mtime: crate::file_meta::mtime_secs(&metadata),
+    };
+    if !metadata.is_file() || state.files.get(path).map(|s| s.local) != Some(current) {
+        return Err(Error::Conflict(format!(
+            "{} changed during the sync",
+            full.display()
+        )));
+    }
+    std::fs::remove_file(&full).map_err(|e| Error::io(&full, e))
+}
+
+/// Removes directories left empty by deletions on one side when they no
+/// longer exist on the other. Non-empty directories are kept.
+async fn prune_dirs(plan: &BisyncPlan, remote: &RemoteClient) {
+    let deleted = |action| {
+        plan.items
+            .iter()
+            .filter(move |item| item.action == action)
+            .flat_map(|item| ancestors(&item.path))
+            .collect::<BTreeSet<_>>()
+    };
+    // Deepest first, so parents are empty by the time they are tried.
+    for dir in deleted(BisyncAction::DeleteLocal).into_iter().rev() {
+        let _ = std::fs::remove_dir(plan.local_dir.join(dir));
+    }
+    for dir in deleted(BisyncAction::DeleteRemote).into_iter().rev() {
+        if !plan.local_dir.join(dir).exists() {
+            let _ = remote
+                .delete(join_remote(&plan.remote_dir, dir), false)
+                .await;
+        }
+    }
+}
+
+/// Updates the state with the versions that were actually transferred: the
+/// plan-time version of the side a file was copied from and the version now
+/// on the side it was copied to. A source changed since the plan (e.g. saved
+/// again while the uploads ran) keeps its previous record, as do unchanged
+/// and failed paths, so the next run picks the change up. Paths deleted on
+/// both sides or no longer a file on both sides are dropped.
+async fn record_state(
+    plan: &BisyncPlan,
+    remote: &RemoteClient,
+    state: &mut SyncState,
+    failed: &HashSet<&str>,
+) -> Result<()> {
+    let root = plan.local_dir.clone();
+    let local = tokio::task::spawn_blocking(move || sync::walk_local(&root, SymlinkPolicy::Skip))
+        .await
+        .map_err(|e| Error::io(&plan.local_dir, std::io::Error::other(e)))??
+        .entries;
+    let remote_entries = sync::walk_remote(remote, &plan.remote_dir, SymlinkPolicy::Skip).await?;
+    let file = |entries: &BTreeMap<String, Entry>, path: &str| {
+        entries
+            .get(path)
+            .filter(|entry| !entry.is_dir)
+            .map(Version::from)
+    };
+    // Both sides as walked now, if they are files of the same size.
+    let current = |path: &str| {
+        let l = file(&local, path)?;
+        let r = file(&remote_entries, path)?;
+        (l.size == r.size).then_some(SyncedFile {
+            local: l,
+            remote: r,
+        })
+    };
+
+    let mut files = BTreeMap::new();
+    for item in &plan.items {
+        let previous = || state.files.get(&item.path).cloned();
+        let record = if failed.contains(item.path.as_str()) {
+            previous()
+        } else {
+            match item.action {
+                BisyncAction::Skip => match item.reason {
+                    BisyncReason::Identical => match current(&item.path) {
+                        Some(now)
+                            if Some(now.local) == item.local && Some(now.remote) == item.remote =>
+                        {
+                            Some(now)
+                        }
+                        _ => previous(),
+                    },
+                    BisyncReason::TypeConflict => None,
+                    _ => previous(),
+                },
+                BisyncAction::Upload | BisyncAction::KeepBoth => match current(&item.path) {
+                    Some(now) if Some(now.local) == item.local => Some(now),
+                    _ => previous(),
+                },
+                BisyncAction::Download => match current(&item.path) {
+                    Some(now) if Some(now.remote) == item.remote => Some(now),
+                    _ => previous(),
+                },
+                BisyncAction::DeleteLocal | BisyncAction::DeleteRemote => None,
+            }
+        };
+        if let Some(record) = record {
+            files.insert(item.path.clone(), record);
+        }
+        // The conflict copy holds the plan-time remote version on both sides.
+        if item.action == BisyncAction::KeepBoth && !failed.contains(item.path.as_str()) {
+            let copy = item.conflict_copy.as_deref().unwrap_or_default();
+            if let Some(now) =
+                current(copy).filter(|now| Some(now.local.size) == item.remote.map(|r| r.size))
+            {
+                files.insert(copy.to_string(), now);
+            }
+        }
+    }
+    state.files = files;
+    state.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(size: u64, mtime: i64) -> Option<Version> {
+        Some(Version { size, mtime })
+    }
+
+    fn synced(local: Option<Version>, remote: Option<Version>) -> SyncedFile {
+        SyncedFile {
+            local: local.unwrap(),
+            remote: remote.unwrap(),
+        }
+    }
+
+    #[test]
+    fn new_files() {
+        use BisyncAction::*;
+        use BisyncReason::*;
+        assert_eq!(decide(v(1, 1), None, None), Some((Upload, LocalNew)));
+        assert_eq!(decide(None, v(1, 1), None), Some((Download, RemoteNew)));
+        // New on both sides: the content check decides between both copies
+        // and nothing to do, even when size and mtime match.
+        assert_eq!(decide(v(1, 1), v(2, 2), None), Some((KeepBoth, BothNew)));
+        assert_eq!(decide(v(1, 1), v(1, 1), None), Some((KeepBoth, BothNew)));
+    }
+
+    #[test]
+    fn changes_since_last_sync() {
+        use BisyncAction::*;
+        use BisyncReason::*;
+        let state = synced(v(1, 10), v(1, 20));
+        assert_eq!(
+            decide(v(1, 10), v(1, 20), Some(&state)),
+            Some((Skip, Unchanged))
+        );
+        assert_eq!(
+            decide(v(1, 11), v(1, 20), Some(&state)),
+            Some((Upload, LocalChanged))
+        );
+        assert_eq!(
+            decide(v(1, 10), v(2, 20), Some(&state)),
+            Some((Download, RemoteChanged))
+        );
+        assert_eq!(
+            decide(v(2, 10), v(1, 21), Some(&state)),
+            Some((KeepBoth, BothChanged))
+        );
+    }
+
+    #[test]
+    fn deletions() {
+        use BisyncAction::*;
+        use BisyncReason::*;
+        let state = synced(v(1, 10), v(1, 20));
+        assert_eq!(
+            decide(v(1, 10), None, Some(&state)),
+            Some((DeleteLocal, RemoteDeleted))
+        );
+        assert_eq!(
+            decide(None, v(1, 20), Some(&state)),
+            Some((DeleteRemote, LocalDeleted))
+        );
+        // A copy modified since the last sync is restored, not deleted.
+        assert_eq!(
+            decide(v(1, 11), None, Some(&state)),
+            Some((Upload, LocalChanged))
+        );
+        assert_eq!(
+            decide(None, v(3, 20), Some(&state)),
+            Some((Download, RemoteChanged))
+        );
+        assert_eq!(decide(None, None, Some(&state)), None);
+    }
+
+    #[test]
+    fn transfer_temp_files_are_not_synced() {
+        let temp = format!("dir/.a.txt.1234{}", protocol::PARTIAL_SUFFIX);
+        let local: BTreeSet<String> = ["a.txt".to_string()].into();
+        let remote: BTreeSet<String> = ["a.txt".to_string(), temp.clone()].into();
+        // Recorded by an older run that did not leave them out.
+        let synced = [temp.clone(), "b.txt".to_string()];
+        let paths = sync_paths(&local, &remote, synced.iter());
+        assert_eq!(paths.into_iter().collect::<Vec<_>>(), ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn deleting_everything_on_one_side_is_refused() {
+        let plan = |delete_local_count, delete_remote_count| BisyncPlan {
+            local_dir: PathBuf::from("/home/me/notes"),
+            remote_dir: "notes".to_string(),
+            items: Vec::new(),
+            upload_count: 0,
+            download_count: 0,
+            delete_local_count,
+            delete_remote_count,
+            conflict_count: 0,
+            skip_count: 0,
+        };
+        assert!(matches!(
+            refuse_emptied_side(&plan(0, 3), true, false),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            refuse_emptied_side(&plan(3, 0), false, true),
+            Err(Error::InvalidArgument(_))
+        ));
+        // Deletions are fine while the side they come from still has files.
+        assert!(refuse_emptied_side(&plan(3, 3), false, false).is_ok());
+        assert!(refuse_emptied_side(&plan(0, 0), true, true).is_ok());
+    }
+
+    #[test]
+    fn conflict_names() {
+        let none = |_: &str| false;
+        assert_eq!(
+            conflict_name("dir/report.txt", "laptop", none),
+            "dir/report (conflict laptop).txt"
+        );
+        assert_eq!(
+            conflict_name("Makefile", "laptop", none),
+            "Makefile (conflict laptop)"
+        );
+        assert_eq!(
+            conflict_name("a/.bashrc", "laptop", none),
+            "a/.bashrc (conflict laptop)"
+        );
+        assert_eq!(
+            conflict_name("x.tar.gz", "host/1:2", none),
+            "x.tar (conflict host_1_2).gz"
+        );
+        assert_eq!(conflict_name("x", "", none), "x (conflict remote)");
+
+        let taken = ["r (conflict pc).md", "r (conflict pc 2).md"];
+        assert_eq!(
+            conflict_name("r.md", "pc", |name| taken.contains(&name)),
+            "r (conflict pc 3).md"
+        );
+    }
+}
@@ -0,0 +1,1633 @@
+// rustsend-core/src/client.rs
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+use tokio::task;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use uuid::Uuid;
+
+// 从原 src/client.rs 复制
+const CHUNK_SIZE: usize = 1024 * 64; // 64 KB
+
+use crate::connection::{
+    self, ConnectOptions, ConnectionMonitor, ConnectionState, RequestSettings,
+};
+use crate::dedup::{self, ChunkHash};
+use crate::delta::{self, DeltaOp, SignatureIndex};
+use crate::error::{Error, Result};
+use crate::file_meta::PreservePolicy;
+use crate::filerpc::{
+    chunked_message, delta_message, download_message, file_service_client::FileServiceClient,
+    upload_message::Payload, ChangeKind, ChunkedMessage, Compression, ConflictPolicy, CopyBlocks,
+    CreateSymlinkRequest, DeleteRequest, DeltaHeader, DeltaMessage, DirChangeBatch, DirEntry,
+    DirUsageProgress, DirUsageRequest, DiskUsageRequest, DownloadMessage, DownloadRequest,
+    EntryType, HashFileRequest, ListDirRequest, ListDirResponse, QueryChunksRequest, SearchMatch,
+    SearchRequest, ServerInfo, ServerInfoRequest, SignatureRequest, UploadHeader, UploadMessage,
+    UploadStatus, UploadTrailer, WatchDirRequest,
+};
+use crate::listing::{DirLister, ListOptions, Listable};
+use crate::profile::Profile;
+use crate::{checksum, compression, file_meta, protocol};
+use sha2::{Digest, Sha256};
+
+// --- 客户端数据结构 ---
+
+/// 远程目录条目 (可直接序列化给前端)
+#[derive(Debug, Serialize)]
+pub struct RemoteDirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// 文件字节数，目录为 0
+    pub size: u64,
+    /// 修改时间 (Unix 秒)
+    pub mtime: i64,
+    /// 条目本身是符号链接；is_dir、size、mtime 描述其目标
+    pub is_symlink: bool,
+    /// 链接目标；目标在共享目录之外时服务端不返回
+    pub symlink_target: Option<String>,
+}
+
+impl From<DirEntry> for RemoteDirEntry {
+    fn from(e: DirEntry) -> Self {
+        RemoteDirEntry {
+            name: e.name,
+            is_dir: e.is_dir,
+            size: e.size,
+            mtime: e.mtime,
+            is_symlink: e.is_symlink,
+            symlink_target: (!e.symlink_target.is_empty()).then_some(e.symlink_target),
+        }
+    }
+}
+
+impl Listable for RemoteDirEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn mtime(&self) -> i64 {
+        self.mtime
+    }
+}
+
+/// 远程目录列表的一页
+#[derive(Debug, Serialize)]
+pub struct RemoteDirPage {
+    pub entries: Vec<RemoteDirEntry>,
+    /// 下一页的游标；已是最后一页时为 None
+    pub next_cursor: Option<String>,
+    /// 过滤后目录中的条目总数
+    pub total: u64,
+}
+
+impl From<ListDirResponse> for RemoteDirPage {
+    fn from(page: ListDirResponse) -> Self {
+        RemoteDirPage {
+            entries: page.entries.into_iter().map(RemoteDirEntry::from).collect(),
+            next_cursor: (!page.next_cursor.is_empty()).then_some(page.next_cursor),
+            total: page.total,
+        }
+    }
+}
+
+/// 远程目录中一次变化的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+/// 远程目录的一条变化 (可直接序列化给前端)
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteDirChange {
+    pub kind: RemoteChangeKind,
+    /// 相对于服务端根目录，以 '/' 分隔
+    pub path: String,
+    /// 仅 Renamed：新路径
+    pub new_path: Option<String>,
+}
+
+/// 远程文件系统的容量 (字节)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RemoteDiskSpace {
+    pub total: u64,
+    /// 包括只有 root 可用的保留空间
+    pub free: u64,
+    /// 服务端可写入的空间
+    pub available: u64,
+    /// 共享目录配额；没有配额时为 None
+    pub quota: Option<u64>,
+    pub quota_used: u64,
+}
+
+/// 远程目录大小统计到目前为止的累计值
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RemoteDirUsage {
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    /// 无法读取而跳过的目录数
+    pub errors: u64,
+    /// 统计已完成，数值为最终结果
+    pub complete: bool,
+}
+
+/// 远程查找时按条目本身的类型过滤 (不跟随符号链接)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    #[default]
+    Any,
+    File,
+    Dir,
+    Symlink,
+}
+
+/// 远程查找条件，未设置的字段不限 (前端可只传部分字段)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchQuery {
+    /// 名称模式，不区分大小写：含 * ? [ 时为 glob，否则为子串
+    pub pattern: String,
+    pub kind: SearchKind,
+    /// 大小范围 (字节，含边界)；设置后只匹配文件
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// 修改时间范围 (Unix 秒，含边界)
+    pub modified_after: Option<i64>,
+    pub modified_before: Option<i64>,
+    /// 最多返回的结果数；服务端另有上限
+    pub max_results: Option<u32>,
+}
+
+/// 远程查找的一条结果
+#[derive(Debug, Serialize)]
+pub struct RemoteSearchMatch {
+    /// 相对于服务端根目录，以 '/' 分隔
+    pub path: String,
+    pub entry: RemoteDirEntry,
+}
+
+/// 调用方可选的同名文件冲突策略，默认覆盖 (与旧版本行为一致)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictMode {
+    Fail,
+    #[default]
+    Overwrite,
+    SkipIfIdentical,
+    Rename,
+    OverwriteIfNewer,
+}
+
+impl From<ConflictMode> for ConflictPolicy {
+    fn from(policy: ConflictMode) -> Self {
+        match policy {
+            ConflictMode::Fail => ConflictPolicy::Fail,
+            ConflictMode::Overwrite => ConflictPolicy::Overwrite,
+            ConflictMode::SkipIfIdentical => ConflictPolicy::SkipIfIdentical,
+            ConflictMode::Rename => ConflictPolicy::Rename,
+            ConflictMode::OverwriteIfNewer => ConflictPolicy::OverwriteIfNewer,
+        }
+    }
+}
+
+/// 上传方式；服务端不支持增量或去重时自动退回完整上传
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadMode {
+    /// 发送完整文件
+    #[default]
+    Full,
+    /// 只发送与服务器上同名文件不同的部分 (rsync 式)
+    Delta,
+    /// 只发送服务器共享目录中还没有的内容块
+    Dedup,
+}
+
+/// 对端服务器的版本与能力，连接时通过 GetServerInfo 获取
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerCapabilities {
+    pub protocol_version: u32,
+    pub app_version: String,
+    pub device_name: String,
+    pub features: Vec<String>,
+    pub max_chunk_size: u64,
+}
+
+impl ServerCapabilities {
+    /// 不支持 GetServerInfo 的旧版本服务器：只有 v1 上传和列目录
+    fn legacy() -> Self {
+        ServerCapabilities {
+            protocol_version: 1,
+            app_version: "unknown".to_string(),
+            device_name: String::new(),
+            features: Vec::new(),
+            max_chunk_size: CHUNK_SIZE as u64,
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// 功能缺失时返回给前端的明确提示
+    fn require(&self, feature: &str) -> Result<()> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported {
+                feature: feature.to_string(),
+                server_version: self.app_version.clone(),
+            })
+        }
+    }
+}
+
+impl From<ServerInfo> for ServerCapabilities {
+    fn from(info: ServerInfo) -> Self {
+        ServerCapabilities {
+            protocol_version: info.protocol_version,
+            app_version: info.app_version,
+            device_name: info.device_name,
+            features: info.features,
+            max_chunk_size: info.max_chunk_size,
+        }
+    }
+}
+
+/// 握手：查询服务端能力。旧版本服务器没有该 RPC，视为 v1 协议
+async fn fetch_capabilities(
+    client: &mut FileServiceClient<Channel>,
+    settings: &RequestSettings,
+) -> Result<ServerCapabilities> {
+    let request = settings.unary(ServerInfoRequest {
+        client_protocol_version: protocol::PROTOCOL_VERSION,
+        client_app_version: protocol::APP_VERSION.to_string(),
+    });
+    match client.get_server_info(request).await {
+        Ok(response) => Ok(response.into_inner().into()),
+        Err(status) if status.code() == tonic::Code::Unimplemented => {
+            warn!("Server does not implement GetServerInfo, assuming protocol v1");
+            Ok(ServerCapabilities::legacy())
+        }
+        Err(status) => Err(status.into()),
+    }
+}
+
+// --- 客户端连接 (GUI、CLI 及其他嵌入方共用) ---
+
+/// 与一台服务器的连接：gRPC 客户端及握手得到的服务端能力
+#[derive(Clone)]
+pub struct RemoteClient {
+    url: String,
+    client: FileServiceClient<Channel>,
+    capabilities: ServerCapabilities,
+    settings: RequestSettings,
+    compression: bool,
+    preserve: PreservePolicy,
+    monitor: ConnectionMonitor,
+}
+
+impl RemoteClient {
+    /// 连接服务器并完成能力握手
+    pub async fn connect(url: String) -> Result<Self> {
+        Self::connect_with(url, ConnectOptions::default()).await
+    }
+
+    /// 按保存的连接配置连接，使用其中的访问令牌和超时设置
+    pub async fn connect_profile(profile: &Profile) -> Result<Self> {
+        let defaults = ConnectOptions::default();
+        let options = ConnectOptions {
+            token: profile.token.clone(),
+            connect_timeout: profile
+                .connect_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            request_timeout: match profile.request_timeout_secs {
+                // 0 表示不限制
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => defaults.request_timeout,
+            },
+            preserve: profile.preserve.unwrap_or(defaults.preserve),
+            ..defaults
+        };
+        Self::connect_with(profile.address.clone(), options).await
+    }
+
+    /// 按指定选项连接服务器并完成能力握手
+    pub async fn connect_with(url: String, options: ConnectOptions) -> Result<Self> {
+        info!("Attempting to connect to {}", url);
+        let settings = RequestSettings::new(&options)?;
+
+        // Tonic connection requires a scheme (http:// or https://)
+        let server_url = if url.starts_with("http://") || url.starts_with("https://") {
+            url
+        } else {
+            format!("http://{}", url)
+        };
+
+        // 启用 HTTP/2 keepalive，及时发现断开的连接
+        // 连接超时覆盖拨号和能力握手 (HTTP/2 握手在第一个请求时才完成)；
+        // Endpoint 上的同一超时还限制之后的自动重连
+        let endpoint = connection::endpoint(&server_url, &options)?;
+        let connecting = async {
+            let channel = endpoint.connect().await.map_err(|e| {
+                error!("Connection failed: {}", e);
+                Error::from(e)
+            })?;
+            let mut client = FileServiceClient::new(channel);
+            let capabilities = fetch_capabilities(&mut client, &settings)
+                .await
+                .map_err(|e| {
+                    error!("Server info handshake failed: {}", e);
+                    e
+                })?;
+            Ok::<_, Error>((client, capabilities))
+        };
+        let (client, capabilities) =
+            match tokio::time::timeout(options.connect_timeout, connecting).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    error!("Connection to {} timed out", server_url);
+                    return Err(Error::Timeout(format!(
+                        "connecting to {} ({:?})",
+                        server_url, options.connect_timeout
+                    )));
+                }
+            };
+        info!(
+            "Successfully connected to Server: {} v{} (protocol {}, features {:?})",
+            capabilities.device_name,
+            capabilities.app_version,
+            capabilities.protocol_version,
+            capabilities.features
+        );
+
+        // 后台探测连接状态，断开后按指数退避自动重连
+        let monitor =
+            ConnectionMonitor::spawn(server_url.clone(), client.clone(), settings.clone());
+        Ok(RemoteClient {
+            url: server_url,
+            client,
+            capabilities,
+            settings,
+            compression: options.compression,
+            preserve: options.preserve,
+            monitor,
+        })
+    }
+
+    /// 规范化后的服务器地址
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// 连接时协商得到的服务端能力
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.capabilities
+    }
+
+    /// 当前连接状态
+    pub fn connection_state(&self) -> ConnectionState {
+        self.monitor.state()
+    }
+
+    /// 订阅连接状态变化；连接对象全部释放后通道关闭
+    pub fn subscribe_state(&self) -> watch::Receiver<ConnectionState> {
+        self.monitor.subscribe()
+    }
+
+    /// 断开连接：停止后台探测并取消此连接上所有进行中的传输
+    pub fn disconnect(&self) {
+        info!("Disconnecting from {}", self.url);
+        self.monitor.disconnect();
+    }
+
+    /// 本端与服务端都支持的压缩算法 (按偏好排序)；禁用压缩时为空
+    fn accepted_compression(&self) -> Vec<Compression> {
+        if !self.compression {
+            return Vec::new();
+        }
+        compression::SUPPORTED
+            .iter()
+            .copied()
+            .filter(|&codec| {
+                compression::feature_name(codec).is_some_and(|f| self.capabilities.supports(f))
+            })
+            .collect()
+    }
+
+    /// 单个 data 帧的原始数据大小：不超过服务端允许的单帧大小；
+    /// 压缩后的帧可能略大于原始数据，因此留出余量
+    fn frame_size(&self, accepted: &[Compression]) -> usize {
+        let max_frame = if accepted.is_empty() {
+            self.capabilities.max_chunk_size
+        } else {
+            self.capabilities.max_chunk_size / 2
+        };
+        CHUNK_SIZE.min(max_frame as usize).max(1)
+    }
+
+    /// 在连接被断开时中止 `future`；已断开的连接直接返回 Cancelled
+    async fn cancellable<T>(
+        &self,
+        what: &str,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = self.monitor.cancel_token().cancelled() => {
+                warn!("{} cancelled: disconnected from {}", what, self.url);
+                Err(Error::Cancelled(what.to_string()))
+            }
+            result = future => result,
+        }
+    }
+
+    /// 将 RPC 错误转换为 Error，网络错误会触发立即重新探测
+    fn rpc_error(&self, status: tonic::Status) -> Error {
+        let error = Error::from(status);
+        self.monitor.report_failure(&error);
+        error
+    }
+
+    /// 列出远程目录的全部内容，包括隐藏文件 (按名称排序)
+    pub async fn list_dir(&self, path: String) -> Result<Vec<RemoteDirEntry>> {
+        let options = ListOptions {
+            show_hidden: true,
+            ..ListOptions::default()
+        };
+        self.list_dir_with(path, &options).await
+    }
+
+    /// 按排序与过滤选项列出远程目录内容。服务端支持时分页流式获取，不受单条消息大小限制；
+    /// 旧版本服务端返回完整列表，由本端按同样的规则排序过滤
+    pub async fn list_dir_with(
+        &self,
+        path: String,
+        options: &ListOptions,
+    ) -> Result<Vec<RemoteDirEntry>> {
+        if self.capabilities.supports(protocol::FEATURE_LIST_PAGING) {
+            let mut listing = self.list_dir_stream(path, options, 0).await?;
+            let mut entries = Vec::new();
+            while let Some(page) = listing.next().await? {
+                entries.extend(page.entries);
+            }
+            return Ok(entries);
+        }
+        let lister = DirLister::new(options.clone()).map_err(|e| {
+            Error::InvalidArgument(format!("Bad pattern \"{}\": {}", options.pattern, e))
+        })?;
+        Ok(lister.arrange(self.list_dir_legacy(path).await?))
+    }
+
+    /// 不支持分页的旧版本服务端：一次返回整个目录
+    async fn list_dir_legacy(&self, path: String) -> Result<Vec<RemoteDirEntry>> {
+        // FileServiceClient 实现了 Clone，克隆开销很小并且共享底层 Channel
+        let mut client = self.client.clone();
+        info!("Attempting to list remote directory: {}", path);
+
+        let request = self.settings.unary(ListDirRequest {
+            path,
+            ..Default::default()
+        });
+
+        let response = self
+            .cancellable("list_dir", async { Ok(client.list_dir(request).await) })
+            .await?;
+        match response {
+            Ok(response) => {
+                let entries = response
+                    .into_inner()
+                    .entries
+                    .into_iter()
+                    .map(RemoteDirEntry::from)
+                    .collect();
+                Ok(entries)
+            }
+            Err(e) => {
+                error!("Failed to list directory: {}", e.message());
+                Err(self.rpc_error(e))
+            }
+        }
+    }
+
+    /// 按排序与过滤选项获取远程目录的一页；cursor 为上一页的 next_cursor，首页为 None
+    pub async fn list_dir_page(
+        &self,
+        path: String,
+        options: &ListOptions,
+        page_size: u32,
+        cursor: Option<String>,
+    ) -> Result<RemoteDirPage> {
+        self.capabilities.require(protocol::FEATURE_LIST_PAGING)?;
+        let mut client = self.client.clone();
+        let request =
+            self.settings
+                .unary(options.to_request(path, page_size, cursor.unwrap_or_default()));
+        let page = self
+            .cancellable("list_dir", async { Ok(client.list_dir(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(page.into())
+    }
+
+    /// 按排序与过滤选项分页流式列出远程目录 (page_size 为 0 时由服务端决定)；
+    /// 返回的 `RemoteDirListing` 被丢弃时服务端停止发送
+    pub async fn list_dir_stream(
+        &self,
+        path: String,
+        options: &ListOptions,
+        page_size: u32,
+    ) -> Result<RemoteDirListing> {
+        self.capabilities.require(protocol::FEATURE_LIST_PAGING)?;
+        let mut client = self.client.clone();
+        info!("Streaming remote directory listing: {}", path);
+        // 超大目录可能需要很久，不设截止时间
+        let request = self
+            .settings
+            .request(options.to_request(path, page_size, String::new()));
+        let stream = self
+            .cancellable("list_dir", async {
+                Ok(client.list_dir_stream(request).await)
+            })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(RemoteDirListing {
+            remote: self.clone(),
+            stream,
+        })
+    }
+
+    /// 检查服务端能力、打开本地文件并构造上传 header (压缩算法由发送任务决定)
+    async fn prepare_upload(
+        &self,
+        actual_path: &Path,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+    ) -> Result<(std::fs::File, UploadHeader)> {
+        let capabilities = &self.capabilities;
+        capabilities.require(protocol::FEATURE_UPLOAD_V2)?;
+        if conflict_policy != ConflictMode::Overwrite {
+            capabilities.require(protocol::FEATURE_CONFLICT_POLICY)?;
+        }
+
+        let filename = actual_path
+            .file_name()
+            .ok_or_else(|| {
+                error!(
+                    "UPLOAD ERROR (Step 2): Local path invalid or missing filename: {:?}",
+                    actual_path
+                );
+                Error::InvalidArgument(format!("{} has no file name", actual_path.display()))
+            })?
+            .to_string_lossy()
+            .into_owned();
+
+        // 3. 打开本地文件
+        // 在主异步函数中打开文件，以进行错误处理
+        let file = std::fs::File::open(actual_path).map_err(|e| {
+            error!(
+                "UPLOAD ERROR (Step 3): Failed to open local file {:?}. Error: {}",
+                actual_path, e
+            );
+            Error::io(actual_path, e)
+        })?;
+
+        // 大小随 header 预先声明，服务端据此在接收前检查磁盘空间与配额
+        let metadata = file.metadata().map_err(|e| Error::io(actual_path, e))?;
+        let file_size = metadata.len();
+
+        // SKIP_IF_IDENTICAL 需要服务器比对哈希，只有该策略才预先计算，避免多读一遍文件
+        let sha256 = if conflict_policy == ConflictMode::SkipIfIdentical {
+            let hash_path = actual_path.to_path_buf();
+            task::spawn_blocking(move || checksum::sha256_file(&hash_path))
+                .await
+                .map_err(|e| Error::io(actual_path, std::io::Error::other(e)))?
+                .map_err(|e| {
+                    error!("UPLOAD ERROR (Step 3.1): Failed to hash local file: {}", e);
+                    Error::io(actual_path, e)
+                })?
+        } else {
+            Vec::new()
+        };
+
+        // [LOG B: 文件信息日志]
+        info!(
+            "Starting upload for: {} ({} bytes, policy {:?})",
+            filename, file_size, conflict_policy
+        );
+
+        // 文件名、目标目录等元数据只在 header 中发送一次
+        let mut header = UploadHeader {
+            filename,
+            target_dir,
+            size: file_size,
+            conflict_policy: ConflictPolicy::from(conflict_policy) as i32,
+            sha256,
+            ..Default::default()
+        };
+        // 时间戳、权限及 (按策略) 扩展属性，由服务端按其保留策略恢复
+        file_meta::describe(actual_path, &metadata, self.preserve, &mut header);
+        Ok((file, header))
+    }
+
+    /// 按指定方式上传单个本地文件
+    pub async fn upload(
+        &self,
+        actual_path: &Path,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+        mode: UploadMode,
+    ) -> Result<UploadStatus> {
+        match mode {
+            UploadMode::Full => {
+                self.upload_file(actual_path, target_dir, conflict_policy)
+                    .await
+            }
+            UploadMode::Delta => {
+                self.upload_file_delta(actual_path, target_dir, conflict_policy)
+                    .await
+            }
+            UploadMode::Dedup => {
+                self.upload_file_dedup(actual_path, target_dir, conflict_policy)
+                    .await
+            }
+        }
+    }
+
+    /// 计算远程文件的 SHA-256
+    pub async fn hash_file(&self, path: String) -> Result<Vec<u8>> {
+        self.capabilities.require(protocol::FEATURE_HASHING)?;
+        let mut client = self.client.clone();
+        let request = self.settings.unary(HashFileRequest { path });
+        let response = self
+            .cancellable("hash_file", async { Ok(client.hash_file(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?;
+        Ok(response.into_inner().sha256)
+    }
+
+    /// 删除远程文件或目录；`recursive` 为 true 时连同目录内容一起删除
+    pub async fn delete(&self, path: String, recursive: bool) -> Result<()> {
+        self.capabilities.require(protocol::FEATURE_DELETE)?;
+        let mut client = self.client.clone();
+        info!("Deleting remote path {} (recursive: {})", path, recursive);
+        let request = self.settings.unary(DeleteRequest { path, recursive });
+        self.cancellable("delete", async { Ok(client.delete(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?;
+        Ok(())
+    }
+
+    /// 在远程创建或替换符号链接；`target` 相对于链接所在目录，且不能离开共享目录
+    pub async fn create_symlink(&self, path: String, target: String) -> Result<()> {
+        self.capabilities.require(protocol::FEATURE_SYMLINKS)?;
+        let mut client = self.client.clone();
+        info!("Creating remote symlink {} -> {}", path, target);
+        let request = self.settings.unary(CreateSymlinkRequest { path, target });
+        self.cancellable("create_symlink", async {
+            Ok(client.create_symlink(request).await)
+        })
+        .await?
+        .map_err(|e| self.rpc_error(e))?;
+        Ok(())
+    }
+
+    /// 查询远程路径所在文件系统的容量及共享目录配额
+    pub async fn disk_usage(&self, path: String) -> Result<RemoteDiskSpace> {
+        self.capabilities.require(protocol::FEATURE_DISK_USAGE)?;
+        let mut client = self.client.clone();
+        let request = self.settings.unary(DiskUsageRequest { path });
+        let response = self
+            .cancellable("disk_usage", async { Ok(client.disk_usage(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(RemoteDiskSpace {
+            total: response.total_bytes,
+            free: response.free_bytes,
+            available: response.available_bytes,
+            quota: (response.quota_bytes > 0).then_some(response.quota_bytes),
+            quota_used: response.quota_used_bytes,
+        })
+    }
+
+    /// 开始统计远程路径下的总大小；返回的 `RemoteDirUsageScan` 被丢弃时服务端停止统计
+    pub async fn dir_usage(&self, path: String) -> Result<RemoteDirUsageScan> {
+        self.capabilities.require(protocol::FEATURE_DISK_USAGE)?;
+        let mut client = self.client.clone();
+        info!("Summing remote sizes under {}", path);
+        // 大目录树可能统计很久，不设截止时间
+        let request = self.settings.request(DirUsageRequest { path });
+        let stream = self
+            .cancellable("dir_usage", async { Ok(client.dir_usage(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(RemoteDirUsageScan {
+            remote: self.clone(),
+            stream,
+        })
+    }
+
+    /// 开始在远程目录树中查找；返回的 `RemoteSearch` 被丢弃时服务端停止查找
+    pub async fn search(&self, root: String, query: SearchQuery) -> Result<RemoteSearch> {
+        self.capabilities.require(protocol::FEATURE_SEARCH)?;
+        let mut client = self.client.clone();
+        info!("Searching remote {} for \"{}\"", root, query.pattern);
+        let entry_type = match query.kind {
+            SearchKind::Any => EntryType::Any,
+            SearchKind::File => EntryType::File,
+            SearchKind::Dir => EntryType::Dir,
+            SearchKind::Symlink => EntryType::Symlink,
+        };
+        // 大目录树可能查找很久，不设截止时间
+        let request = self.settings.request(SearchRequest {
+            root,
+            pattern: query.pattern,
+            entry_type: entry_type as i32,
+            min_size: query.min_size.unwrap_or(0),
+            max_size: query.max_size.unwrap_or(0),
+            modified_after: query.modified_after.unwrap_or(0),
+            modified_before: query.modified_before.unwrap_or(0),
+            max_results: query.max_results.unwrap_or(0),
+        });
+        let stream = self
+            .cancellable("search", async { Ok(client.search(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(RemoteSearch {
+            remote: self.clone(),
+            stream,
+        })
+    }
+
+    /// 开始监视远程目录；返回的 `RemoteDirWatch` 被丢弃时停止监视
+    pub async fn watch_dir(&self, path: String, recursive: bool) -> Result<RemoteDirWatch> {
+        self.capabilities.require(protocol::FEATURE_WATCH_DIR)?;
+        let mut client = self.client.clone();
+        info!(
+            "Watching remote directory {} (recursive: {})",
+            path, recursive
+        );
+        // 监视没有截止时间，只受连接状态约束
+        let request = self.settings.request(WatchDirRequest { path, recursive });
+        let stream = self
+            .cancellable("watch_dir", async { Ok(client.watch_dir(request).await) })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+        Ok(RemoteDirWatch {
+            remote: self.clone(),
+            stream,
+        })
+    }
+
+    /// 上传单个本地文件 (v2 协议)，返回服务器的上传结果
+    pub async fn upload_file(
+        &self,
+        actual_path: &Path,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+    ) -> Result<UploadStatus> {
+        let mut client = self.client.clone();
+        let (file, mut header) = self
+            .prepare_upload(actual_path, target_dir, conflict_policy)
+            .await?;
+        let accepted = self.accepted_compression();
+        let chunk_size = self.frame_size(&accepted);
+
+        // (tx_main, rx) - 主线程持有 tx_main
+        let (tx_main, rx) = mpsc::channel(4);
+
+        // 将 tx_main 克隆给 spawn_blocking 任务
+        let tx_blocking = tx_main.clone();
+        let sample_path = actual_path.to_path_buf();
+
+        // 4. 启动阻塞任务进行 I/O
+        task::spawn_blocking(move || {
+            let mut file = file;
+            let mut buffer = vec![0u8; chunk_size];
+            // 边读边计算摘要，随 trailer 发送给服务器校验
+            let mut hasher = Sha256::new();
+
+            // 第一块数据同时作为样本，决定是否压缩 (已压缩格式或压缩率不佳时不压缩)
+            let mut bytes_read = match file.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) => {
+                    error!(
+                        "UPLOAD ERROR (Step 4.1): Failed to read local file chunk: {}",
+                        e
+                    );
+                    return;
+                }
+            };
+            let codec = compression::choose(&sample_path, &buffer[..bytes_read], &accepted);
+            header.compression = codec as i32;
+            if codec != Compression::None {
+                info!(
+                    "Compressing upload of {} with {:?}",
+                    sample_path.display(),
+                    codec
+                );
+            }
+
+            if tx_blocking
+                .blocking_send(UploadMessage {
+                    payload: Some(Payload::Header(header)),
+                })
+                .is_err()
+            {
+                error!("UPLOAD ERROR (Step 4.2): Failed to send header to gRPC stream (receiver closed)");
+                return;
+            }
+
+            while bytes_read > 0 {
+                let chunk = &buffer[..bytes_read];
+                hasher.update(chunk);
+                let data = match compression::compress(codec, chunk) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        error!("UPLOAD ERROR (Step 4.1): Failed to compress chunk: {}", e);
+                        return;
+                    }
+                };
+                let message = UploadMessage {
+                    payload: Some(Payload::Data(data)),
+                };
+
+                // Use blocking_send inside spawn_blocking
+                if tx_blocking.blocking_send(message).is_err() {
+                    // 这个错误通常是因为接收端 rx 提前关闭，这意味着 gRPC 调用已经失败或取消
+                    error!("UPLOAD ERROR (Step 4.2): Failed to send chunk to gRPC stream (receiver closed)");
+                    return;
+                }
+
+                bytes_read = match file.read(&mut buffer) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        error!(
+                            "UPLOAD ERROR (Step 4.1): Failed to read local file chunk: {}",
+                            e
+                        );
+                        // 读取失败时不发送 trailer，服务器会丢弃这次不完整的上传
+                        return;
+                    }
+                };
+            }
+
+            let trailer = UploadMessage {
+                payload: Some(Payload::Trailer(UploadTrailer {
+                    sha256: hasher.finalize().to_vec(),
+                })),
+            };
+            if tx_blocking.blocking_send(trailer).is_err() {
+                error!("UPLOAD ERROR (Step 4.2): Failed to send trailer to gRPC stream (receiver closed)");
+            }
+            // 当此 spawn_blocking 任务结束时，tx_blocking 被 drop
+        });
+
+        // 5. 丢弃主线程的 Sender，允许流终止
+        drop(tx_main);
+
+        // 上传的响应在全部数据发送后才返回，因此不设请求超时
+        let request_stream = self.settings.request(ReceiverStream::new(rx));
+
+        // 6. 发起 gRPC 调用
+        let response = self
+            .cancellable("upload", async { Ok(client.upload(request_stream).await) })
+            .await?;
+        match response {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => {
+                // gRPC 调用失败，可能是网络问题或服务器内部错误
+                error!("UPLOAD FAILED (Step 6.2): gRPC call failed. Error: {}", e);
+                Err(self.rpc_error(e))
+            }
+        }
+    }
+
+    /// 增量上传 (类似 rsync)：目标目录中已有同名文件时只发送变化的部分。
+    /// 服务端不支持、远程文件不存在或其内容在传输期间被修改时退回完整上传
+    pub async fn upload_file_delta(
+        &self,
+        actual_path: &Path,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+    ) -> Result<UploadStatus> {
+        if !self.capabilities.supports(protocol::FEATURE_DELTA_UPLOAD) {
+            info!(
+                "Server does not support delta uploads, sending {:?} in full",
+                actual_path
+            );
+            return self
+                .upload_file(actual_path, target_dir, conflict_policy)
+                .await;
+        }
+        let (file, header) = self
+            .prepare_upload(actual_path, target_dir.clone(), conflict_policy)
+            .await?;
+
+        let remote_path = format!(
+            "{}/{}",
+            header.target_dir.trim_end_matches('/'),
+            header.filename
+        );
+        let index = match self.fetch_signatures(remote_path).await {
+            Ok(index) if !index.is_empty() => index,
+            Ok(_) | Err(Error::NotFound(_)) => {
+                info!(
+                    "No existing copy of {} on the server, sending it in full",
+                    header.filename
+                );
+                return self
+                    .upload_file(actual_path, target_dir, conflict_policy)
+                    .await;
+            }
+            Err(e) => return Err(e),
+        };
+
+        match self.send_delta(actual_path, file, header, index).await {
+            // 基准文件被删除或修改，重建结果校验失败
+            Err(
+                e @ (Error::NotFound(_)
+                | Error::Integrity(_)
+                | Error::Remote {
+                    code: tonic::Code::FailedPrecondition,
+                    ..
+                }),
+            ) => {
+                warn!(
+                    "Delta upload of {:?} failed ({}), sending it in full",
+                    actual_path, e
+                );
+                self.upload_file(actual_path, target_dir, conflict_policy)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// 获取远程文件的分块签名
+    async fn fetch_signatures(&self, remote_path: String) -> Result<SignatureIndex> {
+        let mut client = self.client.clone();
+        info!("Fetching block signatures of {}", remote_path);
+        let request = self.settings.unary(SignatureRequest { path: remote_path });
+        let mut stream = self
+            .cancellable("get_signatures", async {
+                Ok(client.get_signatures(request).await)
+            })
+            .await?
+            .map_err(|e| self.rpc_error(e))?
+            .into_inner();
+
+        self.cancellable("get_signatures", async {
+            let mut index: Option<SignatureIndex> = None;
+            while let Some(batch) = stream.message().await.map_err(|e| self.rpc_error(e))? {
+                let block_size = batch.block_size as usize;
+                if !(delta::MIN_BLOCK_SIZE..=delta::MAX_BLOCK_SIZE).contains(&block_size) {
+                    return Err(Error::Integrity(format!(
+                        "invalid block size from server: {}",
+                        block_size
+                    )));
+                }
+                index
+                    .get_or_insert_with(|| SignatureIndex::new(block_size, batch.file_size))
+                    .extend(batch.blocks);
+            }
+            // 空文件没有任何块
+            Ok(index.unwrap_or_else(|| SignatureIndex::new(delta::MIN_BLOCK_SIZE, 0)))
+        })
+        .await
+    }
+
+    /// 按签名计算增量并发送，服务端用已有文件和新数据重建文件
+    async fn send_delta(
+        &self,
+        actual_path: &Path,
+        file: std::fs::File,
+        mut header: UploadHeader,
+        index: SignatureIndex,
+    ) -> Result<UploadStatus> {
+        let mut client = self.client.clone();
+        let accepted = self.accepted_compression();
+        let max_literal = self.frame_size(&accepted);
+        let sample_path = actual_path.to_path_buf();
+
+        let (tx, rx) = mpsc::channel(4);
+        task::spawn_blocking(move || {
+            use std::io::{Seek, SeekFrom};
+
+            let frame = |payload| DeltaMessage {
+                payload: Some(payload),
+            };
+            let mut file = file;
+
+            // 先读取一段样本决定压缩算法，再回到文件开头
+            let mut sample = vec![0u8; max_literal];
+            let sampled = file
+                .read(&mut sample)
+                .and_then(|n| file.seek(SeekFrom::Start(0)).map(|_| n));
+            let codec = match sampled {
+                Ok(n) => compression::choose(&sample_path, &sample[..n], &accepted),
+                Err(e) => {
+                    error!("DELTA UPLOAD ERROR: Failed to read local file: {}", e);
+                    return;
+                }
+            };
+            header.compression = codec as i32;
+
+            let delta_header = DeltaHeader {
+                file: Some(header),
+                block_size: index.block_size() as u32,
+                basis_size: index.basis_size(),
+            };
+            if tx
+                .blocking_send(frame(delta_message::Payload::Header(delta_header)))
+                .is_err()
+            {
+                error!("DELTA UPLOAD ERROR: Failed to send header (receiver closed)");
+                return;
+            }
+
+            // 边计算增量边计算整个新文件的摘要，随 trailer 发送给服务器校验
+            let mut reader = checksum::HashingReader::new(file);
+            let mut complete = true;
+            let result = delta::compute_delta(&mut reader, &index, max_literal, |op| {
+                let payload = match op {
+                    DeltaOp::Copy { start_block, count } => {
+                        delta_message::Payload::Copy(CopyBlocks { start_block, count })
+                    }
+                    DeltaOp::Literal(data) => match compression::compress(codec, &data) {
+                        Ok(data) => delta_message::Payload::Data(data),
+                        Err(e) => {
+                            error!("DELTA UPLOAD ERROR: Failed to compress data: {}", e);
+                            complete = false;
+                            return false;
+                        }
+                    },
+                };
+                complete = tx.blocking_send(frame(payload)).is_ok();
+                complete
+            });
+            match result {
+                Ok(stats) if complete => {
+                    info!(
+                        "Delta of {:?}: {} bytes matched, {} bytes sent",
+                        sample_path, stats.copied_bytes, stats.literal_bytes
+                    );
+                    let trailer = UploadTrailer {
+                        sha256: reader.finalize(),
+                    };
+                    if tx
+                        .blocking_send(frame(delta_message::Payload::Trailer(trailer)))
+                        .is_err()
+                    {
+                        error!("DELTA UPLOAD ERROR: Failed to send trailer (receiver closed)");
+                    }
+                }
+                // 不发送 trailer，服务器会丢弃这次不完整的上传
+                Ok(_) => error!("DELTA UPLOAD ERROR: Upload stream closed early"),
+                Err(e) => error!("DELTA UPLOAD ERROR: Failed to read local file: {}", e),
+            }
+        });
+
+        let request_stream = self.settings.request(ReceiverStream::new(rx));
+        let response = self
+            .cancellable("upload", async {
+                Ok(client.upload_delta(request_stream).await)
+            })
+            .await?;
+        match response {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => {
+                error!("DELTA UPLOAD FAILED: gRPC call failed. Error: {}", e);
+                Err(self.rpc_error(e))
+            }
+        }
+    }
+
+    /// 去重上传：按内容分块 (FastCDC)，服务器共享目录中任意文件已有的块只发送哈希。
+    /// 服务端不支持、没有任何相同的块或块在传输期间失效时退回完整上传
+    pub async fn upload_file_dedup(
+        &self,
+        actual_path: &Path,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+    ) -> Result<UploadStatus> {
+        if !self.capabilities.supports(protocol::FEATURE_CHUNK_DEDUP) {
+            info!(
+                "Server does not support deduplicated uploads, sending {:?} in full",
+                actual_path
+            );
+            return self
+                .upload_file(actual_path, target_dir, conflict_policy)
+                .await;
+        }
+        let (file, header) = self
+            .prepare_upload(actual_path, target_dir.clone(), conflict_policy)
+            .await?;
+
+        // 第一遍读取：分块并计算每块的哈希
+        let chunk_path = actual_path.to_path_buf();
+        let hashes = task::spawn_blocking(move || {
+            let file = std::fs::File::open(&chunk_path)?;
+            dedup::chunks(std::io::BufReader::new(file))
+                .map(|chunk| chunk.map(|c| c.hash))
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .await
+        .map_err(|e| Error::io(actual_path, std::io::Error::other(e)))?
+        .map_err(|e| Error::io(actual_path, e))?;
+
+        let present = self.query_chunks(&hashes).await?;
+        if present.is_empty() {
+            info!(
+                "None of the {} chunks of {} are on the server, sending it in full",
+                hashes.len(),
+                header.filename
+            );
+            return self
+                .upload_file(actual_path, target_dir, conflict_policy)
+                .await;
+        }
+
+        match self.send_chunked(actual_path, file, header, present).await {
+            // 服务端索引中的块已被修改或删除
+            Err(
+                e @ (Error::NotFound(_)
+                | Error::Integrity(_)
+                | Error::Remote {
+                    code: tonic::Code::FailedPrecondition,
+                    ..
+                }),
+            ) => {
+                warn!(
+                    "Deduplicated upload of {:?} failed ({}), sending it in full",
+                    actual_path, e
+                );
+                self.upload_file(actual_path, target_dir, conflict_policy)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// 分批查询服务端已有的块，返回其中已存在的哈希
+    async fn query_chunks(&self, hashes: &[ChunkHash]) -> Result<HashSet<ChunkHash>> {
+        let unique: Vec<ChunkHash> = hashes
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let mut present = HashSet::new();
+        for batch in unique.chunks(protocol::MAX_CHUNK_QUERY) {
+            let mut client = self.client.clone();
+            let request = self.settings.unary(QueryChunksRequest {
+                hashes: batch.iter().map(|hash| hash.to_vec()).collect(),
+            });
+            let response = self
+                .cancellable("query_chunks", async {
+                    Ok(client.query_chunks(request).await)
+                })
+                .await?
+                .map_err(|e| self.rpc_error(e))?
+                .into_inner();
+            present.extend(
+                batch
+                    .iter()
+                    .zip(response.present)
+                    .filter(|(_, present)| *present)
+                    .map(|(hash, _)| *hash),
+            );
+        }
+        info!(
+            "{} of {} distinct chunks already on the server",
+            present.len(),
+            unique.len()
+        );
+        Ok(present)
+    }
+
+    /// 第二遍读取：已有的块发送哈希，其余块发送数据
+    async fn send_chunked(
+        &self,
+        actual_path: &Path,
+        file: std::fs::File,
+        mut header: UploadHeader,
+        present: HashSet<ChunkHash>,
+    ) -> Result<UploadStatus> {
+        let mut client = self.client.clone();
+        let accepted = self.accepted_compression();
+        let frame_size = self.frame_size(&accepted);
+        let sample_path = actual_path.to_path_buf();
+
+        let (tx, rx) = mpsc::channel(4);
+        task::spawn_blocking(move || {
+            use chunked_message::Payload;
+
+            let send = |payload| {
+                tx.blocking_send(ChunkedMessage {
+                    payload: Some(payload),
+                })
+                .is_ok()
+            };
+            // 边分块边计算整个文件的摘要，随 trailer 发送给服务器校验
+            let mut reader = checksum::HashingReader::new(file);
+            let mut reused = 0u64;
+            {
+                let mut chunks = dedup::chunks(&mut reader).peekable();
+                // 第一块数据同时作为样本，决定是否压缩
+                let sample = match chunks.peek() {
+                    Some(Ok(chunk)) => &chunk.data[..],
+                    _ => &[],
+                };
+                let codec = compression::choose(&sample_path, sample, &accepted);
+                header.compression = codec as i32;
+                if !send(Payload::Header(header)) {
+                    error!("DEDUP UPLOAD ERROR: Failed to send header (receiver closed)");
+                    return;
+                }
+
+                for chunk in chunks {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            // 不发送 trailer，服务器会丢弃这次不完整的上传
+                            error!("DEDUP UPLOAD ERROR: Failed to read local file: {}", e);
+                            return;
+                        }
+                    };
+                    if present.contains(&chunk.hash) {
+                        reused += chunk.data.len() as u64;
+                        if !send(Payload::ChunkHash(chunk.hash.to_vec())) {
+                            error!("DEDUP UPLOAD ERROR: Upload stream closed early");
+                            return;
+                        }
+                        continue;
+                    }
+                    for piece in chunk.data.chunks(frame_size) {
+                        let data = match compression::compress(codec, piece) {
+                            Ok(data) => data,
+                            Err(e) => {
+                                error!("DEDUP UPLOAD ERROR: Failed to compress data: {}", e);
+                                return;
+                            }
+                        };
+                        if !send(Payload::Data(data)) {
+                            error!("DEDUP UPLOAD ERROR: Upload stream closed early");
+                            return;
+                        }
+                    }
+                }
+            }
+
+            info!(
+                "Deduplicated upload of {:?}: {} bytes referenced by hash",
+                sample_path, reused
+            );
+            let trailer = UploadTrailer {
+                sha256: reader.finalize(),
+            };
+            if !send(Payload::Trailer(trailer)) {
+                error!("DEDUP UPLOAD ERROR: Failed to send trailer (receiver closed)");
+            }
+        });
+
+        let request_stream = self.settings.request(ReceiverStream::new(rx));
+        let response = self
+            .cancellable("upload", async {
+                Ok(client.upload_chunked(request_stream).await)
+            })
+            .await?;
+        match response {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => {
+                error!("DEDUP UPLOAD FAILED: gRPC call failed. Error: {}", e);
+                Err(self.rpc_error(e))
+            }
+        }
+    }
+
+    /// 下载远程文件到本地。`local_dest` 为目录时使用远程文件名，返回最终路径和字节数
+    pub async fn download_file(
+        &self,
+        remote_path: String,
+        local_dest: &Path,
+    ) -> Result<(PathBuf, u64)> {
+        self.capabilities.require(protocol::FEATURE_DOWNLOAD)?;
+        let mut client = self.client.clone();
+        info!("Attempting to download {} to {:?}", remote_path, local_dest);
+
+        // 请求超时只限制服务器开始响应之前，不限制传输本身
+        let request = self.settings.unary(DownloadRequest {
+            path: remote_path,
+            accept_compression: self
+                .accepted_compression()
+                .into_iter()
+                .map(|c| c as i32)
+                .collect(),
+        });
+        let mut stream = self
+            .cancellable("download", async { Ok(client.download(request).await) })
+            .await?
+            .map_err(|e| {
+                error!("DOWNLOAD FAILED: gRPC call failed. Error: {}", e);
+                self.rpc_error(e)
+            })?
+            .into_inner();
+
+        let first = self
+            .cancellable("download", async { Ok(stream.message().await) })
+            .await?;
+        let header = match first {
+            Ok(Some(DownloadMessage {
+                payload: Some(download_message::Payload::Header(header)),
+            })) => header,
+            Ok(_) => {
+                return Err(Error::Integrity(
+                    "server did not send a file header".to_string(),
+                ))
+            }
+            Err(e) => return Err(self.rpc_error(e)),
+        };
+
+        // 远程文件名只取最后一段，防止 "../" 之类的名字写到目标目录之外
+        let remote_name = Path::new(&header.filename)
+            .file_name()
+            .ok_or_else(|| {
+                Error::Integrity(format!(
+                    "invalid file name from server: {}",
+                    header.filename
+                ))
+            })?
+            .to_owned();
+        // 以分隔符结尾的目标视为目录，不存在时自动创建
+        let dest_str = local_dest.as_os_str().to_string_lossy();
+        if (dest_str.ends_with('/') || dest_str.ends_with(std::path::MAIN_SEPARATOR))
+            && !local_dest.exists()
+        {
+            tokio::fs::create_dir_all(local_dest)
+                .await
+                .map_err(|e| Error::io(local_dest, e))?;
+        }
+        let final_path = if local_dest.is_dir() {
+            local_dest.join(&remote_name)
+        } else {
+            local_dest.to_path_buf()
+        };
+        let parent = final_path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        // 先写入临时文件，校验通过后再改名，避免留下不完整的文件
+        let temp_path = parent.join(format!(
+            ".{}.{}{}",
+            remote_name.to_string_lossy(),
+            Uuid::new_v4(),
+            protocol::PARTIAL_SUFFIX
+        ));
+
+        let received = self
+            .cancellable(
+                "download",
+                receive_download(&mut stream, &header, &temp_path),
+            )
+            .await;
+        let result = match received {
+            Ok(bytes) => {
+                // 元数据恢复失败不影响已校验的内容
+                if let Err(e) = file_meta::apply(&temp_path, &header, self.preserve) {
+                    warn!("Could not restore metadata of {:?}: {}", final_path, e);
+                }
+                tokio::fs::rename(&temp_path, &final_path)
+                    .await
+                    .map(|_| bytes)
+                    .map_err(|e| Error::io(&final_path, e))
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(bytes) => {
+                info!("DOWNLOAD SUCCESS: {:?} ({} bytes)", final_path, bytes);
+                Ok((final_path, bytes))
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                error!("DOWNLOAD FAILED: {}", e);
+                self.monitor.report_failure(&e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// 接收 data 帧写入临时文件，并按 trailer 校验大小与 SHA-256
+async fn receive_download(
+    stream: &mut tonic::Streaming<DownloadMessage>,
+    header: &UploadHeader,
+    temp_path: &Path,
+) -> Result<u64> {
+    let mut file = tokio::fs::File::create(temp_path)
+        .await
+        .map_err(|e| Error::io(temp_path, e))?;
+    let codec = compression::from_wire(header.compression)
+        .ok_or_else(|| Error::Integrity(format!("unknown compression {}", header.compression)))?;
+    let mut hasher = Sha256::new();
+    let mut bytes = 0u64;
+
+    loop {
+        let message = stream.message().await?;
+        match message.and_then(|m| m.payload) {
+            Some(download_message::Payload::Data(data)) => {
+                let data = if codec == Compression::None {
+                    data
+                } else {
+                    compression::decompress(codec, &data, protocol::MAX_CHUNK_SIZE as usize)
+                        .map_err(|e| Error::Integrity(format!("corrupt compressed frame: {}", e)))?
+                };
+                file.write_all(&data)
+                    .await
+                    .map_err(|e| Error::io(temp_path, e))?;
+                hasher.update(&data);
+                bytes += data.len() as u64;
+            }
+            Some(download_message::Payload::Trailer(trailer)) => {
+                file.flush().await.map_err(|e| Error::io(temp_path, e))?;
+                if bytes != header.size {
+                    return Err(Error::Integrity(format!(
+                        "size mismatch (expected {} bytes, received {})",
+                        header.size, bytes
+                    )));
+                }
+                if hasher.finalize().as_slice() != trailer.sha256.as_slice() {
+                    return Err(Error::Integrity("SHA-256 mismatch".to_string()));
+                }
+                return Ok(bytes);
+            }
+            Some(download_message::Payload::Header(_)) => {
+                return Err(Error::Integrity("duplicate file header".to_string()));
+            }
+            None => {
+                return Err(Error::Network(
+                    "connection closed before the transfer completed".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+// --- 远程目录监视 ---
+
+/// 一个进行中的远程目录监视
+pub struct RemoteDirWatch {
+    remote: RemoteClient,
+    stream: tonic::Streaming<DirChangeBatch>,
+}
+
+impl RemoteDirWatch {
+    /// 等待下一批变化；服务端结束监视时返回 None，断开连接时返回 Cancelled
+    pub async fn next(&mut self) -> Result<Option<Vec<RemoteDirChange>>> {
+        let remote = &self.remote;
+        let stream = &mut self.stream;
+        let batch = remote
+            .cancellable("watch_dir", async {
+                stream.message().await.map_err(|e| remote.rpc_error(e))
+            })
+            .await?;
+        Ok(batch.map(|batch| {
+            batch
+                .changes
+                .into_iter()
+                .filter_map(|change| {
+                    let kind = match change.kind() {
+                        ChangeKind::Created => RemoteChangeKind::Created,
+                        ChangeKind::Modified => RemoteChangeKind::Modified,
+                        ChangeKind::Deleted => RemoteChangeKind::Deleted,
+                        ChangeKind::Renamed => RemoteChangeKind::Renamed,
+                        // 更新的服务端可能发送本端不认识的类型
+                        ChangeKind::Unspecified => return None,
+                    };
+                    Some(RemoteDirChange {
+                        kind,
+                        path: change.path,
+                        new_path: (kind == RemoteChangeKind::Renamed).then_some(change.new_path),
+                    })
+                })
+                .collect()
+        }))
+    }
+}
+
+// --- 远程目录大小统计 ---
+
+/// 一次进行中的远程目录大小统计
+pub struct RemoteDirUsageScan {
+    remote: RemoteClient,
+    stream: tonic::Streaming<DirUsageProgress>,
+}
+
+impl RemoteDirUsageScan {
+    /// 等待下一次累计值；最后一次的 complete 为 true，之后返回 None
+    pub async fn next(&mut self) -> Result<Option<RemoteDirUsage>> {
+        let remote = &self.remote;
+        let stream = &mut self.stream;
+        let progress = remote
+            .cancellable("dir_usage", async {
+                stream.message().await.map_err(|e| remote.rpc_error(e))
+            })
+            .await?;
+        Ok(progress.map(|p| RemoteDirUsage {
+            bytes: p.bytes,
+            files: p.files,
+            dirs: p.dirs,
+            errors: p.errors,
+            complete: p.complete,
+        }))
+    }
+
+    /// 等待统计完成，返回最终结果
+    pub async fn finish(mut self) -> Result<RemoteDirUsage> {
+        while let Some(usage) = self.next().await? {
+            if usage.complete {
+                return Ok(usage);
+            }
+        }
+        Err(Error::Integrity(
+            "Size summary ended without a final result".to_string(),
+        ))
+    }
+}
+
+// --- 远程目录分页列表 ---
+
+/// 一次进行中的远程目录流式列表
+pub struct RemoteDirListing {
+    remote: RemoteClient,
+    stream: tonic::Streaming<ListDirResponse>,
+}
+
+impl RemoteDirListing {
+    /// 等待下一页；全部发送完后返回 None
+    pub async fn next(&mut self) -> Result<Option<RemoteDirPage>> {
+        let remote = &self.remote;
+        let stream = &mut self.stream;
+        let page = remote
+            .cancellable("list_dir", async {
+                stream.message().await.map_err(|e| remote.rpc_error(e))
+            })
+            .await?;
+        Ok(page.map(RemoteDirPage::from))
+    }
+}
+
+// --- 远程查找 ---
+
+/// 一次进行中的远程查找
+pub struct RemoteSearch {
+    remote: RemoteClient,
+    stream: tonic::Streaming<SearchMatch>,
+}
+
+impl RemoteSearch {
+    /// 等待下一条结果；查找结束 (或达到结果上限) 后返回 None
+    pub async fn next(&mut self) -> Result<Option<RemoteSearchMatch>> {
+        let remote = &self.remote;
+        let stream = &mut self.stream;
+        let found = remote
+            .cancellable("search", async {
+                stream.message().await.map_err(|e| remote.rpc_error(e))
+            })
+            .await?;
+        Ok(found.map(|m| RemoteSearchMatch {
+            path: m.path,
+            entry: m.entry.unwrap_or_default().into(),
+        }))
+    }
+}
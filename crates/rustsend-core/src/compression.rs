@@ -0,0 +1,230 @@
+// rustsend-core/src/compression.rs
+
+//! Per-frame compression of `data` messages.
+//!
+//! Each frame is compressed on its own so the receiver can decode it without
+//! any state from earlier frames. The sender picks a codec per file and skips
+//! compression for formats that are already compressed.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::filerpc::Compression;
+use crate::protocol;
+
+/// zstd level: fast enough not to become the bottleneck on a LAN.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compress only if the sample shrinks to at most this fraction.
+const MIN_SAVING_RATIO: f64 = 0.9;
+
+/// Extensions of formats that are already compressed.
+const PRECOMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic",
+    "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "odt",
+    "ogg", "opus", "png", "pptx", "rar", "tgz", "txz", "webm", "webp", "whl", "woff2", "xlsx",
+    "xz", "zip", "zst",
+];
+
+/// Codecs this build can encode and decode, in order of preference.
+pub const SUPPORTED: &[Compression] = &[Compression::Zstd, Compression::Gzip];
+
+/// Feature name a server advertises for `codec`.
+pub fn feature_name(codec: Compression) -> Option<&'static str> {
+    match codec {
+        Compression::None => None,
+        Compression::Zstd => Some(protocol::FEATURE_COMPRESSION_ZSTD),
+        Compression::Gzip => Some(protocol::FEATURE_COMPRESSION_GZIP),
+    }
+}
+
+/// Decodes the raw enum value of a header, rejecting values this build does not know.
+pub fn from_wire(value: i32) -> Option<Compression> {
+    Compression::try_from(value).ok()
+}
+
+pub fn is_precompressed(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            PRECOMPRESSED_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+        .unwrap_or(false)
+}
+
+/// Picks the codec for a file from the codecs the peer accepts, given the
+/// file's name and its first chunk; `Compression::None` when it would not pay off.
+pub fn choose(path: &Path, sample: &[u8], accepted: &[Compression]) -> Compression {
+    if sample.is_empty() || is_precompressed(path) {
+        return Compression::None;
+    }
+    let Some(codec) = SUPPORTED
+        .iter()
+        .copied()
+        .find(|codec| accepted.contains(codec))
+    else {
+        return Compression::None;
+    };
+    match compress(codec, sample) {
+        Ok(compressed) if (compressed.len() as f64) <= sample.len() as f64 * MIN_SAVING_RATIO => {
+            codec
+        }
+        _ => Compression::None,
+    }
+}
+
+pub fn compress(codec: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+    match codec {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(
+                Vec::with_capacity(data.len() / 2),
+                flate2::Compression::fast(),
+            );
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Decompresses one frame, refusing output larger than `max_len` bytes.
+pub fn decompress(codec: Compression, data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    match codec {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Zstd => zstd::bulk::decompress(data, max_len),
+        Compression::Gzip => {
+            let mut out = Vec::new();
+            flate2::read::GzDecoder::new(data)
+                .take(max_len as u64 + 1)
+                .read_to_end(&mut out)?;
+            if out.len() > max_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("decompressed frame exceeds {} bytes", max_len),
+                ));
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Compression; 3] = [Compression::None, Compression::Zstd, Compression::Gzip];
+
+    fn text() -> Vec<u8> {
+        b"the quick brown fox jumps over the lazy dog\n".repeat(1000)
+    }
+
+    /// Does not shrink under either codec.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_decode_on_their_own() {
+        let data = text();
+        let (first, second) = data.split_at(10_000);
+        for codec in CODECS {
+            // Decoded out of order: no state is carried between frames.
+            let frames = [
+                compress(codec, first).unwrap(),
+                compress(codec, second).unwrap(),
+            ];
+            assert_eq!(decompress(codec, &frames[1], data.len()).unwrap(), second);
+            assert_eq!(decompress(codec, &frames[0], data.len()).unwrap(), first);
+            if codec != Compression::None {
+                assert!(frames[0].len() < first.len() / 10, "{:?}", codec);
+            }
+            let empty = compress(codec, &[]).unwrap();
+            assert!(decompress(codec, &empty, 0).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn oversized_and_corrupt_frames_are_refused() {
+        let data = text();
+        for codec in [Compression::Zstd, Compression::Gzip] {
+            let frame = compress(codec, &data).unwrap();
+            assert!(decompress(codec, &frame, data.len()).is_ok());
+            assert!(
+                decompress(codec, &frame, data.len() - 1).is_err(),
+                "{:?}",
+                codec
+            );
+            assert!(
+                decompress(codec, &data[..100], data.len()).is_err(),
+                "{:?}",
+                codec
+            );
+        }
+    }
+
+    #[test]
+    fn negotiation_prefers_zstd_and_falls_back() {
+        let path = Path::new("notes.txt");
+        let sample = text();
+        assert_eq!(choose(path, &sample, SUPPORTED), Compression::Zstd);
+        assert_eq!(
+            choose(path, &sample, &[Compression::Gzip, Compression::Zstd]),
+            Compression::Zstd
+        );
+        // A peer without zstd gets gzip, one without either gets raw frames.
+        assert_eq!(
+            choose(path, &sample, &[Compression::Gzip]),
+            Compression::Gzip
+        );
+        assert_eq!(choose(path, &sample, &[]), Compression::None);
+        assert_eq!(
+            choose(path, &sample, &[Compression::None]),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn negotiation_skips_what_would_not_shrink() {
+        let sample = text();
+        assert_eq!(
+            choose(Path::new("a.txt"), &[], SUPPORTED),
+            Compression::None
+        );
+        assert_eq!(
+            choose(Path::new("a.bin"), &noise(64 * 1024), SUPPORTED),
+            Compression::None
+        );
+        for name in ["photo.jpg", "PHOTO.JPG", "backup.tar.gz", "disk.zst"] {
+            assert_eq!(
+                choose(Path::new(name), &sample, SUPPORTED),
+                Compression::None,
+                "{}",
+                name
+            );
+        }
+        assert!(!is_precompressed(Path::new("archive.tar")));
+    }
+
+    #[test]
+    fn wire_values_and_feature_names() {
+        for codec in CODECS {
+            assert_eq!(from_wire(codec as i32), Some(codec));
+        }
+        assert_eq!(from_wire(99), None);
+        assert_eq!(feature_name(Compression::None), None);
+        for codec in SUPPORTED {
+            assert!(feature_name(*codec).is_some_and(|f| protocol::SUPPORTED_FEATURES.contains(&f)));
+        }
+    }
+}
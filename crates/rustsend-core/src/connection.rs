@@ -0,0 +1,286 @@
+// rustsend-core/src/connection.rs
+
+//! Channel setup with HTTP/2 keepalive and a background monitor that probes
+//! the server, reconnects with exponential backoff and publishes the
+//! connection state through a `watch` channel.
+
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch, Notify};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::error::{Error, Result};
+use crate::file_meta::PreservePolicy;
+use crate::filerpc::{file_service_client::FileServiceClient, ServerInfoRequest};
+use crate::protocol;
+
+/// Default limit for establishing the TCP/HTTP2 connection.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default limit for unary requests (listing, server info, ...).
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// HTTP/2 PING interval; also used for TCP keepalive.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// How long to wait for a PING ack before the transport is considered dead.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between application-level probes while connected.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+/// A probe slower than this marks the connection as degraded.
+const PROBE_SLOW_THRESHOLD: Duration = Duration::from_secs(2);
+/// A probe that takes longer than this counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive failed probes before the state drops to `Disconnected`.
+const MAX_DEGRADED_FAILURES: u32 = 3;
+const BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// A (re)connection attempt is in progress.
+    Connecting,
+    Connected,
+    /// Probes are failing or slow; calls may still succeed.
+    Degraded,
+    /// The server is unreachable; reconnection continues in the background.
+    Disconnected,
+}
+
+/// How a `RemoteClient` connects and how long it waits.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// Bearer token sent with every request.
+    pub token: Option<String>,
+    /// Limit for dialing the server, including re-dials after a disconnect.
+    pub connect_timeout: Duration,
+    /// Limit for unary requests and for the first response of a download.
+    /// Uploads are not limited, since their response only arrives at the end.
+    pub request_timeout: Option<Duration>,
+    /// Compress transfers when the server supports it (skipped per file for
+    /// already-compressed data).
+    pub compression: bool,
+    /// Metadata sent with uploads and restored on downloads.
+    pub preserve: PreservePolicy,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            token: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            compression: true,
+            preserve: PreservePolicy::default(),
+        }
+    }
+}
+
+/// Builds the endpoint for `url` with keepalive and the connect timeout.
+pub(crate) fn endpoint(url: &str, options: &ConnectOptions) -> Result<Endpoint> {
+    let endpoint = Endpoint::from_shared(url.to_string())
+        .map_err(|e| Error::InvalidArgument(format!("invalid server address {}: {}", url, e)))?;
+    Ok(endpoint
+        .connect_timeout(options.connect_timeout)
+        .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+        .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+        .keep_alive_while_idle(true)
+        .tcp_keepalive(Some(KEEPALIVE_INTERVAL)))
+}
+
+/// Per-connection request settings: bearer token and unary timeout.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestSettings {
+    auth: Option<AsciiMetadataValue>,
+    timeout: Option<Duration>,
+}
+
+impl RequestSettings {
+    pub(crate) fn new(options: &ConnectOptions) -> Result<Self> {
+        let token = options.token.as_deref();
+        let auth = match token.map(str::trim).filter(|t| !t.is_empty()) {
+            Some(token) => Some(format!("Bearer {}", token).parse().map_err(|_| {
+                Error::InvalidArgument("token must be printable ASCII".to_string())
+            })?),
+            None => None,
+        };
+        Ok(RequestSettings {
+            auth,
+            timeout: options.request_timeout,
+        })
+    }
+
+    /// A request without a deadline (uploads).
+    pub(crate) fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(value) = &self.auth {
+            request
+                .metadata_mut()
+                .insert("authorization", value.clone());
+        }
+        request
+    }
+
+    /// A request limited by the request timeout; the channel enforces the
+    /// `grpc-timeout` header on the client side as well.
+    pub(crate) fn unary<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = self.request(message);
+        if let Some(timeout) = self.timeout {
+            request.set_timeout(timeout);
+        }
+        request
+    }
+}
+
+/// Handle shared by all clones of a `RemoteClient`. The monitor task exits
+/// once every handle is gone, which closes the state channel for subscribers.
+#[derive(Clone)]
+pub(crate) struct ConnectionMonitor {
+    state: watch::Receiver<ConnectionState>,
+    wake: Arc<Notify>,
+    cancel: CancellationToken,
+    _alive: Arc<oneshot::Sender<()>>,
+}
+
+impl ConnectionMonitor {
+    /// Spawns the monitor for an already connected client.
+    pub(crate) fn spawn(
+        url: String,
+        client: FileServiceClient<Channel>,
+        settings: RequestSettings,
+    ) -> Self {
+        let (tx, rx) = watch::channel(ConnectionState::Connected);
+        let wake = Arc::new(Notify::new());
+        let cancel = CancellationToken::new();
+        let (alive, dropped) = oneshot::channel();
+        tokio::spawn(monitor(
+            url,
+            client,
+            settings,
+            tx,
+            wake.clone(),
+            cancel.clone(),
+            dropped,
+        ));
+        ConnectionMonitor {
+            state: rx,
+            wake,
+            cancel,
+            _alive: Arc::new(alive),
+        }
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Cancelled by `disconnect`; in-flight transfers select on it.
+    pub(crate) fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Stops the monitor and cancels every transfer of this connection.
+    pub(crate) fn disconnect(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Called when a request failed at the transport level, so the monitor
+    /// probes immediately instead of waiting for the next interval.
+    pub(crate) fn report_failure(&self, error: &Error) {
+        if matches!(error, Error::Network(_) | Error::Timeout(_)) {
+            self.wake.notify_one();
+        }
+    }
+}
+
+/// One application-level round trip. Any answer from the server, including
+/// UNIMPLEMENTED from legacy servers, proves the connection is alive.
+async fn probe(
+    client: &mut FileServiceClient<Channel>,
+    settings: &RequestSettings,
+) -> std::result::Result<Duration, ()> {
+    let started = tokio::time::Instant::now();
+    let request = settings.request(ServerInfoRequest {
+        client_protocol_version: protocol::PROTOCOL_VERSION,
+        client_app_version: protocol::APP_VERSION.to_string(),
+    });
+    match tokio::time::timeout(PROBE_TIMEOUT, client.get_server_info(request)).await {
+        Ok(Ok(_)) => Ok(started.elapsed()),
+        Ok(Err(status)) if status.code() == tonic::Code::Unimplemented => Ok(started.elapsed()),
+        Ok(Err(_)) | Err(_) => Err(()),
+    }
+}
+
+async fn monitor(
+    url: String,
+    mut client: FileServiceClient<Channel>,
+    settings: RequestSettings,
+    tx: watch::Sender<ConnectionState>,
+    wake: Arc<Notify>,
+    cancel: CancellationToken,
+    mut dropped: oneshot::Receiver<()>,
+) {
+    let mut failures = 0u32;
+    let mut backoff = BACKOFF_INITIAL;
+
+    loop {
+        let delay = if failures == 0 {
+            PROBE_INTERVAL
+        } else {
+            backoff
+        };
+        tokio::select! {
+            _ = &mut dropped => break,
+            _ = cancel.cancelled() => {
+                tx.send_replace(ConnectionState::Disconnected);
+                break;
+            }
+            _ = tokio::time::sleep(delay) => {}
+            _ = wake.notified() => {}
+        }
+
+        if *tx.borrow() == ConnectionState::Disconnected {
+            tx.send_replace(ConnectionState::Connecting);
+        }
+        // The channel re-dials lazily, so a successful probe is a reconnect.
+        match probe(&mut client, &settings).await {
+            Ok(elapsed) => {
+                if failures > 0 {
+                    info!("Connection to {} restored", url);
+                }
+                failures = 0;
+                backoff = BACKOFF_INITIAL;
+                let state = if elapsed > PROBE_SLOW_THRESHOLD {
+                    ConnectionState::Degraded
+                } else {
+                    ConnectionState::Connected
+                };
+                tx.send_if_modified(|s| std::mem::replace(s, state) != state);
+            }
+            Err(()) => {
+                failures += 1;
+                if failures > 1 {
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                }
+                let state = if failures >= MAX_DEGRADED_FAILURES {
+                    ConnectionState::Disconnected
+                } else {
+                    ConnectionState::Degraded
+                };
+                warn!(
+                    "Probe to {} failed ({} in a row), retrying in {:?}",
+                    url, failures, backoff
+                );
+                tx.send_if_modified(|s| std::mem::replace(s, state) != state);
+            }
+        }
+    }
+    info!("Connection monitor for {} stopped", url);
+}
@@ -0,0 +1,431 @@
+// rustsend-core/src/dedup.rs
+
+//! Content-defined chunk deduplication.
+//!
+//! Files are split with FastCDC, so an insertion only changes the chunks
+//! around it, and each chunk is identified by its SHA-256. The server keeps a
+//! `ChunkIndex` of every chunk under its share; a client asks which chunk
+//! hashes are already there and only sends the others.
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use fastcdc::v2020::StreamCDC;
+
+use crate::{checksum, file_meta};
+
+/// FastCDC parameters; both sides must use the same values for chunk
+/// boundaries, and therefore hashes, to line up.
+pub const MIN_CHUNK_SIZE: u32 = 16 * 1024;
+pub const AVG_CHUNK_SIZE: u32 = 64 * 1024;
+pub const MAX_CHUNK_SIZE: u32 = 256 * 1024;
+
+/// How often the server writes a changed index back to its cache file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type ChunkHash = [u8; 32];
+
+/// A chunk of a file: its hash, position and content.
+pub struct Chunk {
+    pub hash: ChunkHash,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Splits `reader` into content-defined chunks. Blocking; call from `spawn_blocking`.
+pub fn chunks(reader: impl Read) -> impl Iterator<Item = io::Result<Chunk>> {
+    StreamCDC::new(reader, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE).map(|chunk| {
+        let chunk = chunk?;
+        Ok(Chunk {
+            hash: Sha256::digest(&chunk.data).into(),
+            offset: chunk.offset,
+            data: chunk.data,
+        })
+    })
+}
+
+// --- Server-side Chunk Index ---
+
+/// Where the server can read a chunk back.
+#[derive(Debug, Clone)]
+pub struct ChunkLocation {
+    /// Path relative to the share root.
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChunkEntry {
+    hash: String,
+    offset: u64,
+    len: u32,
+}
+
+/// Chunks of one indexed file, valid while size and mtime are unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileRecord {
+    size: u64,
+    mtime: i64,
+    chunks: Vec<ChunkEntry>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct IndexFile {
+    files: HashMap<PathBuf, FileRecord>,
+}
+
+/// Every chunk of every regular file under the share, by hash.
+///
+/// Only kept when the server enables it (`with_chunk_index`). Built by a
+/// background scan at startup and updated after each upload. The index is
+/// cached in the user's cache directory so a restart only re-chunks files
+/// whose size or mtime changed. Entries can go stale when files change
+/// behind the server's back, so readers must verify a chunk's hash.
+#[derive(Debug)]
+pub struct ChunkIndex {
+    root: PathBuf,
+    cache_path: Option<PathBuf>,
+    chunks: DashMap<ChunkHash, ChunkLocation>,
+    files: DashMap<PathBuf, FileRecord>,
+    dirty: AtomicBool,
+}
+
+impl ChunkIndex {
+    /// Opens the index of the share at `root`, starting from the cached state if any.
+    pub fn open(root: &Path) -> Arc<Self> {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let cache_path = dirs::cache_dir().map(|dir| {
+            let digest = checksum::to_hex(&Sha256::digest(root.as_os_str().as_encoded_bytes()));
+            dir.join("rustsend")
+                .join(format!("chunk-index-{}.json", &digest[..16]))
+        });
+        let index = ChunkIndex {
+            root,
+            cache_path,
+            chunks: DashMap::new(),
+            files: DashMap::new(),
+            dirty: AtomicBool::new(false),
+        };
+        if let Some(path) = &index.cache_path {
+            match std::fs::read(path) {
+                Ok(bytes) => match serde_json::from_slice::<IndexFile>(&bytes) {
+                    Ok(file) => {
+                        for (rel, record) in file.files {
+                            index.insert_record(rel, record);
+                        }
+                    }
+                    Err(e) => warn!("Ignoring corrupt chunk index {}: {}", path.display(), e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Could not read chunk index {}: {}", path.display(), e),
+            }
+        }
+        Arc::new(index)
+    }
+
+    /// Scans the share in the background, then keeps saving changes periodically.
+    pub fn spawn_scan(self: &Arc<Self>) {
+        let index = self.clone();
+        tokio::spawn(async move {
+            let scan = index.clone();
+            match tokio::task::spawn_blocking(move || scan.scan()).await {
+                Ok(()) => index.save_if_dirty(),
+                Err(e) => error!("Chunk index scan failed: {}", e),
+            }
+            loop {
+                tokio::time::sleep(SAVE_INTERVAL).await;
+                let index = index.clone();
+                let _ = tokio::task::spawn_blocking(move || index.save_if_dirty()).await;
+            }
+        });
+    }
+
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        <&ChunkHash>::try_from(hash).is_ok_and(|hash| self.chunks.contains_key(hash))
+    }
+
+    pub fn locate(&self, hash: &[u8]) -> Option<ChunkLocation> {
+        let hash = <&ChunkHash>::try_from(hash).ok()?;
+        self.chunks.get(hash).map(|location| location.clone())
+    }
+
+    /// Re-indexes `path` (absolute) in a blocking task, e.g. after an upload.
+    pub fn index_later(self: &Arc<Self>, path: PathBuf) {
+        let index = self.clone();
+        tokio::task::spawn_blocking(move || index.index_file(&path));
+    }
+
+    /// Re-chunks a file; a file that no longer exists is dropped from the index.
+    /// Blocking.
+    pub fn index_file(&self, path: &Path) {
+        // A link that replaced a file: its target is indexed under its own path.
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+            self.remove_file(&self.relative(path));
+            return;
+        }
+        let Some(rel) = path
+            .canonicalize()
+            .ok()
+            .and_then(|p| p.strip_prefix(&self.root).ok().map(Path::to_path_buf))
+        else {
+            self.remove_file(&self.relative(path));
+            return;
+        };
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                if let Err(e) = self.chunk_file(&rel, &metadata) {
+                    warn!("Could not index {}: {}", path.display(), e);
+                    self.remove_file(&rel);
+                }
+            }
+            _ => self.remove_file(&rel),
+        }
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.root)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    fn chunk_file(&self, rel: &Path, metadata: &std::fs::Metadata) -> io::Result<()> {
+        let file = file_meta::open_untouched(&self.root.join(rel))?;
+        let mut entries = Vec::new();
+        for chunk in chunks(io::BufReader::new(file)) {
+            let chunk = chunk?;
+            entries.push(ChunkEntry {
+                hash: checksum::to_hex(&chunk.hash),
+                offset: chunk.offset,
+                len: chunk.data.len() as u32,
+            });
+        }
+        let record = FileRecord {
+            size: metadata.len(),
+            mtime: file_meta::mtime_secs(metadata),
+            chunks: entries,
+        };
+        self.remove_file(rel);
+        self.insert_record(rel.to_path_buf(), record);
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn insert_record(&self, rel: PathBuf, record: FileRecord) {
+        for entry in &record.chunks {
+            if let Some(hash) = parse_hash(&entry.hash) {
+                self.chunks.insert(
+                    hash,
+                    ChunkLocation {
+                        path: rel.clone(),
+                        offset: entry.offset,
+                        len: entry.len,
+                    },
+                );
+            }
+        }
+        self.files.insert(rel, record);
+    }
+
+    fn remove_file(&self, rel: &Path) {
+        let Some((_, record)) = self.files.remove(rel) else {
+            return;
+        };
+        for entry in &record.chunks {
+            if let Some(hash) = parse_hash(&entry.hash) {
+                self.chunks
+                    .remove_if(&hash, |_, location| location.path == rel);
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Walks the share, skipping hidden entries and symlinks, and re-chunks
+    /// files whose size or mtime changed since they were indexed.
+    fn scan(&self) {
+        info!("Indexing chunks under {}", self.root.display());
+        let mut seen = HashSet::new();
+        let mut pending = vec![self.root.clone()];
+        let mut indexed = 0usize;
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Skipping {} while indexing: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                let path = entry.path();
+                if metadata.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if !metadata.is_file() || metadata.len() == 0 {
+                    continue;
+                }
+                let rel = self.relative(&path);
+                let unchanged = self.files.get(&rel).is_some_and(|record| {
+                    record.size == metadata.len()
+                        && record.mtime == file_meta::mtime_secs(&metadata)
+                });
+                if !unchanged {
+                    match self.chunk_file(&rel, &metadata) {
+                        Ok(()) => indexed += 1,
+                        Err(e) => warn!("Could not index {}: {}", path.display(), e),
+                    }
+                }
+                seen.insert(rel);
+            }
+        }
+
+        let removed: Vec<PathBuf> = self
+            .files
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|rel| !seen.contains(rel))
+            .collect();
+        for rel in &removed {
+            self.remove_file(rel);
+        }
+        info!(
+            "Chunk index ready: {} files, {} chunks ({} re-indexed, {} removed)",
+            self.files.len(),
+            self.chunks.len(),
+            indexed,
+            removed.len()
+        );
+    }
+
+    /// Writes the index to its cache file if it changed. Blocking.
+    fn save_if_dirty(&self) {
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let file = IndexFile {
+            files: self
+                .files
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+        };
+        let result = (|| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let temp = path.with_extension("json.tmp");
+            std::fs::write(&temp, serde_json::to_vec(&file)?)?;
+            std::fs::rename(&temp, path)
+        })();
+        if let Err(e) = result {
+            warn!("Could not save chunk index {}: {}", path.display(), e);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn parse_hash(hex: &str) -> Option<ChunkHash> {
+    checksum::from_hex(hex)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ConflictMode, RemoteClient};
+    use crate::filerpc::file_service_client::FileServiceClient;
+    use crate::filerpc::QueryChunksRequest;
+    use crate::server::{tests::serve, MyFileService};
+
+    /// Incompressible, repeatable content, so chunk boundaries are stable.
+    fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    fn hashes(data: &[u8]) -> Vec<(ChunkHash, usize)> {
+        chunks(data)
+            .map(|chunk| {
+                let chunk = chunk.unwrap();
+                (chunk.hash, chunk.data.len())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn known_chunks_are_sent_by_hash() {
+        let share = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(share.path().to_path_buf()).with_chunk_index(true);
+        let url = serve(service).await;
+        let remote = RemoteClient::connect(url.clone()).await.unwrap();
+
+        // v2 differs from v1 only in the middle, so only the chunks around
+        // the change are new.
+        let v1 = noise(1024 * 1024, 7);
+        let mut v2 = v1.clone();
+        v2[500_000..501_000].copy_from_slice(&noise(1000, 9));
+        std::fs::write(local.path().join("v1.bin"), &v1).unwrap();
+        std::fs::write(local.path().join("v2.bin"), &v2).unwrap();
+        remote
+            .upload_file(&local.path().join("v1.bin"), "/".into(), ConflictMode::Fail)
+            .await
+            .unwrap();
+
+        // The server indexes the new file in the background.
+        let old: HashSet<ChunkHash> = hashes(&v1).into_iter().map(|(h, _)| h).collect();
+        let mut client = FileServiceClient::connect(url).await.unwrap();
+        let query = QueryChunksRequest {
+            hashes: old.iter().map(|hash| hash.to_vec()).collect(),
+        };
+        for _ in 0..500 {
+            let present = client.query_chunks(query.clone()).await.unwrap();
+            if present.into_inner().present.iter().all(|p| *p) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let reused: usize = hashes(&v2)
+            .into_iter()
+            .filter(|(hash, _)| old.contains(hash))
+            .map(|(_, len)| len)
+            .sum();
+        assert!(reused > 0 && reused < v2.len());
+        let status = remote
+            .upload_file_dedup(&local.path().join("v2.bin"), "/".into(), ConflictMode::Fail)
+            .await
+            .unwrap();
+        assert!(status.success);
+        assert!(
+            status
+                .message
+                .ends_with(&format!("Reused {} bytes of existing chunks.", reused)),
+            "{}",
+            status.message
+        );
+        assert_eq!(std::fs::read(share.path().join("v2.bin")).unwrap(), v2);
+    }
+}
@@ -0,0 +1,495 @@
+// rustsend-core/src/delta.rs
+
+//! rsync-style delta encoding.
+//!
+//! The receiver splits its existing copy into fixed-size blocks and sends a
+//! weak rolling checksum plus a SHA-256 for each block. The sender slides a
+//! window over its new file, looks the rolling checksum up in that index and
+//! confirms candidates with the strong hash. Matched blocks become `Copy`
+//! operations; everything else is sent as literal data.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::filerpc::BlockSignature;
+
+/// Smallest and largest block size used for signatures.
+pub const MIN_BLOCK_SIZE: usize = 2 * 1024;
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Bytes read from the new file per refill of the sliding window.
+const READ_SIZE: usize = 256 * 1024;
+
+/// Block size for a basis file of `len` bytes: about √len, like rsync,
+/// rounded to a multiple of 1 KB and clamped to the allowed range.
+pub fn block_size_for(len: u64) -> usize {
+    let root = (len as f64).sqrt() as usize;
+    root.div_ceil(1024)
+        .saturating_mul(1024)
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+// --- Rolling Checksum ---
+
+/// Adler-32 style checksum that can slide over the data one byte at a time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    pub fn new(window: &[u8]) -> Self {
+        let mut rolling = Rolling::default();
+        for &byte in window {
+            rolling.a = rolling.a.wrapping_add(byte as u32);
+            rolling.b = rolling.b.wrapping_add(rolling.a);
+        }
+        rolling.len = window.len() as u32;
+        rolling
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+
+    /// Drops `out` from the front of the window and appends `input`.
+    pub fn roll(&mut self, out: u8, input: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(input as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    /// Drops `out` from the front of the window, shrinking it by one byte.
+    pub fn roll_out(&mut self, out: u8) {
+        self.a = self.a.wrapping_sub(out as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32));
+        self.len -= 1;
+    }
+}
+
+pub fn strong_hash(block: &[u8]) -> Vec<u8> {
+    Sha256::digest(block).to_vec()
+}
+
+// --- Signatures ---
+
+/// Reads `reader` block by block and hands signatures to `emit` in batches
+/// of at most `batch_len`. Stops early when `emit` returns false.
+/// Blocking; call from `spawn_blocking`.
+pub fn signatures(
+    mut reader: impl Read,
+    block_size: usize,
+    batch_len: usize,
+    mut emit: impl FnMut(Vec<BlockSignature>) -> bool,
+) -> io::Result<()> {
+    let mut buffer = vec![0u8; block_size];
+    let mut batch = Vec::with_capacity(batch_len);
+    loop {
+        let n = read_full(&mut reader, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        let block = &buffer[..n];
+        batch.push(BlockSignature {
+            weak: Rolling::new(block).digest(),
+            strong: strong_hash(block),
+        });
+        if batch.len() == batch_len && !emit(std::mem::take(&mut batch)) {
+            return Ok(());
+        }
+        if n < block_size {
+            break;
+        }
+    }
+    if !batch.is_empty() {
+        emit(batch);
+    }
+    Ok(())
+}
+
+/// Like `read_exact`, but a short read at end of file is not an error.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// The receiver's signatures, indexed by weak checksum.
+#[derive(Debug)]
+pub struct SignatureIndex {
+    block_size: usize,
+    basis_size: u64,
+    blocks: HashMap<u32, Vec<(u64, Vec<u8>)>>,
+    block_count: u64,
+}
+
+impl SignatureIndex {
+    pub fn new(block_size: usize, basis_size: u64) -> Self {
+        SignatureIndex {
+            block_size,
+            basis_size,
+            blocks: HashMap::new(),
+            block_count: 0,
+        }
+    }
+
+    /// Appends the next signatures in block order.
+    pub fn extend(&mut self, signatures: impl IntoIterator<Item = BlockSignature>) {
+        for signature in signatures {
+            self.blocks
+                .entry(signature.weak)
+                .or_default()
+                .push((self.block_count, signature.strong));
+            self.block_count += 1;
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn basis_size(&self) -> u64 {
+        self.basis_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.block_count == 0
+    }
+
+    /// Length of block `index` in the basis file; only the last one may be short.
+    fn block_len(&self, index: u64) -> usize {
+        let start = index * self.block_size as u64;
+        (self.basis_size.saturating_sub(start)).min(self.block_size as u64) as usize
+    }
+
+    /// The basis block whose content equals `window`, if any. The strong hash
+    /// is only computed when the weak checksum has candidates.
+    fn find(&self, weak: u32, window: &[u8]) -> Option<u64> {
+        let candidates = self.blocks.get(&weak)?;
+        let mut strong = None;
+        candidates.iter().find_map(|(index, hash)| {
+            if self.block_len(*index) != window.len() {
+                return None;
+            }
+            let strong = strong.get_or_insert_with(|| strong_hash(window));
+            (strong == hash).then_some(*index)
+        })
+    }
+}
+
+// --- Delta Generation ---
+
+/// One step of rebuilding the new file on the receiver.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Copy `count` consecutive basis blocks starting at `start_block`.
+    Copy { start_block: u64, count: u32 },
+    /// Bytes that do not occur in the basis file.
+    Literal(Vec<u8>),
+}
+
+/// Totals of a generated delta.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeltaStats {
+    pub copied_bytes: u64,
+    pub literal_bytes: u64,
+}
+
+/// Collects literal bytes and merges adjacent block copies before emitting.
+struct OpWriter<F> {
+    emit: F,
+    literal: Vec<u8>,
+    max_literal: usize,
+    copy: Option<(u64, u32)>,
+    stats: DeltaStats,
+    stopped: bool,
+}
+
+impl<F: FnMut(DeltaOp) -> bool> OpWriter<F> {
+    fn send(&mut self, op: DeltaOp) {
+        if !self.stopped && !(self.emit)(op) {
+            self.stopped = true;
+        }
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((start_block, count)) = self.copy.take() {
+            self.send(DeltaOp::Copy { start_block, count });
+        }
+    }
+
+    fn flush_literal(&mut self) {
+        if !self.literal.is_empty() {
+            let literal = std::mem::take(&mut self.literal);
+            self.send(DeltaOp::Literal(literal));
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.flush_copy();
+        self.literal.push(byte);
+        self.stats.literal_bytes += 1;
+        if self.literal.len() >= self.max_literal {
+            self.flush_literal();
+        }
+    }
+
+    fn push_block(&mut self, index: u64, len: usize) {
+        self.flush_literal();
+        self.stats.copied_bytes += len as u64;
+        match &mut self.copy {
+            Some((start, count)) if *start + *count as u64 == index && *count < u32::MAX => {
+                *count += 1
+            }
+            _ => {
+                self.flush_copy();
+                self.copy = Some((index, 1));
+            }
+        }
+    }
+}
+
+/// Streams the delta of `reader` against the basis described by `index`.
+/// Literal runs are at most `max_literal` bytes. Stops early when `emit`
+/// returns false. Blocking; call from `spawn_blocking`.
+pub fn compute_delta(
+    mut reader: impl Read,
+    index: &SignatureIndex,
+    max_literal: usize,
+    emit: impl FnMut(DeltaOp) -> bool,
+) -> io::Result<DeltaStats> {
+    let block_size = index.block_size();
+    let mut out = OpWriter {
+        emit,
+        literal: Vec::with_capacity(max_literal),
+        max_literal: max_literal.max(1),
+        copy: None,
+        stats: DeltaStats::default(),
+        stopped: false,
+    };
+
+    let mut buffer: Vec<u8> = Vec::with_capacity(READ_SIZE + block_size);
+    let mut chunk = vec![0u8; READ_SIZE];
+    let mut pos = 0;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+
+    while !out.stopped {
+        // Keep a full window (or whatever is left of the file) ahead of `pos`.
+        while !eof && buffer.len() - pos < block_size + 1 {
+            if pos > 0 {
+                buffer.drain(..pos);
+                pos = 0;
+            }
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                eof = true;
+            } else {
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+        }
+        let available = buffer.len() - pos;
+        if available == 0 {
+            break;
+        }
+        let window_len = available.min(block_size);
+        let window = &buffer[pos..pos + window_len];
+        let weak = *rolling.get_or_insert_with(|| Rolling::new(window));
+
+        if let Some(block) = index.find(weak.digest(), window) {
+            out.push_block(block, window_len);
+            pos += window_len;
+            rolling = None;
+            continue;
+        }
+
+        let byte = buffer[pos];
+        out.push_byte(byte);
+        let rolling = rolling
+            .as_mut()
+            .expect("rolling checksum initialized above");
+        if available > block_size {
+            rolling.roll(byte, buffer[pos + block_size]);
+        } else {
+            rolling.roll_out(byte);
+        }
+        pos += 1;
+    }
+
+    out.flush_copy();
+    out.flush_literal();
+    Ok(out.stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: usize = 64;
+
+    /// Deterministic pseudo-random bytes (xorshift), so failures reproduce.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn index_of(basis: &[u8]) -> SignatureIndex {
+        let mut index = SignatureIndex::new(BLOCK, basis.len() as u64);
+        signatures(basis, BLOCK, 7, |batch| {
+            index.extend(batch);
+            true
+        })
+        .unwrap();
+        index
+    }
+
+    /// Rebuilds the new file the way the receiver does.
+    fn apply(basis: &[u8], ops: &[DeltaOp]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                DeltaOp::Copy { start_block, count } => {
+                    let start = *start_block as usize * BLOCK;
+                    let end = (start + *count as usize * BLOCK).min(basis.len());
+                    out.extend_from_slice(&basis[start..end]);
+                }
+                DeltaOp::Literal(data) => out.extend_from_slice(data),
+            }
+        }
+        out
+    }
+
+    /// Deltas `new` against `basis`, checks that it rebuilds `new` and
+    /// returns the ops and totals.
+    fn round_trip(basis: &[u8], new: &[u8]) -> (Vec<DeltaOp>, DeltaStats) {
+        let index = index_of(basis);
+        let mut ops = Vec::new();
+        let stats = compute_delta(new, &index, 100, |op| {
+            ops.push(op);
+            true
+        })
+        .unwrap();
+        assert_eq!(apply(basis, &ops), new);
+        assert_eq!(
+            stats.copied_bytes + stats.literal_bytes,
+            new.len() as u64,
+            "totals must cover the new file"
+        );
+        (ops, stats)
+    }
+
+    #[test]
+    fn rolled_checksum_equals_fresh_checksum() {
+        let data = noise(1000, 1);
+        let mut rolling = Rolling::new(&data[..BLOCK]);
+        for start in 1..=data.len() - BLOCK {
+            rolling.roll(data[start - 1], data[start + BLOCK - 1]);
+            let window = &data[start..start + BLOCK];
+            assert_eq!(
+                rolling.digest(),
+                Rolling::new(window).digest(),
+                "at {start}"
+            );
+        }
+        // Shrinking the window at the end of the file.
+        let mut start = data.len() - BLOCK;
+        while start < data.len() - 1 {
+            rolling.roll_out(data[start]);
+            start += 1;
+            assert_eq!(rolling.digest(), Rolling::new(&data[start..]).digest());
+        }
+    }
+
+    #[test]
+    fn identical_file_is_one_copy() {
+        let basis = noise(BLOCK * 20, 2);
+        let (ops, stats) = round_trip(&basis, &basis);
+        assert_eq!(
+            ops,
+            vec![DeltaOp::Copy {
+                start_block: 0,
+                count: 20
+            }]
+        );
+        assert_eq!(stats.literal_bytes, 0);
+    }
+
+    #[test]
+    fn short_final_block_is_matched() {
+        let basis = noise(BLOCK * 10 + 17, 3);
+        let (_, stats) = round_trip(&basis, &basis);
+        assert_eq!(stats.literal_bytes, 0);
+
+        // An edit inside the short block only resends that block.
+        let mut new = basis.clone();
+        *new.last_mut().unwrap() ^= 0xff;
+        let (_, stats) = round_trip(&basis, &new);
+        assert_eq!(stats.literal_bytes, 17);
+    }
+
+    #[test]
+    fn insertions_are_sent_as_literals() {
+        let basis = noise(BLOCK * 30 + 5, 4);
+        let inserted = noise(40, 5);
+        for at in [0, BLOCK * 15 + 3, basis.len()] {
+            let mut new = basis.clone();
+            new.splice(at..at, inserted.iter().copied());
+            let (_, stats) = round_trip(&basis, &new);
+            // The insertion plus at most the block it split.
+            assert!(
+                stats.literal_bytes <= (inserted.len() + 2 * BLOCK) as u64,
+                "insert at {at}: {stats:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn deletions_reuse_the_remaining_blocks() {
+        let basis = noise(BLOCK * 30 + 5, 6);
+        for at in [0, BLOCK * 15 + 3, basis.len() - 40] {
+            let mut new = basis.clone();
+            new.drain(at..at + 40);
+            let (_, stats) = round_trip(&basis, &new);
+            assert!(
+                stats.literal_bytes <= 2 * BLOCK as u64,
+                "delete at {at}: {stats:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_basis_sends_everything() {
+        let new = noise(BLOCK * 3 + 1, 7);
+        let (ops, stats) = round_trip(&[], &new);
+        assert_eq!(stats.copied_bytes, 0);
+        assert!(ops.iter().all(|op| matches!(op, DeltaOp::Literal(_))));
+
+        let (ops, _) = round_trip(&[], &[]);
+        assert!(ops.is_empty());
+    }
+
+    #[test]
+    fn emptied_file_has_no_ops() {
+        let (ops, _) = round_trip(&noise(BLOCK * 4, 8), &[]);
+        assert!(ops.is_empty());
+    }
+}
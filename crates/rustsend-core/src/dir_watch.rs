@@ -0,0 +1,159 @@
+// rustsend-core/src/dir_watch.rs
+
+//! Turns file-system events under the share into the `DirChange` batches
+//! streamed by `WatchDir`.
+//!
+//! Events are collected for `FLUSH_INTERVAL` and sent as one batch, with
+//! repeated modifications of a file folded into one change. Renames arrive
+//! from inotify as a "from" half, a "to" half and a combined event; the
+//! halves are held in the batch and replaced by a single `Renamed` change
+//! when the combined event follows, otherwise they stand for a deletion
+//! (moved out of the watched tree) or a creation (moved in).
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::filerpc::{ChangeKind, DirChange, DirChangeBatch};
+use crate::protocol;
+
+/// How long changes are collected before a batch is sent.
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Pending {
+    change: DirChange,
+    /// Half of a rename that may still be paired.
+    rename_half: bool,
+}
+
+/// Collects the changes of one `WatchDir` stream.
+pub struct ChangeBatcher {
+    root: PathBuf,
+    pending: Vec<Pending>,
+}
+
+impl ChangeBatcher {
+    /// `root` is the canonical share root paths are reported relative to.
+    pub fn new(root: PathBuf) -> Self {
+        ChangeBatcher {
+            root,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: notify::Event) {
+        let paths: Vec<Option<String>> = event.paths.iter().map(|p| self.relative(p)).collect();
+        let first = paths.first().cloned().flatten();
+        match event.kind {
+            EventKind::Create(_) => self.add(ChangeKind::Created, first, false),
+            EventKind::Remove(_) => self.add(ChangeKind::Deleted, first, false),
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                self.add(ChangeKind::Deleted, first, true)
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                self.add(ChangeKind::Created, first, true)
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                let second = paths.get(1).cloned().flatten();
+                self.rename(first, second);
+            }
+            // Platforms that do not say which side of a rename a path is on.
+            EventKind::Modify(ModifyKind::Name(_)) => {
+                let kind = match event.paths.first() {
+                    Some(path) if path.exists() => ChangeKind::Created,
+                    _ => ChangeKind::Deleted,
+                };
+                self.add(kind, first, false)
+            }
+            // Permission and timestamp changes do not alter what a listing shows.
+            EventKind::Modify(ModifyKind::Metadata(_)) => {}
+            EventKind::Modify(_) => self.add(ChangeKind::Modified, first, false),
+            _ => {}
+        }
+    }
+
+    /// The changes collected since the last call, if any.
+    pub fn take(&mut self) -> Option<DirChangeBatch> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let changes = self.pending.drain(..).map(|p| p.change).collect();
+        Some(DirChangeBatch { changes })
+    }
+
+    fn relative(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        Some(
+            rel.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+
+    fn add(&mut self, kind: ChangeKind, path: Option<String>, rename_half: bool) {
+        let Some(path) = path.filter(|p| !protocol::is_partial(p)) else {
+            return;
+        };
+        if kind == ChangeKind::Modified
+            && self.pending.iter().any(|p| {
+                p.change.path == path
+                    && matches!(p.change.kind(), ChangeKind::Created | ChangeKind::Modified)
+            })
+        {
+            return;
+        }
+        self.pending.push(Pending {
+            change: DirChange {
+                kind: kind as i32,
+                path,
+                new_path: String::new(),
+            },
+            rename_half,
+        });
+    }
+
+    fn rename(&mut self, from: Option<String>, to: Option<String>) {
+        let is_half = |p: &Pending, kind: ChangeKind, path: &Option<String>| {
+            p.rename_half && p.change.kind() == kind && Some(&p.change.path) == path.as_ref()
+        };
+        let position = self
+            .pending
+            .iter()
+            .position(|p| is_half(p, ChangeKind::Deleted, &from));
+        self.pending.retain(|p| {
+            !is_half(p, ChangeKind::Deleted, &from) && !is_half(p, ChangeKind::Created, &to)
+        });
+
+        // Finishing an upload renames its temp file: report the result as new.
+        let change = match (
+            from.filter(|p| !protocol::is_partial(p)),
+            to.filter(|p| !protocol::is_partial(p)),
+        ) {
+            (Some(from), Some(to)) => DirChange {
+                kind: ChangeKind::Renamed as i32,
+                path: from,
+                new_path: to,
+            },
+            (None, Some(to)) => DirChange {
+                kind: ChangeKind::Created as i32,
+                path: to,
+                new_path: String::new(),
+            },
+            (Some(from), None) => DirChange {
+                kind: ChangeKind::Deleted as i32,
+                path: from,
+                new_path: String::new(),
+            },
+            (None, None) => return,
+        };
+        let pending = Pending {
+            change,
+            rename_half: false,
+        };
+        match position {
+            Some(index) if index <= self.pending.len() => self.pending.insert(index, pending),
+            _ => self.pending.push(pending),
+        }
+    }
+}
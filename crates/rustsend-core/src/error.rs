@@ -0,0 +1,192 @@
+// rustsend-core/src/error.rs
+
+//! Structured error type returned by the client and the Tauri commands.
+//!
+//! Serializes as `{ code, message, details }` so the frontend can branch on
+//! the stable `code` instead of parsing human-readable text. `Display` is
+//! English (logs, CLI); the serialized `message` follows the `i18n` locale.
+
+use crate::{i18n, protocol};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use tonic::{Code, Status};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Not connected to a server")]
+    NotConnected,
+
+    #[error("Path is outside the shared directory: {0}")]
+    PathOutsideSandbox(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// The target already exists and the conflict policy does not allow replacing it.
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// The server could not be reached or the connection dropped.
+    #[error("Network error: {0}")]
+    Network(String),
+
+    /// Connecting or a request took longer than the configured timeout.
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    /// The operation was cancelled, e.g. by disconnecting.
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    /// The server does not advertise a feature this operation needs.
+    #[error("Server v{server_version} does not support \"{feature}\", please upgrade the peer")]
+    Unsupported {
+        feature: String,
+        server_version: String,
+    },
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The server's file system has no room for the upload.
+    #[error("Not enough space: {0}")]
+    InsufficientSpace(String),
+
+    /// The upload would take the server's share over its quota.
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// Size or digest mismatch, or a malformed transfer stream.
+    #[error("Integrity check failed: {0}")]
+    Integrity(String),
+
+    /// Local filesystem error other than not-found / permission-denied.
+    #[error("I/O error on {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Any other gRPC status returned by the server.
+    #[error("Server error ({code:?}): {message}")]
+    Remote { code: Code, message: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Maps a local I/O error on `path` to the most specific variant.
+    pub fn io(path: impl AsRef<std::path::Path>, source: std::io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        match source.kind() {
+            std::io::ErrorKind::NotFound => Error::NotFound(path),
+            std::io::ErrorKind::PermissionDenied => Error::PermissionDenied(path),
+            std::io::ErrorKind::AlreadyExists => Error::Conflict(path),
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// Stable, language-independent identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotConnected => "not_connected",
+            Error::PathOutsideSandbox(_) => "path_outside_sandbox",
+            Error::NotFound(_) => "not_found",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::Conflict(_) => "conflict",
+            Error::Network(_) => "network",
+            Error::Timeout(_) => "timeout",
+            Error::Cancelled(_) => "cancelled",
+            Error::Unsupported { .. } => "unsupported",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::InsufficientSpace(_) => "insufficient_space",
+            Error::QuotaExceeded(_) => "quota_exceeded",
+            Error::Integrity(_) => "integrity",
+            Error::Io { .. } => "io",
+            Error::Remote { .. } => "remote",
+        }
+    }
+
+    /// Extra machine-readable context, if any.
+    pub fn details(&self) -> Option<String> {
+        match self {
+            Error::Unsupported { feature, .. } => Some(feature.clone()),
+            Error::Io { source, .. } => Some(source.kind().to_string()),
+            Error::Remote { code, .. } => Some(format!("{:?}", code)),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &i18n::error_message(self))?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        let reason = status
+            .metadata()
+            .get(protocol::ERROR_REASON_METADATA)
+            .and_then(|v| v.to_str().ok());
+        match reason {
+            Some(protocol::REASON_PATH_OUTSIDE_SANDBOX) => {
+                return Error::PathOutsideSandbox(message)
+            }
+            Some(protocol::REASON_INSUFFICIENT_SPACE) => return Error::InsufficientSpace(message),
+            Some(protocol::REASON_QUOTA_EXCEEDED) => return Error::QuotaExceeded(message),
+            _ => {}
+        }
+
+        // Client-side transport failures surface as UNKNOWN with the transport error as source.
+        let transport_failure = std::error::Error::source(&status)
+            .is_some_and(|source| source.is::<tonic::transport::Error>());
+        if status.code() == Code::Unknown && transport_failure {
+            return Error::Network(with_causes(message, std::error::Error::source(&status)));
+        }
+
+        match status.code() {
+            Code::NotFound => Error::NotFound(message),
+            Code::PermissionDenied | Code::Unauthenticated => Error::PermissionDenied(message),
+            Code::AlreadyExists => Error::Conflict(message),
+            Code::Unavailable => Error::Network(message),
+            Code::DeadlineExceeded => Error::Timeout(message),
+            Code::Cancelled => Error::Cancelled(message),
+            Code::DataLoss => Error::Integrity(message),
+            code => Error::Remote { code, message },
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(e: tonic::transport::Error) -> Self {
+        Error::Network(with_causes(e.to_string(), std::error::Error::source(&e)))
+    }
+}
+
+/// transport::Error's Display is just "transport error"; append the cause chain.
+fn with_causes(
+    mut message: String,
+    mut source: Option<&(dyn std::error::Error + 'static)>,
+) -> String {
+    while let Some(cause) = source {
+        let text = cause.to_string();
+        // hyper repeats its cause in its own message
+        if !message.ends_with(&text) {
+            message.push_str(": ");
+            message.push_str(&text);
+        }
+        source = cause.source();
+    }
+    message
+}
@@ -0,0 +1,405 @@
+// src/file_meta.rs
+
+//! File metadata helpers shared by the server and the client.
+//!
+//! The sender describes a file's timestamps, mode and (optionally) extended
+//! attributes in its `UploadHeader`; the receiver restores them on the
+//! completed file as far as its `PreservePolicy` allows.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, FileTimes, Metadata};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::filerpc::UploadHeader;
+
+/// Upper bound on the extended attributes sent with one file. Larger sets
+/// (e.g. resource forks) are not sent at all.
+pub const MAX_XATTR_BYTES: usize = 64 * 1024;
+
+/// Which metadata of a received file is restored from the sender's copy.
+///
+/// Senders always describe timestamps and mode, which are cheap; extended
+/// attributes are only read and sent when the sender's policy includes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreservePolicy {
+    pub mtime: bool,
+    pub atime: bool,
+    /// Unix permission bits; setuid, setgid and sticky are never restored.
+    pub mode: bool,
+    /// User extended attributes (`security.*`, `system.*` and `trusted.*`
+    /// are skipped).
+    pub xattrs: bool,
+}
+
+impl Default for PreservePolicy {
+    /// Modification time and permissions, like `cp -p` without ownership.
+    fn default() -> Self {
+        PreservePolicy {
+            mtime: true,
+            atime: false,
+            mode: true,
+            xattrs: false,
+        }
+    }
+}
+
+impl PreservePolicy {
+    pub const NONE: PreservePolicy = PreservePolicy {
+        mtime: false,
+        atime: false,
+        mode: false,
+        xattrs: false,
+    };
+
+    pub const ALL: PreservePolicy = PreservePolicy {
+        mtime: true,
+        atime: true,
+        mode: true,
+        xattrs: true,
+    };
+}
+
+/// Parses a comma-separated list such as `mtime,mode`, or `all` / `none`.
+impl FromStr for PreservePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut policy = PreservePolicy::NONE;
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item {
+                "all" => policy = PreservePolicy::ALL,
+                "none" => policy = PreservePolicy::NONE,
+                "mtime" => policy.mtime = true,
+                "atime" => policy.atime = true,
+                "mode" => policy.mode = true,
+                "xattrs" => policy.xattrs = true,
+                other => {
+                    return Err(Error::InvalidArgument(format!(
+                    "unknown metadata \"{}\" (expected mtime, atime, mode, xattrs, all or none)",
+                    other
+                )))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Returns the mtime of `metadata` as Unix seconds (0 if unavailable).
+pub fn mtime_secs(metadata: &Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Returns the Unix permission bits of `metadata` (0 on non-Unix platforms).
+#[cfg(unix)]
+pub fn unix_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+pub fn unix_mode(_metadata: &Metadata) -> u32 {
+    0
+}
+
+/// Splits a timestamp into Unix seconds and nanoseconds; (0, 0) if unknown
+/// or before the epoch.
+fn unix_time(time: io::Result<SystemTime>) -> (i64, u32) {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| (d.as_secs() as i64, d.subsec_nanos()))
+        .unwrap_or((0, 0))
+}
+
+/// Fills the timestamps, mode and, if `policy.xattrs` is set, the extended
+/// attributes of `header` from the local file at `path`.
+pub fn describe(
+    path: &Path,
+    metadata: &Metadata,
+    policy: PreservePolicy,
+    header: &mut UploadHeader,
+) {
+    (header.mtime, header.mtime_nsec) = unix_time(metadata.modified());
+    (header.atime, header.atime_nsec) = unix_time(metadata.accessed());
+    header.mode = unix_mode(metadata);
+    if policy.xattrs {
+        header.xattrs = xattrs::read(path);
+    }
+}
+
+/// Restores the metadata carried by `header` on the received file at `path`,
+/// limited to what `policy` allows. Fields the sender left empty are skipped.
+///
+/// Applied before the file is moved into place, so it never shows up with
+/// the wrong permissions. Attributes go first and the mode last, since a
+/// read-only mode would prevent setting attributes afterwards.
+pub fn apply(path: &Path, header: &UploadHeader, policy: PreservePolicy) -> io::Result<()> {
+    if policy.xattrs && !header.xattrs.is_empty() {
+        xattrs::write(path, &header.xattrs);
+    }
+
+    let mut times = FileTimes::new();
+    let mut set_times = false;
+    if policy.mtime && header.mtime > 0 {
+        times = times.set_modified(to_system_time(header.mtime, header.mtime_nsec));
+        set_times = true;
+    }
+    if policy.atime && header.atime > 0 {
+        times = times.set_accessed(to_system_time(header.atime, header.atime_nsec));
+        set_times = true;
+    }
+    if set_times {
+        File::options().write(true).open(path)?.set_times(times)?;
+    }
+
+    #[cfg(unix)]
+    if policy.mode && header.mode != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        // Only rwx bits: a peer must not be able to plant setuid files.
+        let mode = header.mode & 0o777;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Opens a file for reading without updating its access time where the
+/// platform allows it, so internal reads (chunk indexing) do not overwrite
+/// a preserved atime.
+pub fn open_untouched(path: &Path) -> io::Result<File> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        match File::options()
+            .read(true)
+            .custom_flags(libc::O_NOATIME)
+            .open(path)
+        {
+            // O_NOATIME is only allowed for the file's owner.
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            result => return result,
+        }
+    }
+    File::open(path)
+}
+
+fn to_system_time(secs: i64, nanos: u32) -> SystemTime {
+    UNIX_EPOCH + Duration::new(secs as u64, nanos.min(999_999_999))
+}
+
+#[cfg(unix)]
+mod xattrs {
+    use super::*;
+    use crate::filerpc::ExtendedAttribute;
+
+    /// Namespaces holding security labels and ACLs, which only make sense
+    /// on the machine that set them.
+    const SKIPPED_NAMESPACES: [&str; 3] = ["security.", "system.", "trusted."];
+
+    fn is_portable(name: &str) -> bool {
+        !SKIPPED_NAMESPACES.iter().any(|ns| name.starts_with(ns))
+    }
+
+    pub(super) fn read(path: &Path) -> Vec<ExtendedAttribute> {
+        let names = match xattr::list(path) {
+            Ok(names) => names,
+            Err(e) => {
+                // Filesystems without xattr support simply have none.
+                if e.kind() != io::ErrorKind::Unsupported {
+                    warn!(
+                        "Cannot list extended attributes of {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                return Vec::new();
+            }
+        };
+        let mut total = 0;
+        let mut attrs = Vec::new();
+        for name in names {
+            let Some(name) = name.to_str().filter(|n| is_portable(n)) else {
+                continue;
+            };
+            let Ok(Some(value)) = xattr::get(path, name) else {
+                continue;
+            };
+            total += name.len() + value.len();
+            if total > MAX_XATTR_BYTES {
+                warn!(
+                    "Extended attributes of {} exceed {} bytes, not sending them",
+                    path.display(),
+                    MAX_XATTR_BYTES
+                );
+                return Vec::new();
+            }
+            attrs.push(ExtendedAttribute {
+                name: name.to_string(),
+                value,
+            });
+        }
+        attrs
+    }
+
+    /// Sets each attribute; one that cannot be set does not stop the others.
+    pub(super) fn write(path: &Path, attrs: &[ExtendedAttribute]) {
+        for attr in attrs.iter().filter(|a| is_portable(&a.name)) {
+            if let Err(e) = xattr::set(path, &attr.name, &attr.value) {
+                warn!(
+                    "Cannot set extended attribute {} on {}: {}",
+                    attr.name,
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod xattrs {
+    use super::*;
+    use crate::filerpc::ExtendedAttribute;
+
+    pub(super) fn read(_path: &Path) -> Vec<ExtendedAttribute> {
+        Vec::new()
+    }
+
+    pub(super) fn write(path: &Path, attrs: &[ExtendedAttribute]) {
+        warn!(
+            "Extended attributes are not supported here, {} of {} dropped",
+            attrs.len(),
+            path.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<PreservePolicy, Error> {
+        s.parse()
+    }
+
+    #[test]
+    fn policies_parse_from_lists() {
+        assert_eq!(parse("all").unwrap(), PreservePolicy::ALL);
+        assert_eq!(parse("none").unwrap(), PreservePolicy::NONE);
+        assert_eq!(parse("").unwrap(), PreservePolicy::NONE);
+        assert_eq!(
+            parse(" mtime , mode,").unwrap(),
+            PreservePolicy {
+                mtime: true,
+                mode: true,
+                ..PreservePolicy::NONE
+            }
+        );
+        // Later items build on earlier ones.
+        assert_eq!(
+            parse("none,xattrs").unwrap(),
+            PreservePolicy {
+                xattrs: true,
+                ..PreservePolicy::NONE
+            }
+        );
+        assert_eq!(parse("atime,all").unwrap(), PreservePolicy::ALL);
+        for invalid in ["size", "mtime,owner", "MTIME", "all;none"] {
+            assert!(
+                matches!(parse(invalid), Err(Error::InvalidArgument(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    /// A file with a known mtime and atime, described as a sender would.
+    fn described(dir: &Path) -> UploadHeader {
+        let source = dir.join("source");
+        std::fs::write(&source, b"data").unwrap();
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_times(
+                FileTimes::new()
+                    .set_modified(to_system_time(1_600_000_000, 123_456_789))
+                    .set_accessed(to_system_time(1_500_000_000, 0)),
+            )
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        let mut header = UploadHeader::default();
+        let metadata = std::fs::metadata(&source).unwrap();
+        describe(&source, &metadata, PreservePolicy::NONE, &mut header);
+        header
+    }
+
+    #[test]
+    fn times_and_mode_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let header = described(dir.path());
+        assert_eq!(
+            (header.mtime, header.mtime_nsec),
+            (1_600_000_000, 123_456_789)
+        );
+        assert_eq!(header.atime, 1_500_000_000);
+
+        let received = dir.path().join("received");
+        std::fs::write(&received, b"data").unwrap();
+        apply(&received, &header, PreservePolicy::ALL).unwrap();
+        let metadata = std::fs::metadata(&received).unwrap();
+        assert_eq!(unix_time(metadata.modified()), (1_600_000_000, 123_456_789));
+        assert_eq!(unix_time(metadata.accessed()).0, 1_500_000_000);
+        #[cfg(unix)]
+        assert_eq!(unix_mode(&metadata) & 0o7777, 0o640);
+    }
+
+    #[test]
+    fn policy_limits_what_is_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let header = described(dir.path());
+        let received = dir.path().join("received");
+        std::fs::write(&received, b"data").unwrap();
+        let before = std::fs::metadata(&received).unwrap();
+
+        apply(&received, &header, PreservePolicy::NONE).unwrap();
+        let after = std::fs::metadata(&received).unwrap();
+        assert_eq!(mtime_secs(&after), mtime_secs(&before));
+        assert_eq!(unix_mode(&after), unix_mode(&before));
+
+        // The default restores mtime and mode but not atime.
+        apply(&received, &header, PreservePolicy::default()).unwrap();
+        let after = std::fs::metadata(&received).unwrap();
+        assert_eq!(mtime_secs(&after), 1_600_000_000);
+        assert_ne!(unix_time(after.accessed()).0, 1_500_000_000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_mode_bits_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let received = dir.path().join("received");
+        std::fs::write(&received, b"#!/bin/sh\n").unwrap();
+        let header = UploadHeader {
+            mode: 0o104_755,
+            ..Default::default()
+        };
+        apply(&received, &header, PreservePolicy::ALL).unwrap();
+        let metadata = std::fs::metadata(&received).unwrap();
+        assert_eq!(unix_mode(&metadata) & 0o7777, 0o755);
+    }
+}
@@ -0,0 +1,353 @@
+// rustsend-core/src/hot_folder.rs
+
+//! Hot folders: local directories whose new or changed files are sent to a
+//! peer automatically. Holds the persisted configuration and a debounced
+//! watcher that reports a file once it has been fully written.
+
+use log::{debug, warn};
+use notify::event::{AccessKind, AccessMode, EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+
+use crate::client::{ConflictMode, UploadMode};
+use crate::error::{Error, Result};
+use crate::protocol;
+use crate::record_store::{RecordStore, StoredRecord};
+
+/// How long a file must go without events, and then keep the same size and
+/// mtime for another such period, before it counts as fully written.
+pub const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// How often pending files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// --- Configuration ---
+
+/// A watched directory and where its files go.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotFolder {
+    /// Stable identifier; generated on first save when empty.
+    pub id: String,
+    /// Local directory, relative to the home directory like other local
+    /// paths coming from the GUI.
+    pub local_dir: String,
+    /// Connection profile of the peer to send to.
+    pub profile_id: String,
+    /// Remote directory the files are uploaded into.
+    pub target_dir: String,
+    /// Also watch subdirectories; their structure is kept under `target_dir`.
+    pub recursive: bool,
+    pub conflict_policy: ConflictMode,
+    pub mode: UploadMode,
+    /// Disabled folders are kept but not watched.
+    pub enabled: bool,
+}
+
+impl StoredRecord for HotFolder {
+    const LIST: &'static str = "folders";
+    const KIND: &'static str = "hot folder";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+}
+
+/// All configured hot folders, keyed by ID. Every mutation is written back to disk.
+#[derive(Debug)]
+pub struct HotFolderStore(RecordStore<HotFolder>);
+
+impl HotFolderStore {
+    /// `<config dir>/rustsend/hot_folders.json`
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("rustsend")
+            .join("hot_folders.json")
+    }
+
+    /// An empty store that will be written to `path` on the first change.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        HotFolderStore(RecordStore::new(path))
+    }
+
+    /// Loads the store from `path`; a missing file yields an empty store.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        RecordStore::load(path).map(HotFolderStore)
+    }
+
+    pub fn list(&self) -> Vec<HotFolder> {
+        self.0.list()
+    }
+
+    pub fn get(&self, id: &str) -> Result<&HotFolder> {
+        self.0.get(id)
+    }
+
+    /// Inserts or replaces a hot folder and returns it with its final ID.
+    pub fn upsert(&mut self, folder: HotFolder) -> Result<HotFolder> {
+        if folder.local_dir.trim().is_empty() {
+            return Err(Error::InvalidArgument(
+                "hot folder directory cannot be empty".to_string(),
+            ));
+        }
+        if folder.profile_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "hot folder needs a connection profile".to_string(),
+            ));
+        }
+        self.0.upsert(folder)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<HotFolder> {
+        self.0.remove(id)
+    }
+}
+
+// --- Debounced Watcher ---
+
+/// Watches a directory and yields each new or changed file once it has been
+/// fully written. Dropping it stops watching.
+pub struct FolderWatcher {
+    _watcher: RecommendedWatcher,
+    ready: mpsc::Receiver<PathBuf>,
+}
+
+/// A file with recent events, waiting to settle.
+struct Pending {
+    last_event: Instant,
+    /// Size and mtime at the previous check.
+    seen: Option<(u64, SystemTime)>,
+}
+
+impl FolderWatcher {
+    /// Starts watching `dir` (inotify on Linux). Must be called inside a Tokio runtime.
+    pub fn start(dir: &Path, recursive: bool) -> Result<Self> {
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = raw_tx.send(event);
+        })
+        .map_err(|e| watch_error(dir, e))?;
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(dir, mode).map_err(|e| watch_error(dir, e))?;
+
+        let (ready_tx, ready) = mpsc::channel(64);
+        tokio::spawn(debounce(raw_rx, ready_tx));
+        Ok(FolderWatcher {
+            _watcher: watcher,
+            ready,
+        })
+    }
+
+    /// The next fully written file; `None` once the watch has failed.
+    pub async fn next(&mut self) -> Option<PathBuf> {
+        self.ready.recv().await
+    }
+}
+
+/// Remote directory for `file` found under the watched `root`: its
+/// subdirectory is kept below `target_dir`.
+pub fn target_dir_for(root: &Path, target_dir: &str, file: &Path) -> String {
+    let subdir = file
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map(|rel| {
+            rel.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default();
+    let target_dir = target_dir.trim_end_matches('/');
+    if subdir.is_empty() {
+        target_dir.to_string()
+    } else {
+        format!("{}/{}", target_dir, subdir)
+    }
+}
+
+fn watch_error(dir: &Path, e: notify::Error) -> Error {
+    match e.kind {
+        notify::ErrorKind::Io(source) => Error::io(dir, source),
+        notify::ErrorKind::PathNotFound => Error::NotFound(dir.display().to_string()),
+        _ => Error::InvalidArgument(format!("cannot watch {}: {}", dir.display(), e)),
+    }
+}
+
+/// Turns raw watcher events into settled files. Ends when the watcher is
+/// dropped or nobody reads the ready files any more.
+async fn debounce(
+    mut raw: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    ready: mpsc::Sender<PathBuf>,
+) {
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            event = raw.recv() => match event {
+                Some(Ok(event)) if is_write(&event.kind) => {
+                    for path in event.paths {
+                        if !is_ignored(&path) {
+                            let now = Instant::now();
+                            pending
+                                .entry(path)
+                                .and_modify(|p| p.last_event = now)
+                                .or_insert(Pending { last_event: now, seen: None });
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => warn!("Folder watch error: {}", e),
+                None => break,
+            },
+            _ = poll.tick(), if !pending.is_empty() => {
+                let mut settled = Vec::new();
+                pending.retain(|path, p| {
+                    if p.last_event.elapsed() < SETTLE_TIME {
+                        return true;
+                    }
+                    let Ok(metadata) = std::fs::metadata(path) else {
+                        // Deleted or renamed away before it settled.
+                        return false;
+                    };
+                    if !metadata.is_file() {
+                        return false;
+                    }
+                    let now = (metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+                    if p.seen == Some(now) {
+                        settled.push(path.clone());
+                        return false;
+                    }
+                    p.seen = Some(now);
+                    p.last_event = Instant::now();
+                    true
+                });
+                for path in settled {
+                    debug!("Hot folder file ready: {}", path.display());
+                    if ready.send(path).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Events that may leave a file with new content; reads and metadata-only
+/// changes are ignored, so sending a file does not trigger it again.
+fn is_write(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        _ => false,
+    }
+}
+
+/// Hidden files and the partial files of browsers, editors and RustSend itself.
+fn is_ignored(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return true;
+    };
+    const PARTIAL: [&str; 6] = [
+        ".part",
+        ".partial",
+        ".crdownload",
+        ".tmp",
+        ".swp",
+        protocol::PARTIAL_SUFFIX,
+    ];
+    name.starts_with('.') || name.ends_with('~') || PARTIAL.iter().any(|ext| name.ends_with(ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::CreateKind;
+
+    #[test]
+    fn temp_and_hidden_files_are_ignored() {
+        for name in [
+            ".hidden",
+            ".report.pdf.1234.rsend-part",
+            "movie.mkv.part",
+            "movie.mkv.partial",
+            "setup.exe.crdownload",
+            "build.tmp",
+            "notes.txt.swp",
+            "notes.txt~",
+        ] {
+            assert!(is_ignored(&Path::new("/in").join(name)), "{name}");
+        }
+        for name in ["report.pdf", "part", "tmp.txt", "a.partition"] {
+            assert!(!is_ignored(&Path::new("/in").join(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn subdirectories_are_kept_below_the_target() {
+        let root = Path::new("/home/me/Outbox");
+        assert_eq!(
+            target_dir_for(root, "/inbox", &root.join("a.txt")),
+            "/inbox"
+        );
+        assert_eq!(
+            target_dir_for(root, "/inbox/", &root.join("photos/2024/a.jpg")),
+            "/inbox/photos/2024"
+        );
+        assert_eq!(target_dir_for(root, "", &root.join("docs/a.txt")), "/docs");
+        // Outside the watched root (should not happen): the target itself.
+        assert_eq!(
+            target_dir_for(root, "/inbox", Path::new("/tmp/a.txt")),
+            "/inbox"
+        );
+    }
+
+    #[tokio::test]
+    async fn files_are_reported_once_settled() {
+        let dir = tempfile::tempdir().unwrap();
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        let (ready_tx, mut ready) = mpsc::channel(8);
+        tokio::spawn(debounce(raw_rx, ready_tx));
+        let created = |path: PathBuf| {
+            Ok(notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path))
+        };
+
+        let file = dir.path().join("a.txt");
+        let ignored = dir.path().join(".a.txt.swp");
+        std::fs::write(&file, b"one").unwrap();
+        std::fs::write(&ignored, b"x").unwrap();
+        let start = Instant::now();
+        raw_tx.send(created(ignored)).unwrap();
+        raw_tx.send(created(file.clone())).unwrap();
+        // Further writes restart the wait and are reported together.
+        tokio::time::sleep(SETTLE_TIME / 2).await;
+        std::fs::write(&file, b"one two").unwrap();
+        raw_tx.send(created(file.clone())).unwrap();
+
+        let reported = tokio::time::timeout(SETTLE_TIME * 5, ready.recv())
+            .await
+            .unwrap();
+        assert_eq!(reported, Some(file));
+        assert!(start.elapsed() >= SETTLE_TIME * 2);
+        assert!(
+            tokio::time::timeout(SETTLE_TIME * 2, ready.recv())
+                .await
+                .is_err(),
+            "reported twice, or an ignored file was reported"
+        );
+    }
+}
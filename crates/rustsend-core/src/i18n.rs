@@ -0,0 +1,165 @@
+// rustsend-core/src/i18n.rs
+
+//! Message catalog for user-facing backend strings.
+//!
+//! The locale is process-wide and only affects rendered text; error codes and
+//! protocol values never change with it.
+
+use crate::error::Error;
+use crate::filerpc::{UploadAction, UploadStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "en")]
+    En,
+}
+
+impl Locale {
+    /// BCP 47 tag, as used by the frontend.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::En => "en",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Locale::En,
+            _ => Locale::ZhCn,
+        }
+    }
+}
+
+impl std::str::FromStr for Locale {
+    type Err = Error;
+
+    /// Accepts the exact tags plus common variants ("zh", "zh_CN", "en-US", ...).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lang = s.split(['-', '_', '.']).next().unwrap_or("");
+        match lang.to_ascii_lowercase().as_str() {
+            "zh" => Ok(Locale::ZhCn),
+            "en" => Ok(Locale::En),
+            _ => Err(Error::InvalidArgument(format!("unsupported locale: {}", s))),
+        }
+    }
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn current() -> Locale {
+    Locale::from_u8(CURRENT.load(Ordering::Relaxed))
+}
+
+pub fn set_locale(locale: Locale) {
+    CURRENT.store(locale as u8, Ordering::Relaxed);
+}
+
+// --- Catalog ---
+
+/// A user-facing message, rendered in the current locale via `Display`.
+#[derive(Debug)]
+pub enum Message<'a> {
+    Connected { url: &'a str, device: &'a str },
+    Upload(&'a UploadStatus),
+    Downloaded { bytes: u64 },
+    Deleted { path: &'a str },
+    Linked { path: &'a str, target: &'a str },
+    ServerFailed { reason: &'a str },
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let locale = current();
+        match (self, locale) {
+            (Message::Connected { url, device: "" }, Locale::ZhCn) => {
+                write!(f, "连接成功: {}", url)
+            }
+            (Message::Connected { url, device }, Locale::ZhCn) => {
+                write!(f, "连接成功: {} ({})", url, device)
+            }
+            (Message::Connected { url, device: "" }, Locale::En) => {
+                write!(f, "Connected to {}", url)
+            }
+            (Message::Connected { url, device }, Locale::En) => {
+                write!(f, "Connected to {} ({})", url, device)
+            }
+
+            (Message::Upload(status), _) => upload_status(f, status, locale),
+
+            (Message::Downloaded { bytes }, Locale::ZhCn) => {
+                write!(f, "下载完成: {} 字节", bytes)
+            }
+            (Message::Downloaded { bytes }, Locale::En) => write!(f, "Downloaded {} bytes", bytes),
+            (Message::Deleted { path }, Locale::ZhCn) => write!(f, "已删除: {}", path),
+            (Message::Deleted { path }, Locale::En) => write!(f, "Deleted {}", path),
+            (Message::Linked { path, target }, Locale::ZhCn) => {
+                write!(f, "已创建链接: {} -> {}", path, target)
+            }
+            (Message::Linked { path, target }, Locale::En) => {
+                write!(f, "Linked {} -> {}", path, target)
+            }
+
+            (Message::ServerFailed { reason }, Locale::ZhCn) => {
+                write!(f, "后台服务启动失败: {}", reason)
+            }
+            (Message::ServerFailed { reason }, Locale::En) => {
+                write!(f, "Background server failed: {}", reason)
+            }
+        }
+    }
+}
+
+/// Renders an upload result from its action; the server's own text is only
+/// used for legacy servers that do not report one.
+fn upload_status(f: &mut fmt::Formatter<'_>, status: &UploadStatus, locale: Locale) -> fmt::Result {
+    let name = &status.final_name;
+    match (status.action(), locale) {
+        (UploadAction::Created, Locale::ZhCn) => write!(f, "✅ 上传成功: {}", name),
+        (UploadAction::Created, Locale::En) => write!(f, "✅ Uploaded {}", name),
+        (UploadAction::Overwritten, Locale::ZhCn) => write!(f, "✅ 上传成功 (已覆盖): {}", name),
+        (UploadAction::Overwritten, Locale::En) => write!(f, "✅ Uploaded {} (overwritten)", name),
+        (UploadAction::Renamed, Locale::ZhCn) => write!(f, "✅ 上传成功 (已重命名): {}", name),
+        (UploadAction::Renamed, Locale::En) => write!(f, "✅ Uploaded as {}", name),
+        (UploadAction::Skipped, Locale::ZhCn) => write!(f, "⏭ 已跳过: {}", name),
+        (UploadAction::Skipped, Locale::En) => write!(f, "⏭ Skipped {}", name),
+        (UploadAction::Unspecified, Locale::ZhCn) => write!(f, "✅ 上传成功: {}", status.message),
+        (UploadAction::Unspecified, Locale::En) => write!(f, "✅ Uploaded: {}", status.message),
+    }
+}
+
+/// Localized text for an error. Embedded server/OS text stays as received.
+pub fn error_message(error: &Error) -> String {
+    match current() {
+        Locale::En => error.to_string(),
+        Locale::ZhCn => match error {
+            Error::NotConnected => "未连接到服务器".to_string(),
+            Error::PathOutsideSandbox(path) => format!("路径超出共享目录范围: {}", path),
+            Error::NotFound(what) => format!("未找到: {}", what),
+            Error::PermissionDenied(what) => format!("没有权限: {}", what),
+            Error::Conflict(what) => format!("目标已存在: {}", what),
+            Error::Network(reason) => format!("网络错误: {}", reason),
+            Error::Timeout(what) => format!("超时: {}", what),
+            Error::Cancelled(what) => format!("已取消: {}", what),
+            Error::Unsupported {
+                feature,
+                server_version,
+            } => format!(
+                "对端服务器 (v{}) 不支持 \"{}\"，请升级对端应用",
+                server_version, feature
+            ),
+            Error::InvalidArgument(reason) => format!("参数无效: {}", reason),
+            Error::InsufficientSpace(reason) => format!("空间不足: {}", reason),
+            Error::QuotaExceeded(reason) => format!("超出配额: {}", reason),
+            Error::Integrity(reason) => format!("校验失败: {}", reason),
+            Error::Io { path, source } => format!("读写 {} 失败: {}", path, source),
+            Error::Remote { code, message } => format!("服务器错误 ({:?}): {}", code, message),
+        },
+    }
+}
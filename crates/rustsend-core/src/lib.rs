@@ -0,0 +1,38 @@
+// rustsend-core/src/lib.rs
+
+//! Send/receive engine of RustSend: the gRPC protocol, the sandboxed file
+//! server and the client. Has no GUI dependency, so other services can embed
+//! it directly; the Tauri app and the `rustsend` CLI are thin layers on top.
+
+pub mod bisync;
+pub mod checksum;
+pub mod client;
+pub mod compression;
+pub mod connection;
+pub mod dedup;
+pub mod delta;
+pub mod dir_watch;
+pub mod error;
+pub mod file_meta;
+pub mod hot_folder;
+pub mod i18n;
+pub mod listing;
+pub mod profile;
+pub mod protocol;
+pub mod record_store;
+pub mod search;
+pub mod server;
+pub mod server_starter;
+pub mod space;
+pub mod symlink;
+pub mod sync;
+pub mod transfer;
+
+// 引入 gRPC 结构 (由 build.rs 生成，全 crate 只 include 一次)
+pub mod filerpc {
+    tonic::include_proto!("filerpc");
+
+    /// Encoded descriptors of `file_rpc.proto`, served through gRPC reflection.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("filerpc_descriptor");
+}
@@ -0,0 +1,453 @@
+// rustsend-core/src/listing.rs
+
+//! Sorting, filtering and paging of directory listings.
+//!
+//! Local and remote listings share `ListOptions` and `DirLister`, so both
+//! panes of the GUI order and filter entries the same way.
+//!
+//! Huge directories are listed a page at a time. The server reads the names
+//! of the whole directory for every page (it has to, to sort them), but only
+//! looks up the metadata it sorts by and the full entries of the page it
+//! returns. Cursors are positions in the sort order rather than offsets, so
+//! entries added or removed between two pages do not shift the rest.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+use crate::filerpc::{ListDirRequest, ListSort};
+use crate::search::NamePattern;
+use crate::{file_meta, protocol};
+
+/// What a listing is sorted by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Mtime,
+}
+
+/// Sorting and filtering of one listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListOptions {
+    /// Include entries whose name starts with a '.'; off by default, like
+    /// the local listing has always been.
+    pub show_hidden: bool,
+    pub sort: SortKey,
+    pub descending: bool,
+    /// List directories before files, whatever the direction.
+    pub dirs_first: bool,
+    /// Name filter, see `NamePattern`; empty lists everything.
+    pub pattern: String,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions {
+            show_hidden: false,
+            sort: SortKey::Name,
+            descending: false,
+            dirs_first: false,
+            pattern: String::new(),
+        }
+    }
+}
+
+impl ListOptions {
+    pub fn from_request(req: &ListDirRequest) -> Self {
+        ListOptions {
+            show_hidden: !req.hide_hidden,
+            sort: match req.sort() {
+                ListSort::Name => SortKey::Name,
+                ListSort::Size => SortKey::Size,
+                ListSort::Mtime => SortKey::Mtime,
+            },
+            descending: req.descending,
+            dirs_first: req.dirs_first,
+            pattern: req.pattern.clone(),
+        }
+    }
+
+    /// Request for one page of `path`; `cursor` is empty for the first.
+    pub fn to_request(&self, path: String, page_size: u32, cursor: String) -> ListDirRequest {
+        let sort = match self.sort {
+            SortKey::Name => ListSort::Name,
+            SortKey::Size => ListSort::Size,
+            SortKey::Mtime => ListSort::Mtime,
+        };
+        ListDirRequest {
+            path,
+            page_size,
+            cursor,
+            sort: sort as i32,
+            descending: self.descending,
+            hide_hidden: !self.show_hidden,
+            pattern: self.pattern.clone(),
+            dirs_first: self.dirs_first,
+        }
+    }
+}
+
+/// An entry `DirLister::arrange` can filter and sort.
+pub trait Listable {
+    fn name(&self) -> &str;
+    fn is_dir(&self) -> bool;
+    /// Bytes of a file; 0 for anything else.
+    fn size(&self) -> u64;
+    /// Unix seconds.
+    fn mtime(&self) -> i64;
+}
+
+/// A listed name with the values it is sorted by.
+#[derive(Debug, Clone)]
+pub struct SortedName {
+    pub name: String,
+    is_dir: bool,
+    /// Size or mtime, depending on the sort key; 0 when sorting by name.
+    key: i128,
+    folded: String,
+}
+
+impl SortedName {
+    fn new(name: String, is_dir: bool, key: i128) -> Self {
+        let folded = name.to_lowercase();
+        SortedName {
+            name,
+            is_dir,
+            key,
+            folded,
+        }
+    }
+
+    /// Cursor of the page that starts right after this entry.
+    pub fn cursor(&self) -> String {
+        format!("{}/{}/{}", u8::from(self.is_dir), self.key, self.name)
+    }
+
+    /// Names never contain '/', so the first two end the sort values.
+    fn parse_cursor(cursor: &str) -> Option<Self> {
+        let (is_dir, rest) = cursor.split_once('/')?;
+        let (key, name) = rest.split_once('/')?;
+        let is_dir = match is_dir {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        Some(SortedName::new(name.to_string(), is_dir, key.parse().ok()?))
+    }
+}
+
+/// Reads directories according to one set of `ListOptions`.
+#[derive(Debug)]
+pub struct DirLister {
+    options: ListOptions,
+    name: NamePattern,
+}
+
+impl DirLister {
+    pub fn new(options: ListOptions) -> Result<Self, glob::PatternError> {
+        let name = NamePattern::parse(&options.pattern)?;
+        Ok(DirLister { options, name })
+    }
+
+    /// True if an entry called `name` passes the hidden and name filters.
+    /// Temp files of transfers in progress are never listed.
+    pub fn accepts(&self, name: &str) -> bool {
+        (self.options.show_hidden || !name.starts_with('.'))
+            && !protocol::is_partial(name)
+            && self.name.matches(name)
+    }
+
+    /// Names in `dir` that pass the filters, in sort order. Symlinks sort
+    /// like what `follow` returns for them (nothing: an empty file), so a
+    /// caller can keep targets outside its sandbox out of the order. Blocking.
+    pub fn list(
+        &self,
+        dir: &Path,
+        follow: impl Fn(&Path) -> Option<Metadata>,
+    ) -> io::Result<Vec<SortedName>> {
+        let needs_metadata = self.options.dirs_first || self.options.sort != SortKey::Name;
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !self.accepts(&name) {
+                continue;
+            }
+            if !needs_metadata {
+                names.push(SortedName::new(name, false, 0));
+                continue;
+            }
+            // Entries removed since read_dir are left out.
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let metadata = if metadata.is_symlink() {
+                follow(&entry.path())
+            } else {
+                Some(metadata)
+            };
+            let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+            let size = metadata
+                .as_ref()
+                .filter(|m| m.is_file())
+                .map_or(0, |m| m.len());
+            let mtime = metadata.as_ref().map_or(0, file_meta::mtime_secs);
+            names.push(self.sorted_name(name, is_dir, size, mtime));
+        }
+        names.sort_by(|a, b| self.compare(a, b));
+        Ok(names)
+    }
+
+    /// Filters and sorts entries that were already read.
+    pub fn arrange<T: Listable>(&self, entries: impl IntoIterator<Item = T>) -> Vec<T> {
+        let mut keyed: Vec<(SortedName, T)> = entries
+            .into_iter()
+            .filter(|entry| self.accepts(entry.name()))
+            .map(|entry| {
+                let name = entry.name().to_string();
+                let sorted = self.sorted_name(name, entry.is_dir(), entry.size(), entry.mtime());
+                (sorted, entry)
+            })
+            .collect();
+        keyed.sort_by(|a, b| self.compare(&a.0, &b.0));
+        keyed.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    /// Index of the first of `names` (as returned by `list`) after `cursor`;
+    /// None if the cursor is malformed.
+    pub fn position_after(&self, names: &[SortedName], cursor: &str) -> Option<usize> {
+        let cursor = SortedName::parse_cursor(cursor)?;
+        Some(names.partition_point(|name| self.compare(name, &cursor) != Ordering::Greater))
+    }
+
+    fn sorted_name(&self, name: String, is_dir: bool, size: u64, mtime: i64) -> SortedName {
+        let key = match self.options.sort {
+            SortKey::Name => 0,
+            SortKey::Size => i128::from(size),
+            SortKey::Mtime => i128::from(mtime),
+        };
+        SortedName::new(name, is_dir, key)
+    }
+
+    fn compare(&self, a: &SortedName, b: &SortedName) -> Ordering {
+        let dirs = if self.options.dirs_first {
+            b.is_dir.cmp(&a.is_dir)
+        } else {
+            Ordering::Equal
+        };
+        dirs.then_with(|| {
+            let order = a
+                .key
+                .cmp(&b.key)
+                .then_with(|| a.folded.cmp(&b.folded))
+                .then_with(|| a.name.cmp(&b.name));
+            if self.options.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    /// Files with repeated sizes and mtimes, names that differ only in case,
+    /// a hidden file and a few directories.
+    fn populate(dir: &Path) {
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let files = [
+            ("a.txt", 10, 0),
+            ("A.txt", 10, 0),
+            ("b.txt", 0, 5),
+            ("c.log", 30, 5),
+            ("D.bin", 10, 5),
+            ("e", 30, 0),
+            (".hidden", 10, 5),
+            ("f.txt", 0, 9),
+            ("g.txt", 20, 9),
+        ];
+        for (name, size, age) in files {
+            let file = File::create(dir.join(name)).unwrap();
+            file.set_len(size).unwrap();
+            file.set_modified(epoch + Duration::from_secs(age)).unwrap();
+        }
+        for name in ["dir1", "Dir2", "dir3"] {
+            fs::create_dir(dir.join(name)).unwrap();
+        }
+    }
+
+    fn follow(path: &Path) -> Option<Metadata> {
+        fs::metadata(path).ok()
+    }
+
+    fn all_options() -> Vec<ListOptions> {
+        let mut all = Vec::new();
+        for sort in [SortKey::Name, SortKey::Size, SortKey::Mtime] {
+            for descending in [false, true] {
+                for dirs_first in [false, true] {
+                    all.push(ListOptions {
+                        show_hidden: true,
+                        sort,
+                        descending,
+                        dirs_first,
+                        ..ListOptions::default()
+                    });
+                }
+            }
+        }
+        all
+    }
+
+    /// Pages through `dir` the way the server does, re-reading the directory
+    /// for every page.
+    fn paged(lister: &DirLister, dir: &Path, page_size: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut cursor = String::new();
+        loop {
+            let listing = lister.list(dir, follow).unwrap();
+            let start = if cursor.is_empty() {
+                0
+            } else {
+                lister.position_after(&listing, &cursor).unwrap()
+            };
+            let page = &listing[start..(start + page_size).min(listing.len())];
+            let Some(last) = page.last() else {
+                return names;
+            };
+            names.extend(page.iter().map(|entry| entry.name.clone()));
+            cursor = last.cursor();
+        }
+    }
+
+    #[test]
+    fn pages_concatenate_to_the_full_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        populate(dir.path());
+        for options in all_options() {
+            let lister = DirLister::new(options.clone()).unwrap();
+            let full: Vec<String> = lister
+                .list(dir.path(), follow)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect();
+            assert_eq!(full.len(), 12, "{options:?}");
+            for page_size in [1, 2, 5, 12, 50] {
+                assert_eq!(
+                    paged(&lister, dir.path(), page_size),
+                    full,
+                    "{options:?}, pages of {page_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn listing_follows_the_options() {
+        let dir = tempfile::tempdir().unwrap();
+        populate(dir.path());
+        let names = |options: ListOptions| -> Vec<String> {
+            let lister = DirLister::new(options).unwrap();
+            let listing = lister.list(dir.path(), follow).unwrap();
+            listing.into_iter().map(|entry| entry.name).collect()
+        };
+
+        let by_size = names(ListOptions {
+            show_hidden: true,
+            sort: SortKey::Size,
+            dirs_first: true,
+            ..ListOptions::default()
+        });
+        assert_eq!(
+            by_size,
+            [
+                "dir1", "Dir2", "dir3", "b.txt", "f.txt", ".hidden", "A.txt", "a.txt", "D.bin",
+                "g.txt", "c.log", "e"
+            ]
+        );
+
+        // Directories stay first when the order is reversed.
+        let by_mtime_desc = names(ListOptions {
+            sort: SortKey::Mtime,
+            descending: true,
+            dirs_first: true,
+            show_hidden: false,
+            ..ListOptions::default()
+        });
+        assert_eq!(&by_mtime_desc[..3], ["dir3", "Dir2", "dir1"]);
+        assert_eq!(&by_mtime_desc[3..5], ["g.txt", "f.txt"]);
+        assert!(!by_mtime_desc.contains(&".hidden".to_string()));
+
+        let filtered = names(ListOptions {
+            pattern: "*.TXT".to_string(),
+            ..ListOptions::default()
+        });
+        assert_eq!(filtered, ["A.txt", "a.txt", "b.txt", "f.txt", "g.txt"]);
+
+        // Hidden files are only listed on request.
+        let default = names(ListOptions::default());
+        assert_eq!(default.len(), 11);
+        assert!(!default.contains(&".hidden".to_string()));
+    }
+
+    #[test]
+    fn stale_cursor_resumes_after_its_position() {
+        let dir = tempfile::tempdir().unwrap();
+        populate(dir.path());
+        for options in all_options() {
+            let lister = DirLister::new(options.clone()).unwrap();
+            let before = lister.list(dir.path(), follow).unwrap();
+            let removed = before.iter().position(|e| e.name == "D.bin").unwrap();
+            let cursor = before[removed].cursor();
+            fs::remove_file(dir.path().join("D.bin")).unwrap();
+
+            let after = lister.list(dir.path(), follow).unwrap();
+            let start = lister.position_after(&after, &cursor).unwrap();
+            let rest: Vec<&str> = after[start..].iter().map(|e| e.name.as_str()).collect();
+            let expected: Vec<&str> = before[removed + 1..]
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect();
+            assert_eq!(rest, expected, "{options:?}");
+
+            let file = File::create(dir.path().join("D.bin")).unwrap();
+            file.set_len(10).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_005))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn transfer_temp_files_are_never_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        populate(dir.path());
+        let temp = format!(".a.txt.1234{}", protocol::PARTIAL_SUFFIX);
+        File::create(dir.path().join(&temp)).unwrap();
+        for options in all_options() {
+            let lister = DirLister::new(options).unwrap();
+            let listing = lister.list(dir.path(), follow).unwrap();
+            assert_eq!(listing.len(), 12);
+            assert!(listing.iter().all(|entry| entry.name != temp));
+            assert!(!lister.accepts(&temp));
+        }
+    }
+
+    #[test]
+    fn malformed_cursor_is_rejected() {
+        let lister = DirLister::new(ListOptions::default()).unwrap();
+        for cursor in ["", "x", "2/0/a", "0/ten/a", "1/0"] {
+            assert!(lister.position_after(&[], cursor).is_none(), "{cursor}");
+        }
+        assert_eq!(lister.position_after(&[], "0/0/a"), Some(0));
+    }
+}
@@ -0,0 +1,121 @@
+// rustsend-core/src/profile.rs
+
+//! Saved connection profiles, persisted as JSON in the user's config directory.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::file_meta::PreservePolicy;
+use crate::record_store::{RecordStore, StoredRecord};
+
+/// A named server the user can connect to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Stable identifier; generated on first save when empty.
+    pub id: String,
+    /// Display name, e.g. "build server".
+    pub name: String,
+    /// Server address, with or without the `http://` scheme.
+    pub address: String,
+    /// Sent as `authorization: Bearer <token>` on every request. Only servers
+    /// started with a token check it; others accept any request.
+    pub token: Option<String>,
+    /// Overrides the default connect timeout.
+    pub connect_timeout_secs: Option<u64>,
+    /// Overrides the default request timeout; 0 disables it.
+    pub request_timeout_secs: Option<u64>,
+    /// Overrides which file metadata is sent and restored.
+    pub preserve: Option<PreservePolicy>,
+    pub last_remote_path: Option<String>,
+    pub last_local_path: Option<String>,
+}
+
+impl StoredRecord for Profile {
+    const LIST: &'static str = "profiles";
+    const KIND: &'static str = "profile";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+}
+
+/// All saved profiles, keyed by ID. Every mutation is written back to disk.
+#[derive(Debug)]
+pub struct ProfileStore(RecordStore<Profile>);
+
+impl ProfileStore {
+    /// `<config dir>/rustsend/profiles.json`
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("rustsend")
+            .join("profiles.json")
+    }
+
+    /// An empty store that will be written to `path` on the first change.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ProfileStore(RecordStore::new(path))
+    }
+
+    /// Loads the store from `path`; a missing file yields an empty store.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        RecordStore::load(path).map(ProfileStore)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    pub fn list(&self) -> Vec<Profile> {
+        self.0.list()
+    }
+
+    pub fn get(&self, id: &str) -> Result<&Profile> {
+        self.0.get(id)
+    }
+
+    /// Inserts or replaces a profile and returns it with its final ID.
+    pub fn upsert(&mut self, mut profile: Profile) -> Result<Profile> {
+        if profile.address.trim().is_empty() {
+            return Err(Error::InvalidArgument(
+                "profile address cannot be empty".to_string(),
+            ));
+        }
+        if profile.name.is_empty() {
+            profile.name = profile.address.clone();
+        }
+        self.0.upsert(profile)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<Profile> {
+        self.0.remove(id)
+    }
+
+    /// Records the last browsed paths; `None` leaves a path unchanged.
+    pub fn update_paths(
+        &mut self,
+        id: &str,
+        remote: Option<String>,
+        local: Option<String>,
+    ) -> Result<()> {
+        let profile = self.0.get_mut(id)?;
+        let changed = (remote.is_some() && remote != profile.last_remote_path)
+            || (local.is_some() && local != profile.last_local_path);
+        if !changed {
+            return Ok(());
+        }
+        if remote.is_some() {
+            profile.last_remote_path = remote;
+        }
+        if local.is_some() {
+            profile.last_local_path = local;
+        }
+        self.0.save()
+    }
+}
@@ -0,0 +1,91 @@
+// src/protocol.rs
+
+//! Protocol version and capability names shared by the server and the client.
+
+/// Wire protocol version. 1 = `FileChunk` framing only, 2 = header/data/trailer.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Application version reported to peers.
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Largest `data` frame the server accepts in a single upload message.
+pub const MAX_CHUNK_SIZE: u64 = 1024 * 1024; // 1 MB
+
+/// Most hashes accepted in a single `QueryChunks` request.
+pub const MAX_CHUNK_QUERY: usize = 4096;
+
+/// Most matches a single `Search` returns.
+pub const MAX_SEARCH_RESULTS: u32 = 10_000;
+
+/// Most entries in one page of a listing.
+pub const MAX_LIST_PAGE: u32 = 5_000;
+
+/// Page size of `ListDirStream` when the request leaves it at 0.
+pub const DEFAULT_LIST_PAGE: u32 = 1_000;
+
+/// Suffix of the hidden temp files that uploads, downloads and symlink
+/// replacements are written to before being renamed into place. Listings,
+/// searches, watches and usage totals leave them out.
+pub const PARTIAL_SUFFIX: &str = ".rsend-part";
+
+/// True if `name` (or a path) is one of those temp files.
+pub fn is_partial(name: &str) -> bool {
+    name.ends_with(PARTIAL_SUFFIX)
+}
+
+// --- Feature names advertised in `ServerInfo.features` ---
+pub const FEATURE_UPLOAD_V2: &str = "upload_v2";
+pub const FEATURE_CONFLICT_POLICY: &str = "conflict_policy";
+/// SHA-256 verification of uploads and the `HashFile` RPC.
+pub const FEATURE_HASHING: &str = "hashing";
+pub const FEATURE_DOWNLOAD: &str = "download";
+pub const FEATURE_COMPRESSION_ZSTD: &str = "compression_zstd";
+pub const FEATURE_COMPRESSION_GZIP: &str = "compression_gzip";
+pub const FEATURE_DELTA_UPLOAD: &str = "delta_upload";
+pub const FEATURE_CHUNK_DEDUP: &str = "chunk_dedup";
+/// Alias of `FEATURE_HASHING`, still advertised for clients that look for
+/// it before calling `HashFile`. Clients check `FEATURE_HASHING`.
+pub const FEATURE_HASHING_ALIAS: &str = "file_hash";
+pub const FEATURE_DELETE: &str = "delete";
+pub const FEATURE_WATCH_DIR: &str = "watch_dir";
+pub const FEATURE_PRESERVE_METADATA: &str = "preserve_metadata";
+pub const FEATURE_SYMLINKS: &str = "symlinks";
+pub const FEATURE_DISK_USAGE: &str = "disk_usage";
+pub const FEATURE_SEARCH: &str = "search";
+pub const FEATURE_LIST_PAGING: &str = "list_paging";
+
+/// Features implemented by this build of the server.
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    FEATURE_UPLOAD_V2,
+    FEATURE_CONFLICT_POLICY,
+    FEATURE_HASHING,
+    FEATURE_DOWNLOAD,
+    FEATURE_COMPRESSION_ZSTD,
+    FEATURE_COMPRESSION_GZIP,
+    FEATURE_DELTA_UPLOAD,
+    FEATURE_CHUNK_DEDUP,
+    FEATURE_HASHING_ALIAS,
+    FEATURE_DELETE,
+    FEATURE_WATCH_DIR,
+    FEATURE_PRESERVE_METADATA,
+    FEATURE_SYMLINKS,
+    FEATURE_DISK_USAGE,
+    FEATURE_SEARCH,
+    FEATURE_LIST_PAGING,
+];
+
+/// Name of this machine as shown to peers.
+pub fn device_name() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+// --- Machine-readable error reasons ---
+
+/// Status metadata key carrying a reason more specific than the gRPC code.
+pub const ERROR_REASON_METADATA: &str = "rustsend-error-reason";
+/// The requested path resolves outside the server's sandbox root.
+pub const REASON_PATH_OUTSIDE_SANDBOX: &str = "path_outside_sandbox";
+/// RESOURCE_EXHAUSTED: the target file system lacks room for the upload.
+pub const REASON_INSUFFICIENT_SPACE: &str = "insufficient_space";
+/// RESOURCE_EXHAUSTED: the upload would exceed the share's quota.
+pub const REASON_QUOTA_EXCEEDED: &str = "quota_exceeded";
@@ -0,0 +1,213 @@
+// rustsend-core/src/record_store.rs
+
+//! Small settings files holding a list of records keyed by ID, such as the
+//! connection profiles and hot folders. The file is a JSON object with one
+//! array, e.g. `{"profiles": [...]}`, and is rewritten on every change.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+
+/// A record kept in a `RecordStore`.
+pub trait StoredRecord: Clone + Serialize + DeserializeOwned {
+    /// Name of the array in the file, e.g. "profiles".
+    const LIST: &'static str;
+    /// What one record is called in errors, e.g. "profile".
+    const KIND: &'static str;
+
+    fn id(&self) -> &str;
+    fn set_id(&mut self, id: String);
+}
+
+/// All records of one file, keyed by ID.
+#[derive(Debug)]
+pub struct RecordStore<T> {
+    path: PathBuf,
+    records: BTreeMap<String, T>,
+}
+
+impl<T: StoredRecord> RecordStore<T> {
+    /// An empty store that will be written to `path` on the first change.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        RecordStore {
+            path: path.into(),
+            records: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the store from `path`; a missing file yields an empty store.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let corrupt = |e: serde_json::Error| {
+            Error::InvalidArgument(format!(
+                "corrupt {} file {}: {}",
+                T::KIND,
+                path.display(),
+                e
+            ))
+        };
+        let list: Vec<T> = match std::fs::read(&path) {
+            Ok(bytes) => {
+                let mut file: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_slice(&bytes).map_err(corrupt)?;
+                match file.remove(T::LIST) {
+                    Some(list) => serde_json::from_value(list).map_err(corrupt)?,
+                    None => Vec::new(),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+        let records = list
+            .into_iter()
+            .map(|record| (record.id().to_string(), record))
+            .collect();
+        Ok(RecordStore { path, records })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn list(&self) -> Vec<T> {
+        self.records.values().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Result<&T> {
+        self.records.get(id).ok_or_else(|| not_found::<T>(id))
+    }
+
+    /// Changes a record in place; call `save` afterwards.
+    pub fn get_mut(&mut self, id: &str) -> Result<&mut T> {
+        self.records.get_mut(id).ok_or_else(|| not_found::<T>(id))
+    }
+
+    /// Inserts or replaces a record, generating its ID when empty, and
+    /// returns it as stored.
+    pub fn upsert(&mut self, mut record: T) -> Result<T> {
+        if record.id().is_empty() {
+            record.set_id(Uuid::new_v4().to_string());
+        }
+        self.records.insert(record.id().to_string(), record.clone());
+        self.save()?;
+        Ok(record)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<T> {
+        let removed = self.records.remove(id).ok_or_else(|| not_found::<T>(id))?;
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Writes to a temp file, readable only by the user since records may
+    /// hold access tokens, and renames it over the old one.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        let file = serde_json::json!({ T::LIST: self.list() });
+        let json = serde_json::to_vec_pretty(&file).map_err(|e| {
+            Error::InvalidArgument(format!("cannot serialize {} list: {}", T::KIND, e))
+        })?;
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, json).map_err(|e| Error::io(&temp, e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| Error::io(&temp, e))?;
+        }
+        std::fs::rename(&temp, &self.path).map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+fn not_found<T: StoredRecord>(id: &str) -> Error {
+    Error::NotFound(format!("{} {}", T::KIND, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        id: String,
+        text: String,
+    }
+
+    impl StoredRecord for Note {
+        const LIST: &'static str = "notes";
+        const KIND: &'static str = "note";
+
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn set_id(&mut self, id: String) {
+            self.id = id;
+        }
+    }
+
+    fn note(id: &str, text: &str) -> Note {
+        Note {
+            id: id.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn changes_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("notes.json");
+        let mut store = RecordStore::<Note>::load(&path).unwrap();
+        assert!(store.list().is_empty());
+
+        let added = store.upsert(note("", "first")).unwrap();
+        assert!(!added.id.is_empty());
+        store.upsert(note("b", "second")).unwrap();
+        store.upsert(note("b", "second, edited")).unwrap();
+
+        let mut reloaded = RecordStore::<Note>::load(&path).unwrap();
+        assert_eq!(reloaded.get(&added.id).unwrap(), &added);
+        assert_eq!(reloaded.get("b").unwrap().text, "second, edited");
+        assert_eq!(reloaded.list().len(), 2);
+
+        reloaded.remove("b").unwrap();
+        assert!(matches!(reloaded.remove("b"), Err(Error::NotFound(_))));
+        assert_eq!(RecordStore::<Note>::load(&path).unwrap().list(), [added]);
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(json["notes"].is_array());
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        RecordStore::<Note>::new(&path)
+            .upsert(note("a", "secret"))
+            .unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn corrupt_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        std::fs::write(&path, b"{\"notes\": 3}").unwrap();
+        assert!(matches!(
+            RecordStore::<Note>::load(&path),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+// rustsend-core/src/search.rs
+
+//! Name patterns and the filters of the `Search` RPC.
+
+use glob::MatchOptions;
+use std::fs::Metadata;
+
+use crate::file_meta;
+use crate::filerpc::{EntryType, SearchRequest};
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Case-insensitive match on a file name: a glob over the whole name when
+/// the pattern contains `*`, `?` or `[`, otherwise a substring.
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Any,
+    Glob(glob::Pattern),
+    Substring(String),
+}
+
+impl NamePattern {
+    pub fn parse(pattern: &str) -> Result<Self, glob::PatternError> {
+        if pattern.is_empty() {
+            Ok(NamePattern::Any)
+        } else if pattern.contains(['*', '?', '[']) {
+            glob::Pattern::new(pattern).map(NamePattern::Glob)
+        } else {
+            Ok(NamePattern::Substring(pattern.to_lowercase()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Any => true,
+            NamePattern::Glob(glob) => glob.matches_with(name, GLOB_OPTIONS),
+            NamePattern::Substring(needle) => name.to_lowercase().contains(needle),
+        }
+    }
+}
+
+/// The conditions of one `SearchRequest`.
+#[derive(Debug)]
+pub struct SearchFilter {
+    name: NamePattern,
+    entry_type: EntryType,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+}
+
+impl SearchFilter {
+    pub fn new(req: &SearchRequest) -> Result<Self, glob::PatternError> {
+        let positive = |v: i64| (v > 0).then_some(v);
+        Ok(SearchFilter {
+            name: NamePattern::parse(&req.pattern)?,
+            entry_type: req.entry_type(),
+            min_size: (req.min_size > 0).then_some(req.min_size),
+            max_size: (req.max_size > 0).then_some(req.max_size),
+            modified_after: positive(req.modified_after),
+            modified_before: positive(req.modified_before),
+        })
+    }
+
+    /// `metadata` describes the entry itself, not a symlink's target.
+    pub fn matches(&self, name: &str, metadata: &Metadata) -> bool {
+        let type_matches = match self.entry_type {
+            EntryType::Any => true,
+            EntryType::File => metadata.is_file(),
+            EntryType::Dir => metadata.is_dir(),
+            EntryType::Symlink => metadata.is_symlink(),
+        };
+        if !type_matches || !self.name.matches(name) {
+            return false;
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            let size = metadata.len();
+            if !metadata.is_file()
+                || self.min_size.is_some_and(|min| size < min)
+                || self.max_size.is_some_and(|max| size > max)
+            {
+                return false;
+            }
+        }
+        let mtime = file_meta::mtime_secs(metadata);
+        !(self.modified_after.is_some_and(|after| mtime < after)
+            || self.modified_before.is_some_and(|before| mtime > before))
+    }
+}
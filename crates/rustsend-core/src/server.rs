@@ -0,0 +1,2069 @@
+// src/server.rs
+
+use dashmap::DashMap;
+use log::{error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+
+use crate::dedup::ChunkIndex;
+use crate::file_meta::PreservePolicy;
+use crate::filerpc::{
+    chunked_message, delta_message, download_message, file_service_server::FileService,
+    upload_message::Payload, ChunkedMessage, Compression, ConflictPolicy, CreateSymlinkRequest,
+    CreateSymlinkResponse, DeleteRequest, DeleteResponse, DeltaMessage, DirChangeBatch, DirEntry,
+    DirUsageProgress, DirUsageRequest, DiskUsageRequest, DiskUsageResponse, DownloadMessage,
+    DownloadRequest, FileChunk, HashFileRequest, HashFileResponse, ListDirRequest, ListDirResponse,
+    QueryChunksRequest, QueryChunksResponse, SearchMatch, SearchRequest, ServerInfo,
+    ServerInfoRequest, SignatureBatch, SignatureRequest, UploadAction, UploadHeader, UploadMessage,
+    UploadStatus, UploadTrailer, WatchDirRequest,
+};
+use crate::listing::{DirLister, ListOptions, SortedName};
+use crate::search::SearchFilter;
+use crate::space::{QuotaExceeded, QuotaReservation, ShareUsage};
+use crate::{checksum, compression, dedup, delta, dir_watch, file_meta, protocol, space, symlink};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Upper bound on "name (n).ext" candidates tried by the RENAME policy.
+const MAX_RENAME_ATTEMPTS: u32 = 1000;
+
+/// Size of the `data` frames sent by `download`.
+const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 64; // 64 KB
+
+/// Block signatures per `SignatureBatch` message.
+const SIGNATURE_BATCH_LEN: usize = 1024;
+
+// --- Static Path Lock Manager ---
+type PathLockMap = Arc<DashMap<PathBuf, ()>>;
+
+// --- FileService Implementation Struct ---
+#[derive(Debug)]
+pub struct MyFileService {
+    // The base directory where all files will be stored.
+    base_path: PathBuf,
+    // FIX: Fine-grained lock manager for path conflict resolution
+    active_uploads: PathLockMap,
+    // Chunks of all files under base_path, for deduplicated uploads; None
+    // unless enabled, since the index covers the whole share
+    chunks: Option<Arc<ChunkIndex>>,
+    // Metadata restored on received files and sent with downloads
+    preserve: PreservePolicy,
+    // Optional limit on the total size of the share
+    quota: Option<Arc<ShareUsage>>,
+    // Allocate the declared size of each upload before receiving it
+    preallocate: bool,
+}
+
+// Custom implementation of Default to initialize base_path
+impl Default for MyFileService {
+    fn default() -> Self {
+        let default_base_path = match dirs::home_dir() {
+            Some(path) => path,
+            None => PathBuf::from("/tmp"), // 使用 /tmp 作为最后的 fallback
+        };
+
+        MyFileService {
+            // 确保 default 也使用 Home 目录
+            chunks: None,
+            base_path: default_base_path,
+            active_uploads: Arc::new(DashMap::new()),
+            preserve: PreservePolicy::default(),
+            quota: None,
+            preallocate: false,
+        }
+    }
+}
+
+// Implementation of the constructor required by integration tests
+impl MyFileService {
+    /// Creates a new MyFileService instance using the specified base directory.
+    pub fn new(base_path: PathBuf) -> Self {
+        MyFileService {
+            chunks: None,
+            base_path,
+            active_uploads: Arc::new(DashMap::new()),
+            preserve: PreservePolicy::default(),
+            quota: None,
+            preallocate: false,
+        }
+    }
+
+    /// Sets which metadata of uploaded files is restored (see `PreservePolicy`).
+    pub fn with_preserve(mut self, preserve: PreservePolicy) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Limits the total size of the share to `limit` bytes; uploads that
+    /// would exceed it are refused up front.
+    pub fn with_quota(mut self, limit: Option<u64>) -> Self {
+        self.quota = limit.map(|limit| ShareUsage::new(self.base_path.clone(), limit));
+        self
+    }
+
+    /// Preallocates every upload at its declared size (Linux only), so it
+    /// cannot run out of space once accepted.
+    pub fn with_preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Keeps an index of the chunks of every file in the share, so clients
+    /// can send deduplicated uploads. Off by default: the index holds every
+    /// chunk of the share in memory.
+    pub fn with_chunk_index(mut self, enabled: bool) -> Self {
+        self.chunks = enabled.then(|| ChunkIndex::open(&self.base_path));
+        self
+    }
+
+    /// Starts the background scan that indexes the chunks of every file in
+    /// the share, if the index is enabled. Needs a Tokio runtime.
+    pub fn start_chunk_indexing(&self) {
+        if let Some(chunks) = &self.chunks {
+            chunks.spawn_scan();
+        }
+    }
+
+    /// The chunk index, or an error for the dedup RPCs if it is disabled.
+    fn chunk_index(&self) -> Result<&Arc<ChunkIndex>, Status> {
+        self.chunks.as_ref().ok_or_else(|| {
+            Status::unimplemented("Deduplicated uploads are not enabled on this server")
+        })
+    }
+}
+
+// --- Upload Lock Guard ---
+/// Holds a path in `active_uploads` and releases it when dropped, so every
+/// early return (including stream errors) frees the lock.
+struct UploadLock {
+    map: PathLockMap,
+    path: PathBuf,
+}
+
+impl UploadLock {
+    fn acquire(map: &PathLockMap, path: PathBuf) -> Result<Self, Status> {
+        // The entry API makes check-and-insert atomic across concurrent uploads.
+        match map.entry(path.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => {
+                error!("Concurrent write attempt detected for: {}", path.display());
+                Err(Status::unavailable(
+                    "File is currently being written by another client. Try again later.",
+                ))
+            }
+            dashmap::mapref::entry::Entry::Vacant(v) => {
+                v.insert(());
+                Ok(UploadLock {
+                    map: map.clone(),
+                    path,
+                })
+            }
+        }
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.map.remove(&self.path);
+    }
+}
+
+// --- Conflict Resolution ---
+
+/// Outcome of applying the client's `ConflictPolicy` to the requested path.
+enum ConflictResolution {
+    /// Write to this path with the given action reported back to the client.
+    Write(PathBuf, UploadAction),
+    /// Leave the existing file untouched.
+    Skip(String),
+}
+
+/// Builds "name (n).ext" for the n-th rename candidate.
+fn renamed_candidate(filename: &str, n: u32) -> String {
+    let path = Path::new(filename);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) if !stem.is_empty() => format!(
+            "{} ({}).{}",
+            stem.to_string_lossy(),
+            n,
+            ext.to_string_lossy()
+        ),
+        _ => format!("{} ({})", filename, n),
+    }
+}
+
+/// Decides where (and whether) the upload is written according to the policy.
+async fn resolve_conflict(
+    upload_dir: &Path,
+    header: &UploadHeader,
+) -> Result<ConflictResolution, Status> {
+    let final_path = upload_dir.join(&header.filename);
+    let existing = match fs::metadata(&final_path).await {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(ConflictResolution::Write(final_path, UploadAction::Created));
+        }
+        Err(e) => {
+            error!("Failed to stat target file: {}", e);
+            return Err(Status::internal(format!("Could not inspect target: {}", e)));
+        }
+    };
+
+    if existing.is_dir() {
+        return Err(Status::already_exists(format!(
+            "A directory named '{}' already exists",
+            header.filename
+        )));
+    }
+
+    match header.conflict_policy() {
+        ConflictPolicy::Unspecified | ConflictPolicy::Overwrite => Ok(ConflictResolution::Write(
+            final_path,
+            UploadAction::Overwritten,
+        )),
+        ConflictPolicy::Fail => Err(Status::already_exists(format!(
+            "File '{}' already exists",
+            header.filename
+        ))),
+        ConflictPolicy::SkipIfIdentical => {
+            if header.sha256.is_empty() {
+                return Err(Status::invalid_argument(
+                    "SKIP_IF_IDENTICAL requires the sha256 of the uploaded file",
+                ));
+            }
+            if existing.len() == header.size {
+                let path = final_path.clone();
+                let digest = tokio::task::spawn_blocking(move || checksum::sha256_file(&path))
+                    .await
+                    .map_err(|e| Status::internal(format!("Hash task failed: {}", e)))?
+                    .map_err(|e| Status::internal(format!("Could not hash target: {}", e)))?;
+                if digest == header.sha256 {
+                    return Ok(ConflictResolution::Skip(
+                        "Identical file already exists".to_string(),
+                    ));
+                }
+            }
+            Ok(ConflictResolution::Write(
+                final_path,
+                UploadAction::Overwritten,
+            ))
+        }
+        ConflictPolicy::OverwriteIfNewer => {
+            if header.mtime > file_meta::mtime_secs(&existing) {
+                Ok(ConflictResolution::Write(
+                    final_path,
+                    UploadAction::Overwritten,
+                ))
+            } else {
+                Ok(ConflictResolution::Skip(
+                    "Existing file is newer or the same age".to_string(),
+                ))
+            }
+        }
+        ConflictPolicy::Rename => {
+            for n in 1..=MAX_RENAME_ATTEMPTS {
+                let candidate = upload_dir.join(renamed_candidate(&header.filename, n));
+                if fs::symlink_metadata(&candidate).await.is_err() {
+                    return Ok(ConflictResolution::Write(candidate, UploadAction::Renamed));
+                }
+            }
+            Err(Status::already_exists(format!(
+                "No free name found for '{}'",
+                header.filename
+            )))
+        }
+    }
+}
+
+/// PERMISSION_DENIED tagged so clients can tell a sandbox escape from other denials.
+fn outside_sandbox_status() -> Status {
+    let mut status = Status::permission_denied("Access to this path is denied");
+    status.metadata_mut().insert(
+        protocol::ERROR_REASON_METADATA,
+        MetadataValue::from_static(protocol::REASON_PATH_OUTSIDE_SANDBOX),
+    );
+    status
+}
+
+/// RESOURCE_EXHAUSTED for an upload larger than the free space of the target.
+fn insufficient_space_status(needed: u64, available: u64) -> Status {
+    let mut status = Status::resource_exhausted(format!(
+        "Not enough free space: {} bytes needed, {} available",
+        needed, available
+    ));
+    status.metadata_mut().insert(
+        protocol::ERROR_REASON_METADATA,
+        MetadataValue::from_static(protocol::REASON_INSUFFICIENT_SPACE),
+    );
+    status
+}
+
+/// RESOURCE_EXHAUSTED for an upload that does not fit the share's quota.
+fn quota_exceeded_status(needed: u64, exceeded: QuotaExceeded) -> Status {
+    let mut status = Status::resource_exhausted(format!(
+        "Share quota exceeded: {} bytes needed, {} of {} used",
+        needed, exceeded.used, exceeded.limit
+    ));
+    status.metadata_mut().insert(
+        protocol::ERROR_REASON_METADATA,
+        MetadataValue::from_static(protocol::REASON_QUOTA_EXCEEDED),
+    );
+    status
+}
+
+/// True if `path` only consists of plain names (no "..", root or prefix components).
+fn is_plain_relative(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Listing entry for the symlink at `path` in `dir`: type, size and mtime of
+/// its target (zero when the target is missing or outside the share), plus
+/// the target as far as it may be shown.
+async fn symlink_entry(name: String, path: &Path, base: &Path, dir: &Path) -> DirEntry {
+    let target = fs::read_link(path).await.ok();
+    let symlink_target = target
+        .as_deref()
+        .and_then(|target| symlink::display_target(base, dir, target));
+    // Only describe targets the sandbox would let the client reach.
+    let metadata = match fs::canonicalize(path).await {
+        Ok(resolved) if resolved.starts_with(base) => fs::metadata(&resolved).await.ok(),
+        _ => None,
+    };
+    DirEntry {
+        name,
+        is_dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
+        size: metadata
+            .as_ref()
+            .filter(|m| m.is_file())
+            .map_or(0, |m| m.len()),
+        mtime: metadata.as_ref().map_or(0, file_meta::mtime_secs),
+        is_symlink: true,
+        symlink_target: symlink_target.unwrap_or_default(),
+    }
+}
+
+/// Listing entry for `path` in `dir`, given its own (lstat) metadata.
+async fn dir_entry(
+    name: String,
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    base: &Path,
+    dir: &Path,
+) -> DirEntry {
+    if metadata.is_symlink() {
+        return symlink_entry(name, path, base, dir).await;
+    }
+    DirEntry {
+        name,
+        is_dir: metadata.is_dir(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        mtime: file_meta::mtime_secs(metadata),
+        ..Default::default()
+    }
+}
+
+/// Converts the first v1 `FileChunk` into the v2 header the upload pipeline expects.
+fn header_from_v1_chunk(chunk: &FileChunk) -> UploadHeader {
+    UploadHeader {
+        filename: chunk.filename.clone(),
+        target_dir: chunk.target_dir.clone(),
+        size: chunk.file_size,
+        mtime: chunk.mtime,
+        conflict_policy: chunk.conflict_policy,
+        sha256: chunk.sha256.clone(),
+        ..Default::default()
+    }
+}
+
+/// Validates the codec announced in an upload header.
+fn upload_codec(header: &UploadHeader) -> Result<Compression, Status> {
+    match compression::from_wire(header.compression) {
+        Some(codec) if codec == Compression::None || compression::SUPPORTED.contains(&codec) => {
+            Ok(codec)
+        }
+        _ => Err(Status::invalid_argument(format!(
+            "Unsupported compression: {}",
+            header.compression
+        ))),
+    }
+}
+
+/// Checks the size of an incoming `data` frame and decompresses it.
+fn decode_frame(codec: Compression, data: Vec<u8>) -> Result<Vec<u8>, Status> {
+    if data.len() as u64 > protocol::MAX_CHUNK_SIZE {
+        return Err(Status::invalid_argument(format!(
+            "Data frame of {} bytes exceeds the {} byte limit",
+            data.len(),
+            protocol::MAX_CHUNK_SIZE
+        )));
+    }
+    if codec == Compression::None {
+        return Ok(data);
+    }
+    // The decompressed frame is held to the same limit as a raw one.
+    compression::decompress(codec, &data, protocol::MAX_CHUNK_SIZE as usize)
+        .map_err(|e| Status::invalid_argument(format!("Corrupt compressed frame: {}", e)))
+}
+
+// --- Upload Pipeline ---
+
+/// Result of starting an upload: either a sink to stream data into, or an
+/// immediate reply when the conflict policy decided to skip.
+enum UploadStart {
+    Receive(Box<UploadSink>),
+    Skipped(UploadStatus),
+}
+
+/// Receiving side of a single upload, shared by the v1 and v2 handlers.
+///
+/// Data is written to a hidden temporary file next to the target and only
+/// renamed into place by `finish`, so a failed or corrupted upload never
+/// clobbers an existing file. Dropping an unfinished sink removes the temp file.
+struct UploadSink {
+    _lock: UploadLock,
+    chunks: Option<Arc<ChunkIndex>>,
+    file: Option<fs::File>,
+    temp_path: PathBuf,
+    final_path: PathBuf,
+    final_name: String,
+    action: UploadAction,
+    /// Carries the sender's timestamps, mode and xattrs.
+    header: UploadHeader,
+    preserve: PreservePolicy,
+    /// Room held in the share's quota until the upload ends.
+    reservation: Option<QuotaReservation>,
+    hasher: Sha256,
+    bytes_written: u64,
+    expected_size: Option<u64>,
+    finished: bool,
+}
+
+impl UploadSink {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Status> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| Status::internal("Upload sink already closed"))?;
+        // Space was checked against the declared size, so hold the sender to it.
+        if let Some(expected) = self.expected_size {
+            if self.bytes_written + data.len() as u64 > expected {
+                return Err(Status::data_loss(format!(
+                    "Received more than the declared {} bytes",
+                    expected
+                )));
+            }
+        }
+        // FIX: Use AsyncWriteExt::write_all(file, &chunk.data).await (asynchronous)
+        if let Err(e) = AsyncWriteExt::write_all(file, data).await {
+            error!("Failed to write file data: {}", e);
+            return Err(Status::internal(format!("Failed to write data: {}", e)));
+        }
+        self.hasher.update(data);
+        self.bytes_written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Verifies size and (optionally) digest, then moves the temp file into place.
+    async fn finish(mut self, expected_sha256: Option<&[u8]>) -> Result<UploadStatus, Status> {
+        if let Some(mut file) = self.file.take() {
+            if let Err(e) = file.flush().await {
+                error!("Failed to flush file data: {}", e);
+                return Err(Status::internal(format!("Failed to write data: {}", e)));
+            }
+        }
+
+        if let Some(expected) = self.expected_size {
+            if expected != self.bytes_written {
+                error!(
+                    "Size mismatch for {}: header declared {} bytes, received {}",
+                    self.final_name, expected, self.bytes_written
+                );
+                return Err(Status::data_loss(format!(
+                    "Size mismatch: expected {} bytes, received {}",
+                    expected, self.bytes_written
+                )));
+            }
+        }
+
+        let digest = std::mem::take(&mut self.hasher).finalize();
+        if let Some(expected) = expected_sha256.filter(|d| !d.is_empty()) {
+            if digest.as_slice() != expected {
+                error!("Digest mismatch for {}", self.final_name);
+                return Err(Status::data_loss("SHA-256 mismatch, upload discarded"));
+            }
+        }
+
+        // Created/Renamed 不允许覆盖：在改名前再次确认目标仍不存在
+        if matches!(self.action, UploadAction::Created | UploadAction::Renamed)
+            && fs::symlink_metadata(&self.final_path).await.is_ok()
+        {
+            return Err(Status::already_exists(format!(
+                "File '{}' already exists",
+                self.final_name
+            )));
+        }
+
+        // A failure here leaves the content intact, so the upload still succeeds.
+        if let Err(e) = file_meta::apply(&self.temp_path, &self.header, self.preserve) {
+            warn!("Could not restore metadata of {}: {}", self.final_name, e);
+        }
+
+        let replaced = match self.action {
+            UploadAction::Overwritten => fs::symlink_metadata(&self.final_path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0),
+            _ => 0,
+        };
+
+        if let Err(e) = fs::rename(&self.temp_path, &self.final_path).await {
+            error!("Failed to move upload into place: {}", e);
+            return Err(Status::internal(format!("Could not finalize file: {}", e)));
+        }
+        self.finished = true;
+        // New content becomes available for deduplicated uploads.
+        if let Some(chunks) = &self.chunks {
+            chunks.index_later(self.final_path.clone());
+        }
+        if let Some(reservation) = &self.reservation {
+            reservation
+                .usage()
+                .record(self.bytes_written, replaced)
+                .await;
+        }
+
+        info!(
+            "File {} upload successful. Total size: {} bytes.",
+            self.final_name, self.bytes_written
+        );
+
+        let message = match self.action {
+            UploadAction::Renamed => format!(
+                "File uploaded as '{}'. Total bytes written: {}.",
+                self.final_name, self.bytes_written
+            ),
+            UploadAction::Overwritten => format!(
+                "Existing file overwritten. Total bytes written: {}.",
+                self.bytes_written
+            ),
+            _ => format!(
+                "File uploaded successfully. Total bytes written: {}.",
+                self.bytes_written
+            ),
+        };
+
+        Ok(UploadStatus {
+            success: true,
+            message,
+            action: self.action as i32,
+            final_name: self.final_name.clone(),
+        })
+    }
+}
+
+impl Drop for UploadSink {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Close the handle before unlinking so the removal also works on Windows.
+            self.file.take();
+            if let Err(e) = std::fs::remove_file(&self.temp_path) {
+                warn!(
+                    "Failed to remove partial upload {}: {}",
+                    self.temp_path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// A directory read for `ListDir`: its sorted names, listed from `start` on.
+struct DirListing {
+    dir: PathBuf,
+    base: PathBuf,
+    names: Vec<SortedName>,
+    start: usize,
+}
+
+/// Full entries for a page of names; entries removed in the meantime are
+/// left out.
+async fn listing_entries(names: &[SortedName], dir: &Path, base: &Path) -> Vec<DirEntry> {
+    let mut entries = Vec::with_capacity(names.len());
+    for sorted in names {
+        let path = dir.join(&sorted.name);
+        if let Ok(metadata) = fs::symlink_metadata(&path).await {
+            entries.push(dir_entry(sorted.name.clone(), &path, &metadata, base, dir).await);
+        }
+    }
+    entries
+}
+
+impl MyFileService {
+    /// Resolves a client path ("/" or "Documents/Photos") inside `base_path`,
+    /// rejecting anything that escapes the sandbox after canonicalization.
+    fn resolve_sandboxed(&self, path_str: &str) -> Result<PathBuf, Status> {
+        // --- FIX START: 路径构建修正 ---
+        let mut full_path = self.base_path.clone();
+
+        // 如果客户端请求的不是根路径，则将其附加到 base_path
+        if path_str != "/" && !path_str.is_empty() {
+            // 移除前导的 '/'，确保路径被正确地 join 到 base_path 后面
+            full_path.push(path_str.trim_start_matches('/'));
+        }
+        // --- FIX END ---
+
+        // 1. 规范化 base_path (沙箱根目录)
+        let canonical_base = self.canonical_base()?;
+
+        // 2. 规范化请求路径
+        let canonical_path = full_path.canonicalize().map_err(|e| {
+            warn!(
+                "Path lookup failed (path invalid/not found): {} -> {}",
+                path_str, e
+            );
+            Status::not_found(format!("Path not found or inaccessible: {}", path_str))
+        })?;
+
+        // --- 修复点 B: 路径遍历检查 (沙箱机制) ---
+        // 检查 canonical_path 是否以 canonical_base 开头。
+        if !canonical_path.starts_with(&canonical_base) {
+            error!(
+                "Path traversal attempt detected: {} (Base: {})",
+                canonical_path.display(),
+                canonical_base.display()
+            );
+            return Err(outside_sandbox_status());
+        }
+
+        Ok(canonical_path)
+    }
+
+    fn canonical_base(&self) -> Result<PathBuf, Status> {
+        self.base_path.canonicalize().map_err(|e| {
+            error!("Failed to canonicalize server base path: {}", e);
+            Status::internal("Server base directory is invalid or inaccessible")
+        })
+    }
+
+    /// Reads, filters and sorts the directory of a `ListDir` request and finds
+    /// where its cursor points.
+    async fn read_listing(&self, req: &ListDirRequest) -> Result<DirListing, Status> {
+        let dir = self.resolve_sandboxed(&req.path)?;
+        let base = self.canonical_base()?;
+        let lister = DirLister::new(ListOptions::from_request(req)).map_err(|e| {
+            Status::invalid_argument(format!("Bad pattern \"{}\": {}", req.pattern, e))
+        })?;
+        info!("Querying directory: {}", dir.display());
+
+        let read_dir = dir.clone();
+        let sandbox = base.clone();
+        let (lister, names) = tokio::task::spawn_blocking(move || {
+            // Only links inside the sandbox sort by their target.
+            let names = lister.list(&read_dir, |link| {
+                let resolved = std::fs::canonicalize(link).ok()?;
+                resolved
+                    .starts_with(&sandbox)
+                    .then(|| std::fs::metadata(&resolved).ok())?
+            });
+            (lister, names)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Directory listing failed: {}", e)))?;
+        let names = names.map_err(|e| {
+            error!("Failed to read directory: {} -> {}", dir.display(), e);
+            Status::internal(format!("Could not read directory: {}", e))
+        })?;
+        let start = match req.cursor.as_str() {
+            "" => 0,
+            cursor => lister
+                .position_after(&names, cursor)
+                .ok_or_else(|| Status::invalid_argument("Malformed listing cursor"))?,
+        };
+        Ok(DirListing {
+            dir,
+            base,
+            names,
+            start,
+        })
+    }
+
+    /// Opens a regular file inside the sandbox for reading.
+    fn open_regular_file(
+        &self,
+        path_str: &str,
+    ) -> Result<(PathBuf, std::fs::File, std::fs::Metadata), Status> {
+        let path = self.resolve_sandboxed(path_str)?;
+        let file = std::fs::File::open(&path).map_err(|e| {
+            error!("Failed to open {}: {}", path.display(), e);
+            Status::internal(format!("Could not open file: {}", e))
+        })?;
+        let metadata = file
+            .metadata()
+            .map_err(|e| Status::internal(format!("Could not get file metadata: {}", e)))?;
+        if !metadata.is_file() {
+            return Err(Status::invalid_argument(format!(
+                "Not a regular file: {}",
+                path_str
+            )));
+        }
+        Ok((path, file, metadata))
+    }
+
+    /// Reads a chunk back from the file the index points to and verifies its
+    /// hash. A stale entry triggers a re-index of that file.
+    async fn read_chunk(&self, hash: &[u8]) -> Result<Vec<u8>, Status> {
+        let unavailable = || {
+            Status::failed_precondition(format!(
+                "Chunk {} is no longer available",
+                checksum::to_hex(hash)
+            ))
+        };
+        let chunks = self.chunk_index()?;
+        let location = chunks.locate(hash).ok_or_else(unavailable)?;
+        let data = async {
+            if location.len > dedup::MAX_CHUNK_SIZE {
+                return None;
+            }
+            let path = self
+                .resolve_sandboxed(&location.path.to_string_lossy())
+                .ok()?;
+            let mut file = fs::File::from_std(file_meta::open_untouched(&path).ok()?);
+            file.seek(std::io::SeekFrom::Start(location.offset))
+                .await
+                .ok()?;
+            let mut data = vec![0u8; location.len as usize];
+            file.read_exact(&mut data).await.ok()?;
+            Some(data)
+        }
+        .await;
+        match data {
+            Some(data) if Sha256::digest(&data).as_slice() == hash => Ok(data),
+            _ => {
+                warn!(
+                    "Stale chunk index entry for {}, re-indexing",
+                    location.path.display()
+                );
+                chunks.index_later(self.base_path.join(&location.path));
+                Err(unavailable())
+            }
+        }
+    }
+
+    /// Validates the header, applies the conflict policy and opens the temp file.
+    async fn begin_upload(
+        &self,
+        header: &UploadHeader,
+        expected_size: Option<u64>,
+    ) -> Result<UploadStart, Status> {
+        if header.filename.is_empty() {
+            return Err(Status::invalid_argument("Filename cannot be empty"));
+        }
+
+        // --- FIX START: 路径拼接修正 ---
+        // 客户端发来的 target_dir 可能包含前导 '/'，这将导致 PathBuf::join 覆盖 self.base_path。
+        let target_rel_path = header.target_dir.trim_start_matches('/');
+        let upload_dir = self.base_path.join(target_rel_path);
+        let requested_path = upload_dir.join(&header.filename);
+        // --- FIX END ---
+
+        // Reject "../" in the target and any separator in the filename before touching disk.
+        if !is_plain_relative(Path::new(target_rel_path))
+            || Path::new(&header.filename).components().count() != 1
+            || !is_plain_relative(Path::new(&header.filename))
+        {
+            warn!(
+                "Rejected upload outside the sandbox: {}/{}",
+                header.target_dir, header.filename
+            );
+            return Err(outside_sandbox_status());
+        }
+
+        // --- CONCURRENCY LOCK START ---
+        // 尝试规范化路径，如果失败（例如目录不存在），则使用原始路径
+        let path_to_lock = requested_path
+            .canonicalize()
+            .unwrap_or(requested_path.clone());
+        let lock = UploadLock::acquire(&self.active_uploads, path_to_lock)?;
+        // --- CONCURRENCY LOCK ACQUIRED (released when the sink drops) ---
+
+        // FIX: Use tokio::fs::create_dir_all (asynchronous)
+        if let Err(e) = fs::create_dir_all(&upload_dir).await {
+            error!("Failed to create target directory: {}", e);
+            return Err(Status::internal(format!(
+                "Failed to create directory: {}",
+                e
+            )));
+        }
+        // A symlinked directory inside the sandbox could still point outside it.
+        self.resolve_sandboxed(target_rel_path)?;
+
+        let (final_path, action) = match resolve_conflict(&upload_dir, header).await? {
+            ConflictResolution::Write(path, action) => (path, action),
+            ConflictResolution::Skip(reason) => {
+                info!("Skipping upload of {}: {}", header.filename, reason);
+                return Ok(UploadStart::Skipped(UploadStatus {
+                    success: true,
+                    message: format!("File skipped: {}.", reason),
+                    action: UploadAction::Skipped as i32,
+                    final_name: header.filename.clone(),
+                }));
+            }
+        };
+        let final_name = final_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| header.filename.clone());
+
+        info!(
+            "Starting to receive file: {} to directory: {} ({:?})",
+            final_name,
+            upload_dir.display(),
+            action
+        );
+
+        // Refuse what cannot fit before writing anything. The replaced file
+        // stays until the rename, so free space must hold the whole upload.
+        match space::available_space(&upload_dir) {
+            Ok(available) if available < header.size => {
+                warn!(
+                    "Refusing upload of {} ({} bytes): only {} bytes free",
+                    final_name, header.size, available
+                );
+                return Err(insufficient_space_status(header.size, available));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {}
+            Err(e) => warn!(
+                "Could not check free space in {}: {}",
+                upload_dir.display(),
+                e
+            ),
+        }
+        let reservation = match &self.quota {
+            Some(quota) => {
+                let replaced = match action {
+                    UploadAction::Overwritten => fs::symlink_metadata(&final_path)
+                        .await
+                        .map(|m| m.len())
+                        .unwrap_or(0),
+                    _ => 0,
+                };
+                let reservation = quota.reserve(header.size, replaced).await.map_err(|e| {
+                    warn!(
+                        "Refusing upload of {} ({} bytes): quota of {} bytes exceeded",
+                        final_name, header.size, e.limit
+                    );
+                    quota_exceeded_status(header.size, e)
+                })?;
+                Some(reservation)
+            }
+            None => None,
+        };
+
+        let temp_path = upload_dir.join(format!(
+            ".{}.{}{}",
+            final_name,
+            Uuid::new_v4(),
+            protocol::PARTIAL_SUFFIX
+        ));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .await
+            .map_err(|e| {
+                error!("Failed to create file: {}", e);
+                Status::internal(format!("Could not create file: {}", e))
+            })?;
+
+        let sink = UploadSink {
+            _lock: lock,
+            chunks: self.chunks.clone(),
+            file: Some(file),
+            temp_path,
+            final_path,
+            final_name,
+            action,
+            header: header.clone(),
+            preserve: self.preserve,
+            reservation,
+            hasher: Sha256::new(),
+            bytes_written: 0,
+            expected_size,
+            finished: false,
+        };
+
+        // Dropping the sink on failure removes the temp file again.
+        if self.preallocate && header.size > 0 {
+            if let Some(file) = &sink.file {
+                match space::preallocate(file, header.size) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::StorageFull => {
+                        warn!(
+                            "Refusing upload of {}: preallocation failed: {}",
+                            sink.final_name, e
+                        );
+                        return Err(insufficient_space_status(header.size, 0));
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {}
+                    Err(e) => warn!("Could not preallocate {}: {}", sink.final_name, e),
+                }
+            }
+        }
+
+        Ok(UploadStart::Receive(Box::new(sink)))
+    }
+}
+
+/// Reads `file` in a blocking task and feeds header, data frames and a
+/// trailer with the SHA-256 into `tx`.
+fn stream_file(
+    mut file: std::fs::File,
+    path: PathBuf,
+    mut header: UploadHeader,
+    accepted: Vec<Compression>,
+    tx: mpsc::Sender<Result<DownloadMessage, Status>>,
+) {
+    use download_message::Payload;
+    use std::io::Read;
+
+    let frame = |payload| {
+        Ok(DownloadMessage {
+            payload: Some(payload),
+        })
+    };
+    let read_error = |e: std::io::Error| {
+        error!("Failed to read file for download: {}", e);
+        let _ = tx.blocking_send(Err(Status::internal(format!("Failed to read file: {}", e))));
+    };
+
+    let mut buffer = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+    // The first chunk doubles as the sample that decides the codec.
+    let mut n = match file.read(&mut buffer) {
+        Ok(n) => n,
+        Err(e) => return read_error(e),
+    };
+    let codec = compression::choose(&path, &buffer[..n], &accepted);
+    header.compression = codec as i32;
+    if codec != Compression::None {
+        info!(
+            "Compressing download of {} with {:?}",
+            path.display(),
+            codec
+        );
+    }
+
+    if tx.blocking_send(frame(Payload::Header(header))).is_err() {
+        return;
+    }
+
+    let mut hasher = Sha256::new();
+    while n > 0 {
+        let chunk = &buffer[..n];
+        hasher.update(chunk);
+        let data = match compression::compress(codec, chunk) {
+            Ok(data) => data,
+            Err(e) => return read_error(e),
+        };
+        // The receiver is gone when the client cancels the download.
+        if tx.blocking_send(frame(Payload::Data(data))).is_err() {
+            return;
+        }
+        n = match file.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) => return read_error(e),
+        };
+    }
+
+    let trailer = UploadTrailer {
+        sha256: hasher.finalize().to_vec(),
+    };
+    let _ = tx.blocking_send(frame(Payload::Trailer(trailer)));
+}
+
+#[tonic::async_trait]
+impl FileService for MyFileService {
+    type DownloadStream = ReceiverStream<Result<DownloadMessage, Status>>;
+    type GetSignaturesStream = ReceiverStream<Result<SignatureBatch, Status>>;
+    type WatchDirStream = ReceiverStream<Result<DirChangeBatch, Status>>;
+    type DirUsageStream = ReceiverStream<Result<DirUsageProgress, Status>>;
+    type SearchStream = ReceiverStream<Result<SearchMatch, Status>>;
+    type ListDirStreamStream = ReceiverStream<Result<ListDirResponse, Status>>;
+
+    /// 1. Stream file upload (Client Streaming RPC, v1 `FileChunk` framing)
+    ///
+    /// Kept for older clients; new clients use `upload`.
+    async fn upload_file(
+        &self,
+        request: Request<tonic::Streaming<FileChunk>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        info!("Received file upload request (v1)...");
+        let mut stream = request.into_inner();
+
+        // First chunk carries the metadata: filename, target_dir and conflict policy.
+        let first = stream.message().await?.ok_or_else(|| {
+            Status::invalid_argument("File stream ended without receiving first chunk metadata.")
+        })?;
+
+        // v1 clients older than the conflict policy never send file_size. Nothing
+        // would bound such an upload, so a share with a quota only takes it when
+        // the first chunk already shows an empty file.
+        let expected_size = if first.file_size > 0 {
+            Some(first.file_size)
+        } else if self.quota.is_some() {
+            if !first.data.is_empty() || !first.eof {
+                return Err(Status::failed_precondition(
+                    "This share has a quota; uploads must declare their size (file_size)",
+                ));
+            }
+            Some(0)
+        } else {
+            None
+        };
+        let mut sink = match self
+            .begin_upload(&header_from_v1_chunk(&first), expected_size)
+            .await?
+        {
+            UploadStart::Receive(sink) => sink,
+            UploadStart::Skipped(status) => return Ok(Response::new(status)),
+        };
+
+        let mut chunk = first;
+        loop {
+            sink.write(&chunk.data).await?;
+
+            // Check for EOF flag
+            if chunk.eof {
+                break;
+            }
+            chunk = match stream.message().await? {
+                Some(c) => c,
+                None => break,
+            };
+        }
+
+        Ok(Response::new(sink.finish(None).await?))
+    }
+
+    /// 1b. Stream file upload (Client Streaming RPC, v2 header/data/trailer framing)
+    async fn upload(
+        &self,
+        request: Request<tonic::Streaming<UploadMessage>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        info!("Received file upload request (v2)...");
+        let mut stream = request.into_inner();
+
+        let header = match stream.message().await?.and_then(|m| m.payload) {
+            Some(Payload::Header(header)) => header,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Upload stream must start with a header",
+                ))
+            }
+        };
+
+        let codec = upload_codec(&header)?;
+
+        let mut sink = match self.begin_upload(&header, Some(header.size)).await? {
+            UploadStart::Receive(sink) => sink,
+            UploadStart::Skipped(status) => return Ok(Response::new(status)),
+        };
+
+        loop {
+            match stream.message().await?.and_then(|m| m.payload) {
+                Some(Payload::Data(data)) => sink.write(&decode_frame(codec, data)?).await?,
+                Some(Payload::Trailer(trailer)) => {
+                    let status = sink.finish(Some(&trailer.sha256)).await?;
+                    return Ok(Response::new(status));
+                }
+                Some(Payload::Header(_)) => {
+                    return Err(Status::invalid_argument("Duplicate upload header"));
+                }
+                // A stream that ends without a trailer is treated as an aborted upload.
+                None => {
+                    warn!("Upload of {} ended without a trailer", header.filename);
+                    return Err(Status::aborted("Upload ended before the trailer"));
+                }
+            }
+        }
+    }
+
+    /// 2. List directory contents, a page at a time if asked (Unary RPC)
+    async fn list_dir(
+        &self,
+        request: Request<ListDirRequest>,
+    ) -> Result<Response<ListDirResponse>, Status> {
+        let req = request.into_inner();
+        let listing = self.read_listing(&req).await?;
+        let rest = &listing.names[listing.start..];
+        // 0 keeps the whole listing in one response, as before paging.
+        let page = match req.page_size {
+            0 => rest,
+            n => &rest[..rest.len().min(n.min(protocol::MAX_LIST_PAGE) as usize)],
+        };
+        let next_cursor = match page.last() {
+            Some(last) if page.len() < rest.len() => last.cursor(),
+            _ => String::new(),
+        };
+        Ok(Response::new(ListDirResponse {
+            entries: listing_entries(page, &listing.dir, &listing.base).await,
+            next_cursor,
+            total: listing.names.len() as u64,
+        }))
+    }
+
+    /// 3. Report protocol version and capabilities (Unary RPC)
+    async fn get_server_info(
+        &self,
+        request: Request<ServerInfoRequest>,
+    ) -> Result<Response<ServerInfo>, Status> {
+        let req = request.into_inner();
+        info!(
+            "Server info requested by client v{} (protocol {})",
+            req.client_app_version, req.client_protocol_version
+        );
+
+        let reply = ServerInfo {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            app_version: protocol::APP_VERSION.to_string(),
+            device_name: protocol::device_name(),
+            features: protocol::SUPPORTED_FEATURES
+                .iter()
+                .filter(|&&f| f != protocol::FEATURE_CHUNK_DEDUP || self.chunks.is_some())
+                .map(|f| f.to_string())
+                .collect(),
+            max_chunk_size: protocol::MAX_CHUNK_SIZE,
+        };
+        Ok(Response::new(reply))
+    }
+
+    /// 4. Stream a file to the client (Server Streaming RPC)
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let req = request.into_inner();
+        let (path, file, metadata) = self.open_regular_file(&req.path)?;
+
+        let mut header = UploadHeader {
+            filename: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            ..Default::default()
+        };
+        file_meta::describe(&path, &metadata, self.preserve, &mut header);
+
+        info!(
+            "Starting download of {} ({} bytes)",
+            path.display(),
+            header.size
+        );
+
+        let accepted = req
+            .accept_compression
+            .iter()
+            .filter_map(|&c| compression::from_wire(c))
+            .collect();
+        let (tx, rx) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || stream_file(file, path, header, accepted, tx));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 5. Stream the block signatures of an existing file (Server Streaming RPC)
+    async fn get_signatures(
+        &self,
+        request: Request<SignatureRequest>,
+    ) -> Result<Response<Self::GetSignaturesStream>, Status> {
+        let req = request.into_inner();
+        let (path, file, metadata) = self.open_regular_file(&req.path)?;
+        let file_size = metadata.len();
+        let block_size = delta::block_size_for(file_size);
+
+        info!(
+            "Computing signatures of {} ({} bytes, {} byte blocks)",
+            path.display(),
+            file_size,
+            block_size
+        );
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let result = delta::signatures(
+                std::io::BufReader::new(file),
+                block_size,
+                SIGNATURE_BATCH_LEN,
+                |blocks| {
+                    let batch = SignatureBatch {
+                        block_size: block_size as u32,
+                        file_size,
+                        blocks,
+                    };
+                    // The receiver is gone when the client cancels.
+                    tx.blocking_send(Ok(batch)).is_ok()
+                },
+            );
+            if let Err(e) = result {
+                error!("Failed to read {} for signatures: {}", path.display(), e);
+                let _ =
+                    tx.blocking_send(Err(Status::internal(format!("Failed to read file: {}", e))));
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 6. Rebuild a file from blocks of the existing copy plus new data
+    /// (Client Streaming RPC)
+    async fn upload_delta(
+        &self,
+        request: Request<tonic::Streaming<DeltaMessage>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        use delta_message::Payload;
+
+        info!("Received delta upload request...");
+        let mut stream = request.into_inner();
+
+        let delta_header = match stream.message().await?.and_then(|m| m.payload) {
+            Some(Payload::Header(header)) => header,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Delta upload stream must start with a header",
+                ))
+            }
+        };
+        let header = delta_header
+            .file
+            .ok_or_else(|| Status::invalid_argument("Delta header without a file description"))?;
+        let codec = upload_codec(&header)?;
+        let block_size = delta_header.block_size as usize;
+        if !(delta::MIN_BLOCK_SIZE..=delta::MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(Status::invalid_argument(format!(
+                "Unsupported block size: {}",
+                block_size
+            )));
+        }
+
+        let mut sink = match self.begin_upload(&header, Some(header.size)).await? {
+            UploadStart::Receive(sink) => sink,
+            UploadStart::Skipped(status) => return Ok(Response::new(status)),
+        };
+
+        // The basis is the file being replaced; the upload lock is held from here on.
+        let basis_rel = Path::new(header.target_dir.trim_start_matches('/')).join(&header.filename);
+        let (basis_path, basis_file, basis_metadata) =
+            self.open_regular_file(&basis_rel.to_string_lossy())?;
+        let basis_size = delta_header.basis_size;
+        if basis_metadata.len() != basis_size {
+            warn!(
+                "Basis {} changed since its signatures were computed",
+                basis_path.display()
+            );
+            return Err(Status::failed_precondition(
+                "The existing file changed since its signatures were computed",
+            ));
+        }
+        let block_count = basis_size.div_ceil(block_size as u64);
+        let mut basis = fs::File::from_std(basis_file);
+        let mut block = vec![0u8; block_size];
+        let mut copied = 0u64;
+
+        loop {
+            match stream.message().await?.and_then(|m| m.payload) {
+                Some(Payload::Copy(copy)) => {
+                    let end = copy.start_block.saturating_add(copy.count as u64);
+                    if end > block_count {
+                        return Err(Status::invalid_argument(format!(
+                            "Block range {}..{} is outside the existing file",
+                            copy.start_block, end
+                        )));
+                    }
+                    let offset = copy.start_block * block_size as u64;
+                    let len = (end * block_size as u64).min(basis_size) - offset;
+                    basis
+                        .seek(std::io::SeekFrom::Start(offset))
+                        .await
+                        .map_err(|e| Status::internal(format!("Failed to read basis: {}", e)))?;
+                    let mut remaining = len;
+                    while remaining > 0 {
+                        let n = remaining.min(block_size as u64) as usize;
+                        basis.read_exact(&mut block[..n]).await.map_err(|e| {
+                            Status::internal(format!("Failed to read basis: {}", e))
+                        })?;
+                        sink.write(&block[..n]).await?;
+                        remaining -= n as u64;
+                    }
+                    copied += len;
+                }
+                Some(Payload::Data(data)) => sink.write(&decode_frame(codec, data)?).await?,
+                Some(Payload::Trailer(trailer)) => {
+                    // Close the basis before it is replaced by the rename.
+                    drop(basis);
+                    let mut status = sink.finish(Some(&trailer.sha256)).await?;
+                    info!(
+                        "Delta upload of {} reused {} bytes of the existing file",
+                        header.filename, copied
+                    );
+                    status.message = format!(
+                        "{} Reused {} bytes of the existing file.",
+                        status.message, copied
+                    );
+                    return Ok(Response::new(status));
+                }
+                Some(Payload::Header(_)) => {
+                    return Err(Status::invalid_argument("Duplicate delta header"));
+                }
+                None => {
+                    warn!(
+                        "Delta upload of {} ended without a trailer",
+                        header.filename
+                    );
+                    return Err(Status::aborted("Upload ended before the trailer"));
+                }
+            }
+        }
+    }
+
+    /// 7. Report which chunk hashes the share already contains (Unary RPC)
+    async fn query_chunks(
+        &self,
+        request: Request<QueryChunksRequest>,
+    ) -> Result<Response<QueryChunksResponse>, Status> {
+        let req = request.into_inner();
+        if req.hashes.len() > protocol::MAX_CHUNK_QUERY {
+            return Err(Status::invalid_argument(format!(
+                "At most {} hashes per query",
+                protocol::MAX_CHUNK_QUERY
+            )));
+        }
+        let chunks = self.chunk_index()?;
+        let present = req
+            .hashes
+            .iter()
+            .map(|hash| chunks.contains(hash))
+            .collect();
+        Ok(Response::new(QueryChunksResponse { present }))
+    }
+
+    /// 8. Assemble a file from indexed chunks plus new data (Client Streaming RPC)
+    async fn upload_chunked(
+        &self,
+        request: Request<tonic::Streaming<ChunkedMessage>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        use chunked_message::Payload;
+
+        info!("Received deduplicated upload request...");
+        self.chunk_index()?;
+        let mut stream = request.into_inner();
+
+        let header = match stream.message().await?.and_then(|m| m.payload) {
+            Some(Payload::Header(header)) => header,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Upload stream must start with a header",
+                ))
+            }
+        };
+        let codec = upload_codec(&header)?;
+
+        let mut sink = match self.begin_upload(&header, Some(header.size)).await? {
+            UploadStart::Receive(sink) => sink,
+            UploadStart::Skipped(status) => return Ok(Response::new(status)),
+        };
+        let mut reused = 0u64;
+
+        loop {
+            match stream.message().await?.and_then(|m| m.payload) {
+                Some(Payload::ChunkHash(hash)) => {
+                    let data = self.read_chunk(&hash).await?;
+                    sink.write(&data).await?;
+                    reused += data.len() as u64;
+                }
+                Some(Payload::Data(data)) => sink.write(&decode_frame(codec, data)?).await?,
+                Some(Payload::Trailer(trailer)) => {
+                    let mut status = sink.finish(Some(&trailer.sha256)).await?;
+                    info!(
+                        "Deduplicated upload of {} reused {} bytes of existing chunks",
+                        header.filename, reused
+                    );
+                    status.message = format!(
+                        "{} Reused {} bytes of existing chunks.",
+                        status.message, reused
+                    );
+                    return Ok(Response::new(status));
+                }
+                Some(Payload::Header(_)) => {
+                    return Err(Status::invalid_argument("Duplicate upload header"));
+                }
+                None => {
+                    warn!("Upload of {} ended without a trailer", header.filename);
+                    return Err(Status::aborted("Upload ended before the trailer"));
+                }
+            }
+        }
+    }
+
+    /// 9. Hash a file inside the sandbox (Unary RPC)
+    async fn hash_file(
+        &self,
+        request: Request<HashFileRequest>,
+    ) -> Result<Response<HashFileResponse>, Status> {
+        let req = request.into_inner();
+        let (path, file, metadata) = self.open_regular_file(&req.path)?;
+        drop(file);
+        let sha256 = tokio::task::spawn_blocking(move || checksum::sha256_file(&path))
+            .await
+            .map_err(|e| Status::internal(format!("Hash task failed: {}", e)))?
+            .map_err(|e| Status::internal(format!("Could not hash file: {}", e)))?;
+        Ok(Response::new(HashFileResponse {
+            sha256,
+            size: metadata.len(),
+        }))
+    }
+
+    /// 10. Delete a file or directory inside the sandbox (Unary RPC)
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let req = request.into_inner();
+        let rel = Path::new(req.path.trim_start_matches('/'));
+        if !is_plain_relative(rel) {
+            return Err(outside_sandbox_status());
+        }
+        // Resolve only the parent, so a symlink is removed itself instead of its target.
+        let (Some(parent), Some(name)) = (rel.parent(), rel.file_name()) else {
+            return Err(Status::invalid_argument("Cannot delete the shared root"));
+        };
+        let target = self
+            .resolve_sandboxed(&parent.to_string_lossy())?
+            .join(name);
+        let metadata = fs::symlink_metadata(&target).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Status::not_found(format!("Path not found: {}", req.path))
+            } else {
+                Status::internal(format!("Could not inspect {}: {}", req.path, e))
+            }
+        })?;
+
+        // Do not pull a file out from under a running upload.
+        let _lock = UploadLock::acquire(&self.active_uploads, target.clone())?;
+        let result = if !metadata.is_dir() {
+            fs::remove_file(&target).await
+        } else if req.recursive {
+            fs::remove_dir_all(&target).await
+        } else {
+            fs::remove_dir(&target).await
+        };
+        if let Err(e) = result {
+            error!("Failed to delete {}: {}", target.display(), e);
+            return Err(match e.kind() {
+                std::io::ErrorKind::PermissionDenied => {
+                    Status::permission_denied(format!("Cannot delete {}: {}", req.path, e))
+                }
+                _ => Status::failed_precondition(format!("Cannot delete {}: {}", req.path, e)),
+            });
+        }
+        info!("Deleted {}", target.display());
+        if let Some(chunks) = self.chunks.as_ref().filter(|_| metadata.is_file()) {
+            chunks.index_later(target);
+        }
+        if let Some(quota) = &self.quota {
+            if metadata.is_dir() {
+                quota.invalidate().await;
+            } else if metadata.is_file() {
+                quota.record(0, metadata.len()).await;
+            }
+        }
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    /// 11. Push changes inside a sandboxed directory until the client cancels
+    /// (Server Streaming RPC)
+    async fn watch_dir(
+        &self,
+        request: Request<WatchDirRequest>,
+    ) -> Result<Response<Self::WatchDirStream>, Status> {
+        let req = request.into_inner();
+        let dir = self.resolve_sandboxed(&req.path)?;
+        if !dir.is_dir() {
+            return Err(Status::invalid_argument(format!(
+                "Not a directory: {}",
+                req.path
+            )));
+        }
+        let root = self
+            .base_path
+            .canonicalize()
+            .map_err(|e| Status::internal(format!("Server base directory is invalid: {}", e)))?;
+
+        let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = raw_tx.send(event);
+        })
+        .map_err(|e| Status::internal(format!("Cannot watch {}: {}", req.path, e)))?;
+        let mode = if req.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(&dir, mode)
+            .map_err(|e| Status::internal(format!("Cannot watch {}: {}", req.path, e)))?;
+        info!("Watching {} for a client", dir.display());
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            // Keep the watcher alive for as long as the stream is open.
+            let _watcher = watcher;
+            let mut batcher = dir_watch::ChangeBatcher::new(root);
+            let mut flush = tokio::time::interval(dir_watch::FLUSH_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    event = raw_rx.recv() => match event {
+                        Some(Ok(event)) => batcher.push(event),
+                        Some(Err(e)) => warn!("Watch of {} failed: {}", dir.display(), e),
+                        None => break,
+                    },
+                    _ = flush.tick() => {
+                        if let Some(batch) = batcher.take() {
+                            if tx.send(Ok(batch)).await.is_err() {
+                                break;
+                            }
+                        }
+                        if !dir.is_dir() {
+                            let _ = tx
+                                .send(Err(Status::not_found(format!(
+                                    "Watched directory was removed: {}",
+                                    req.path
+                                ))))
+                                .await;
+                            break;
+                        }
+                    }
+                }
+            }
+            info!("Stopped watching {}", dir.display());
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 12. Create or replace a symlink inside the sandbox (Unary RPC)
+    async fn create_symlink(
+        &self,
+        request: Request<CreateSymlinkRequest>,
+    ) -> Result<Response<CreateSymlinkResponse>, Status> {
+        let req = request.into_inner();
+        let rel = Path::new(req.path.trim_start_matches('/'));
+        let target = Path::new(&req.target);
+        if !is_plain_relative(rel) {
+            return Err(outside_sandbox_status());
+        }
+        let (Some(parent), Some(name)) = (rel.parent(), rel.file_name()) else {
+            return Err(Status::invalid_argument("Cannot replace the shared root"));
+        };
+        if req.target.is_empty() || !target.is_relative() {
+            return Err(Status::invalid_argument(
+                "Symlink target must be a relative path",
+            ));
+        }
+
+        let parent_dir = self.base_path.join(parent);
+        fs::create_dir_all(&parent_dir)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create directory: {}", e)))?;
+        let dir = self.resolve_sandboxed(&parent.to_string_lossy())?;
+        if symlink::resolve_inside(&self.canonical_base()?, &dir, target).is_none() {
+            warn!(
+                "Rejected symlink {} -> {} leaving the sandbox",
+                req.path, req.target
+            );
+            return Err(outside_sandbox_status());
+        }
+
+        let link = dir.join(name);
+        let _lock = UploadLock::acquire(&self.active_uploads, link.clone())?;
+        let existing = fs::symlink_metadata(&link).await.ok();
+        if existing.as_ref().is_some_and(|m| m.is_dir()) {
+            return Err(Status::failed_precondition(format!(
+                "Cannot replace directory {} with a symlink",
+                req.path
+            )));
+        }
+        // Create under a temp name and rename over any existing entry, so
+        // the path never disappears in between.
+        let temp = dir.join(format!(
+            ".{}.{}{}",
+            name.to_string_lossy(),
+            Uuid::new_v4(),
+            protocol::PARTIAL_SUFFIX
+        ));
+        create_symlink(target, &temp).await.map_err(|e| {
+            error!("Failed to create symlink {}: {}", temp.display(), e);
+            Status::internal(format!("Could not create symlink: {}", e))
+        })?;
+        if let Err(e) = fs::rename(&temp, &link).await {
+            let _ = fs::remove_file(&temp).await;
+            error!("Failed to move symlink into place: {}", e);
+            return Err(Status::internal(format!("Could not create symlink: {}", e)));
+        }
+        info!("Created symlink {} -> {}", link.display(), req.target);
+        if let Some(chunks) = self
+            .chunks
+            .as_ref()
+            .filter(|_| existing.is_some_and(|m| m.is_file()))
+        {
+            chunks.index_later(link);
+        }
+        Ok(Response::new(CreateSymlinkResponse {}))
+    }
+
+    /// 13. Report the capacity of the file system behind a path and the
+    /// share's quota (Unary RPC)
+    async fn disk_usage(
+        &self,
+        request: Request<DiskUsageRequest>,
+    ) -> Result<Response<DiskUsageResponse>, Status> {
+        let req = request.into_inner();
+        let path = self.resolve_sandboxed(&req.path)?;
+        let fs_space = space::filesystem_space(&path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::Unsupported {
+                Status::unimplemented(e.to_string())
+            } else {
+                Status::internal(format!("Could not query free space: {}", e))
+            }
+        })?;
+        let (quota_bytes, quota_used_bytes) = match &self.quota {
+            Some(quota) => (quota.limit(), quota.used().await),
+            None => (0, 0),
+        };
+        Ok(Response::new(DiskUsageResponse {
+            total_bytes: fs_space.total,
+            free_bytes: fs_space.free,
+            available_bytes: fs_space.available,
+            quota_bytes,
+            quota_used_bytes,
+        }))
+    }
+
+    /// 14. Sum the sizes under a path, streaming running totals until done
+    /// (Server Streaming RPC)
+    async fn dir_usage(
+        &self,
+        request: Request<DirUsageRequest>,
+    ) -> Result<Response<Self::DirUsageStream>, Status> {
+        let req = request.into_inner();
+        let path = self.resolve_sandboxed(&req.path)?;
+        let progress = |totals: &space::DirTotals, complete| DirUsageProgress {
+            bytes: totals.bytes,
+            files: totals.files,
+            dirs: totals.dirs,
+            errors: totals.errors,
+            complete,
+        };
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            info!("Summing sizes under {}", path.display());
+            // Stops as soon as the client is gone.
+            let totals = space::summarize(&path, |totals| {
+                tx.blocking_send(Ok(progress(totals, false))).is_ok()
+            });
+            let _ = tx.blocking_send(Ok(progress(&totals, true)));
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 15. Walk a directory tree and stream the entries matching the filters
+    /// as they are found (Server Streaming RPC)
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<Self::SearchStream>, Status> {
+        let req = request.into_inner();
+        let root = self.resolve_sandboxed(&req.root)?;
+        if !root.is_dir() {
+            return Err(Status::invalid_argument(format!(
+                "Not a directory: {}",
+                req.root
+            )));
+        }
+        let base = self.canonical_base()?;
+        let filter = SearchFilter::new(&req).map_err(|e| {
+            Status::invalid_argument(format!("Bad pattern \"{}\": {}", req.pattern, e))
+        })?;
+        let limit = match req.max_results {
+            0 => protocol::MAX_SEARCH_RESULTS,
+            n => n.min(protocol::MAX_SEARCH_RESULTS),
+        };
+
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            info!("Searching {} for \"{}\"", root.display(), req.pattern);
+            let mut found = 0;
+            let mut pending = vec![root.clone()];
+            'walk: while let Some(dir) = pending.pop() {
+                // Stops as soon as the client is gone, even between matches.
+                if tx.is_closed() {
+                    break;
+                }
+                let mut read_dir = match fs::read_dir(&dir).await {
+                    Ok(read_dir) => read_dir,
+                    Err(e) => {
+                        warn!("Search cannot read {}: {}", dir.display(), e);
+                        continue;
+                    }
+                };
+                while let Ok(Some(entry)) = read_dir.next_entry().await {
+                    let Ok(metadata) = entry.metadata().await else {
+                        continue;
+                    };
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if protocol::is_partial(&name) {
+                        continue;
+                    }
+                    let path = entry.path();
+                    // Symlinks are reported but never followed.
+                    if metadata.is_dir() {
+                        pending.push(path.clone());
+                    }
+                    if !filter.matches(&name, &metadata) {
+                        continue;
+                    }
+                    let relative = path
+                        .strip_prefix(&base)
+                        .unwrap_or(&path)
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    let entry = dir_entry(name, &path, &metadata, &base, &dir).await;
+                    let found_match = SearchMatch {
+                        path: relative,
+                        entry: Some(entry),
+                    };
+                    if tx.send(Ok(found_match)).await.is_err() {
+                        break 'walk;
+                    }
+                    found += 1;
+                    if found >= limit {
+                        break 'walk;
+                    }
+                }
+            }
+            info!("Search of {} ended with {} matches", root.display(), found);
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 16. List a directory in pages of sorted, filtered entries until done
+    /// or the client cancels (Server Streaming RPC)
+    async fn list_dir_stream(
+        &self,
+        request: Request<ListDirRequest>,
+    ) -> Result<Response<Self::ListDirStreamStream>, Status> {
+        let req = request.into_inner();
+        let listing = self.read_listing(&req).await?;
+        let page_size = match req.page_size {
+            0 => protocol::DEFAULT_LIST_PAGE,
+            n => n.min(protocol::MAX_LIST_PAGE),
+        } as usize;
+
+        let (tx, rx) = mpsc::channel(2);
+        tokio::spawn(async move {
+            let total = listing.names.len() as u64;
+            let mut pages = listing.names[listing.start..].chunks(page_size).peekable();
+            let mut first = true;
+            loop {
+                let page = match pages.next() {
+                    Some(page) => page,
+                    // An empty directory still gets one page, with its total.
+                    None if first => &[],
+                    None => break,
+                };
+                first = false;
+                let next_cursor = match page.last() {
+                    Some(last) if pages.peek().is_some() => last.cursor(),
+                    _ => String::new(),
+                };
+                let response = ListDirResponse {
+                    entries: listing_entries(page, &listing.dir, &listing.base).await,
+                    next_cursor,
+                    total,
+                };
+                // Stops as soon as the client is gone.
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(unix)]
+async fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    fs::symlink(target, link).await
+}
+
+#[cfg(not(unix))]
+async fn create_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::filerpc::file_service_client::FileServiceClient;
+    use crate::filerpc::file_service_server::FileServiceServer;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    /// Serves `service` on a free local port and returns its URL.
+    pub(crate) async fn serve(service: MyFileService) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(FileServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        url
+    }
+
+    fn header(filename: &str, policy: ConflictPolicy) -> UploadHeader {
+        UploadHeader {
+            filename: filename.to_string(),
+            conflict_policy: policy as i32,
+            ..Default::default()
+        }
+    }
+
+    /// Starts an upload and returns where it would be written, or the skip reply.
+    async fn start(
+        service: &MyFileService,
+        header: &UploadHeader,
+    ) -> Result<Result<(String, UploadAction), UploadStatus>, Status> {
+        Ok(
+            match service.begin_upload(header, Some(header.size)).await? {
+                UploadStart::Receive(sink) => Ok((sink.final_name.clone(), sink.action)),
+                UploadStart::Skipped(status) => Err(status),
+            },
+        )
+    }
+
+    #[test]
+    fn rename_candidates_keep_the_extension() {
+        assert_eq!(renamed_candidate("report.pdf", 1), "report (1).pdf");
+        assert_eq!(renamed_candidate("backup.tar.gz", 2), "backup.tar (2).gz");
+        assert_eq!(renamed_candidate("Makefile", 3), "Makefile (3)");
+        // Dotfiles have no extension, so the number goes at the end.
+        assert_eq!(renamed_candidate(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(renamed_candidate(".config.json", 1), ".config (1).json");
+        // An existing number is part of the name, not counted up.
+        assert_eq!(renamed_candidate("report (1).pdf", 2), "report (1) (2).pdf");
+    }
+
+    #[tokio::test]
+    async fn overwrite_replaces_an_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(dir.path().to_path_buf());
+        let new = header("a.txt", ConflictPolicy::Overwrite);
+        assert_eq!(
+            start(&service, &new).await.unwrap().unwrap(),
+            ("a.txt".to_string(), UploadAction::Created)
+        );
+
+        std::fs::write(dir.path().join("a.txt"), b"old").unwrap();
+        for policy in [ConflictPolicy::Overwrite, ConflictPolicy::Unspecified] {
+            assert_eq!(
+                start(&service, &header("a.txt", policy))
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                ("a.txt".to_string(), UploadAction::Overwritten)
+            );
+        }
+        let failed = start(&service, &header("a.txt", ConflictPolicy::Fail)).await;
+        assert_eq!(failed.unwrap_err().code(), tonic::Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn skip_policies_leave_the_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(dir.path().to_path_buf());
+        let existing = dir.path().join("a.txt");
+        std::fs::write(&existing, b"same").unwrap();
+
+        let mut identical = header("a.txt", ConflictPolicy::SkipIfIdentical);
+        identical.size = 4;
+        identical.sha256 = Sha256::digest(b"same").to_vec();
+        let skipped = start(&service, &identical).await.unwrap().unwrap_err();
+        assert_eq!(skipped.action, UploadAction::Skipped as i32);
+        assert_eq!(skipped.final_name, "a.txt");
+
+        identical.sha256 = Sha256::digest(b"diff").to_vec();
+        assert_eq!(
+            start(&service, &identical).await.unwrap().unwrap().1,
+            UploadAction::Overwritten
+        );
+        identical.sha256.clear();
+        let missing_digest = start(&service, &identical).await.unwrap_err();
+        assert_eq!(missing_digest.code(), tonic::Code::InvalidArgument);
+
+        let mtime = file_meta::mtime_secs(&std::fs::metadata(&existing).unwrap());
+        let mut newer = header("a.txt", ConflictPolicy::OverwriteIfNewer);
+        newer.mtime = mtime;
+        assert!(start(&service, &newer).await.unwrap().is_err());
+        newer.mtime = mtime + 1;
+        assert_eq!(
+            start(&service, &newer).await.unwrap().unwrap().1,
+            UploadAction::Overwritten
+        );
+        assert_eq!(std::fs::read(&existing).unwrap(), b"same");
+    }
+
+    #[tokio::test]
+    async fn rename_picks_the_first_free_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(dir.path().to_path_buf());
+        let rename = |name| header(name, ConflictPolicy::Rename);
+        assert_eq!(
+            start(&service, &rename("a.txt")).await.unwrap().unwrap(),
+            ("a.txt".to_string(), UploadAction::Created)
+        );
+
+        std::fs::write(dir.path().join("a.txt"), b"").unwrap();
+        std::fs::write(dir.path().join("a (1).txt"), b"").unwrap();
+        assert_eq!(
+            start(&service, &rename("a.txt")).await.unwrap().unwrap(),
+            ("a (2).txt".to_string(), UploadAction::Renamed)
+        );
+
+        std::fs::write(dir.path().join(".env"), b"").unwrap();
+        assert_eq!(
+            start(&service, &rename(".env")).await.unwrap().unwrap(),
+            (".env (1)".to_string(), UploadAction::Renamed)
+        );
+
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        let over_dir = start(&service, &rename("docs")).await.unwrap_err();
+        assert_eq!(over_dir.code(), tonic::Code::AlreadyExists);
+    }
+
+    fn upload_frames(
+        header: UploadHeader,
+        data: &[&[u8]],
+        trailer: Option<Vec<u8>>,
+    ) -> impl tokio_stream::Stream<Item = UploadMessage> {
+        let frame = |payload| UploadMessage {
+            payload: Some(payload),
+        };
+        let mut frames = vec![frame(Payload::Header(header))];
+        frames.extend(data.iter().map(|d| frame(Payload::Data(d.to_vec()))));
+        frames.extend(trailer.map(|sha256| frame(Payload::Trailer(UploadTrailer { sha256 }))));
+        tokio_stream::iter(frames)
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_return_their_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(dir.path().to_path_buf()).with_quota(Some(100));
+        let quota = service.quota.clone().unwrap();
+        let mut client = FileServiceClient::connect(serve(service).await)
+            .await
+            .unwrap();
+        let mut upload = header("a.bin", ConflictPolicy::Fail);
+        upload.size = 80;
+        let data = [0u8; 80];
+
+        // Wrong digest: the data arrives but is discarded.
+        let wrong = Sha256::digest(b"other").to_vec();
+        let corrupt = client
+            .upload(upload_frames(upload.clone(), &[&data], Some(wrong)))
+            .await
+            .unwrap_err();
+        assert_eq!(corrupt.code(), tonic::Code::DataLoss);
+        assert_eq!(quota.used().await, 0);
+
+        // The stream ends before the trailer, as when the client goes away.
+        let aborted = client
+            .upload(upload_frames(upload.clone(), &[&data[..40]], None))
+            .await
+            .unwrap_err();
+        assert_eq!(aborted.code(), tonic::Code::Aborted);
+        assert_eq!(quota.used().await, 0);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // All 100 bytes are free again; the stored file then counts instead.
+        let digest = Sha256::digest(data).to_vec();
+        let status = client
+            .upload(upload_frames(upload, &[&data], Some(digest)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.action, UploadAction::Created as i32);
+        assert_eq!(quota.used().await, 80);
+    }
+
+    #[tokio::test]
+    async fn v1_uploads_cannot_exceed_their_declared_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = MyFileService::new(dir.path().to_path_buf()).with_quota(Some(100));
+        let quota = service.quota.clone().unwrap();
+        let mut client = FileServiceClient::connect(serve(service).await)
+            .await
+            .unwrap();
+        let chunk = |data: &[u8], eof| FileChunk {
+            filename: "a.bin".to_string(),
+            data: data.to_vec(),
+            eof,
+            file_size: 4,
+            ..Default::default()
+        };
+
+        let oversize = client
+            .upload_file(tokio_stream::iter([
+                chunk(b"abc", false),
+                chunk(b"de", true),
+            ]))
+            .await
+            .unwrap_err();
+        assert_eq!(oversize.code(), tonic::Code::DataLoss);
+        assert!(!dir.path().join("a.bin").exists());
+        assert_eq!(quota.used().await, 0);
+
+        // Without a declared size nothing would bound the stream.
+        let mut undeclared = chunk(b"abc", true);
+        undeclared.file_size = 0;
+        let refused = client
+            .upload_file(tokio_stream::iter([undeclared]))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::FailedPrecondition);
+
+        let status = client
+            .upload_file(tokio_stream::iter([
+                chunk(b"ab", false),
+                chunk(b"cd", true),
+            ]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(status.success);
+        assert_eq!(std::fs::read(dir.path().join("a.bin")).unwrap(), b"abcd");
+    }
+}
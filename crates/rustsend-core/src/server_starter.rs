@@ -0,0 +1,164 @@
+// src/server_starter.rs
+
+use crate::file_meta::PreservePolicy;
+use crate::filerpc::file_service_server::FileServiceServer;
+use crate::server;
+use log::{error, info};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Status};
+
+// --- Configuration Constants ---
+// 注意：我们将不再使用 BASE_UPLOAD_DIR，而是使用 dirs::home_dir()
+
+pub const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:50051";
+
+/// 文件服务的可选配置
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// 收到的文件恢复哪些元数据
+    pub preserve: PreservePolicy,
+    /// 共享目录总大小上限 (字节)，超出的上传在接收前被拒绝
+    pub quota: Option<u64>,
+    /// 接收前按声明大小预分配文件 (仅 Linux)
+    pub preallocate: bool,
+    /// 为共享目录建立内容块索引以支持去重上传；索引常驻内存，默认关闭
+    pub chunk_index: bool,
+    /// 访问令牌：设置后 FileService 只接受携带 `authorization: Bearer <token>` 的请求
+    pub token: Option<String>,
+}
+
+/// 校验请求的访问令牌；未配置令牌时放行所有请求
+fn check_token(expected: Option<&str>, request: Request<()>) -> Result<Request<()>, Status> {
+    let Some(expected) = expected else {
+        return Ok(request);
+    };
+    let presented = request
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // 逐字节比较全部内容，避免按前缀长短泄露时间差
+    let valid = presented.is_some_and(|token| {
+        token.len() == expected.len()
+            && token
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    });
+    if valid {
+        Ok(request)
+    } else {
+        Err(Status::unauthenticated("Missing or invalid access token"))
+    }
+}
+
+/// 设置后 GUI 内置的文件服务要求此访问令牌 (与 `rustsend serve` 相同的环境变量)
+pub const TOKEN_ENV: &str = "RUSTSEND_TOKEN";
+
+/// 初始化并启动 gRPC 文件服务，在后台运行。
+/// 访问令牌取自环境变量 `RUSTSEND_TOKEN`，未设置或为空时接受所有请求。
+pub async fn start_background_server() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let addr: SocketAddr = DEFAULT_LISTEN_ADDR.parse()?;
+
+    // --- 关键修复：获取用户 Home 目录 ---
+    let base_path: PathBuf = match dirs::home_dir() {
+        Some(path) => {
+            info!("Found user home directory: {}", path.display());
+            path
+        }
+        None => {
+            // 如果无法获取 Home 目录，回退到默认的 "./data"
+            error!("Could not determine user's home directory. Defaulting to './data'.");
+            PathBuf::from("./data")
+        }
+    };
+    // ------------------------------------
+
+    let options = ServerOptions {
+        token: std::env::var(TOKEN_ENV)
+            .ok()
+            .filter(|token| !token.is_empty()),
+        ..ServerOptions::default()
+    };
+    if options.token.is_some() {
+        info!("Access token required ({} is set)", TOKEN_ENV);
+    }
+    start_server(addr, base_path, options).await
+}
+
+/// 在指定地址启动 gRPC 文件服务，以 `base_path` 作为沙箱根目录 (GUI 与 CLI 共用)。
+pub async fn start_server(
+    addr: SocketAddr,
+    base_path: PathBuf,
+    options: ServerOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // 确保 base_path 存在。对于 Home 目录，它通常是存在的，但为了安全起见，仍然检查。
+    if !base_path.exists() {
+        tokio::fs::create_dir_all(&base_path).await.map_err(|e| {
+            error!(
+                "Failed to create base directory (or default data dir): {}",
+                e
+            );
+            e
+        })?;
+        info!("Base directory created/verified: {}", base_path.display());
+    }
+
+    // 实例化 gRPC 服务实现，将 Home 目录作为根路径
+    let file_service = server::MyFileService::new(base_path)
+        .with_preserve(options.preserve)
+        .with_quota(options.quota)
+        .with_preallocate(options.preallocate)
+        .with_chunk_index(options.chunk_index);
+    // 启用时在后台为共享目录中的文件建立内容块索引，供去重上传使用
+    file_service.start_chunk_indexing();
+
+    // 标准 grpc.health.v1.Health 服务：FileService 开始监听后才报告 SERVING
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_not_serving::<FileServiceServer<server::MyFileService>>()
+        .await;
+
+    // gRPC reflection，让 grpcurl 等通用工具可以发现 filerpc.FileService
+    // 同时注册 v1 与 v1alpha，兼容较旧的工具
+    let reflection_v1 = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(crate::filerpc::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1()?;
+    let reflection_v1alpha = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(crate::filerpc::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1alpha()?;
+
+    // 先绑定端口，确认可以接受连接后再把健康状态置为 SERVING
+    let listener = TcpListener::bind(addr).await?;
+    info!("gRPC File service is listening on: {}", addr);
+    health_reporter
+        .set_serving::<FileServiceServer<server::MyFileService>>()
+        .await;
+
+    // 启动 gRPC Server
+    let result = Server::builder()
+        .add_service(health_service)
+        .add_service(reflection_v1)
+        .add_service(reflection_v1alpha)
+        .add_service(FileServiceServer::with_interceptor(
+            file_service,
+            move |request| check_token(options.token.as_deref(), request),
+        ))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await;
+
+    // 服务退出后不再对外报告 SERVING
+    health_reporter
+        .set_not_serving::<FileServiceServer<server::MyFileService>>()
+        .await;
+    result?;
+
+    Ok(())
+}
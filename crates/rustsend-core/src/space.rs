@@ -0,0 +1,312 @@
+// rustsend-core/src/space.rs
+
+//! Disk space: pre-flight checks for uploads, file system capacity and
+//! directory sizes.
+//!
+//! Uploads declare their size in the header, so the server can refuse one
+//! that cannot fit before a single byte is written, instead of failing
+//! midway with a partial file. Two limits apply: the free space of the file
+//! system holding the target, and an optional quota on the whole share.
+//!
+//! Share usage is measured by walking the share, which is too slow to do for
+//! every upload; the result is cached for `USAGE_TTL` and kept current by the
+//! uploads and deletions the server performs itself. Uploads in flight hold a
+//! `QuotaReservation` for their declared size, so concurrent uploads cannot
+//! overrun the quota together.
+
+use log::{info, warn};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::protocol;
+
+/// How long a measured share usage is trusted before the share is walked
+/// again to pick up changes made outside the server.
+pub const USAGE_TTL: Duration = Duration::from_secs(60);
+
+/// How often `summarize` reports its running totals.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Capacity of a file system, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct FsSpace {
+    pub total: u64,
+    /// Includes blocks reserved for root.
+    pub free: u64,
+    /// What unprivileged users can still write.
+    pub available: u64,
+}
+
+/// Capacity of the file system holding `path`.
+#[cfg(unix)]
+pub fn filesystem_space(path: &Path) -> io::Result<FsSpace> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out-pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let block = stat.f_frsize as u64;
+    #[allow(clippy::unnecessary_cast)]
+    Ok(FsSpace {
+        total: stat.f_blocks as u64 * block,
+        free: stat.f_bfree as u64 * block,
+        available: stat.f_bavail as u64 * block,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn filesystem_space(_path: &Path) -> io::Result<FsSpace> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is not available on this platform",
+    ))
+}
+
+/// Bytes available to unprivileged users on the file system holding `path`.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    filesystem_space(path).map(|space| space.available)
+}
+
+/// Allocates `len` bytes for `file` up front, so writing them later cannot
+/// run out of space. `Unsupported` where the file system or platform cannot.
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &impl std::os::fd::AsRawFd, len: u64) -> io::Result<()> {
+    // SAFETY: the descriptor is open for the lifetime of `file`.
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
+    if result == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EOPNOTSUPP) => Err(io::Error::new(io::ErrorKind::Unsupported, error)),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate<F>(_file: &F, _len: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "preallocation is not available on this platform",
+    ))
+}
+
+/// An upload that would take the share over its quota.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub used: u64,
+    pub limit: u64,
+}
+
+/// Size of the share as of the last walk, adjusted since.
+struct Measured {
+    at: Instant,
+    bytes: u64,
+}
+
+/// Quota accounting of one share.
+pub struct ShareUsage {
+    base: PathBuf,
+    limit: u64,
+    /// Also serializes reservations, so checking and reserving is atomic.
+    measured: Mutex<Option<Measured>>,
+    /// Declared sizes of the uploads in flight.
+    reserved: AtomicU64,
+}
+
+impl std::fmt::Debug for ShareUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShareUsage")
+            .field("base", &self.base)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl ShareUsage {
+    /// Quota of `limit` bytes on everything under `base`.
+    pub fn new(base: PathBuf, limit: u64) -> Arc<Self> {
+        Arc::new(ShareUsage {
+            base,
+            limit,
+            measured: Mutex::new(None),
+            reserved: AtomicU64::new(0),
+        })
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Bytes counted against the quota, including uploads in flight.
+    pub async fn used(&self) -> u64 {
+        let mut measured = self.measured.lock().await;
+        self.stored(&mut measured).await + self.reserved.load(Ordering::SeqCst)
+    }
+
+    /// Reserves room for an upload of `size` bytes that replaces `replaced`
+    /// bytes of an existing file, or reports how full the share is.
+    pub async fn reserve(
+        self: &Arc<Self>,
+        size: u64,
+        replaced: u64,
+    ) -> Result<QuotaReservation, QuotaExceeded> {
+        let mut measured = self.measured.lock().await;
+        let used = self.stored(&mut measured).await + self.reserved.load(Ordering::SeqCst);
+        // The replaced file stays until the upload is renamed over it, but
+        // the quota only has to hold the end result.
+        if used.saturating_sub(replaced).saturating_add(size) > self.limit {
+            return Err(QuotaExceeded {
+                used,
+                limit: self.limit,
+            });
+        }
+        self.reserved.fetch_add(size, Ordering::SeqCst);
+        Ok(QuotaReservation {
+            usage: self.clone(),
+            size,
+        })
+    }
+
+    /// Accounts for a change the server made: `added` bytes written and
+    /// `removed` bytes replaced or deleted.
+    pub async fn record(&self, added: u64, removed: u64) {
+        if let Some(measured) = self.measured.lock().await.as_mut() {
+            measured.bytes = (measured.bytes + added).saturating_sub(removed);
+        }
+    }
+
+    /// Forgets the measured usage, e.g. after a deletion of unknown size;
+    /// the next reservation walks the share again.
+    pub async fn invalidate(&self) {
+        *self.measured.lock().await = None;
+    }
+
+    /// Size of the stored files, walking the share again if the last
+    /// measurement is stale.
+    async fn stored(&self, measured: &mut Option<Measured>) -> u64 {
+        if let Some(m) = measured.as_ref().filter(|m| m.at.elapsed() <= USAGE_TTL) {
+            return m.bytes;
+        }
+        let base = self.base.clone();
+        let bytes = tokio::task::spawn_blocking(move || summarize(&base, |_| true).bytes)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Share usage walk failed: {}", e);
+                0
+            });
+        info!("Share usage: {} of {} bytes", bytes, self.limit);
+        *measured = Some(Measured {
+            at: Instant::now(),
+            bytes,
+        });
+        bytes
+    }
+}
+
+/// Room held for one upload in flight; released when dropped.
+pub struct QuotaReservation {
+    usage: Arc<ShareUsage>,
+    size: u64,
+}
+
+impl QuotaReservation {
+    pub fn usage(&self) -> &ShareUsage {
+        &self.usage
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        self.usage.reserved.fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+/// Running totals of a `summarize` walk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirTotals {
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    /// Directories that could not be read.
+    pub errors: u64,
+}
+
+/// Sums the regular files under `dir` (or just `dir`, if it is a file), not
+/// following symlinks and leaving out partial uploads. Every
+/// `PROGRESS_INTERVAL` the totals so far are passed to `progress`, which
+/// returns false to stop the walk early. Blocking.
+pub fn summarize(dir: &Path, mut progress: impl FnMut(&DirTotals) -> bool) -> DirTotals {
+    let mut totals = DirTotals::default();
+    if let Some(metadata) = std::fs::metadata(dir).ok().filter(|m| m.is_file()) {
+        totals.files = 1;
+        totals.bytes = metadata.len();
+        return totals;
+    }
+    let mut last_report = Instant::now();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(read_dir) = std::fs::read_dir(&dir) else {
+            totals.errors += 1;
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                totals.dirs += 1;
+                pending.push(entry.path());
+            } else if metadata.is_file()
+                // Temp files are covered by the reservation of their upload.
+                && !protocol::is_partial(&entry.file_name().to_string_lossy())
+            {
+                totals.files += 1;
+                totals.bytes += metadata.len();
+            }
+        }
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            if !progress(&totals) {
+                break;
+            }
+        }
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reservations_hold_room_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("old.bin"), [0u8; 30]).unwrap();
+        let usage = ShareUsage::new(dir.path().to_path_buf(), 100);
+        assert_eq!(usage.used().await, 30);
+
+        let first = usage.reserve(60, 0).await.unwrap();
+        assert_eq!(usage.used().await, 90);
+        let Err(full) = usage.reserve(20, 0).await else {
+            panic!("reserved beyond the quota");
+        };
+        assert_eq!((full.used, full.limit), (90, 100));
+        // Replacing old.bin only needs room for the difference.
+        let replacing = usage.reserve(20, 30).await.unwrap();
+
+        drop(first);
+        drop(replacing);
+        assert_eq!(usage.used().await, 30);
+        assert!(usage.reserve(70, 0).await.is_ok());
+    }
+}
@@ -0,0 +1,161 @@
+// rustsend-core/src/symlink.rs
+
+//! Symlink handling shared by listings, the server and directory transfers.
+//!
+//! Link targets are checked lexically against a root (the share on the
+//! server, the synced directory on the client): a link whose target leaves
+//! the root is never shown with its target nor recreated elsewhere. Actually
+//! reaching a file through a link is still guarded by the server's
+//! canonicalizing sandbox check.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// What a directory transfer does with symlinks it meets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Transfer what the link points to, as if it were a regular entry.
+    /// Links that lead back into a directory being walked are skipped.
+    Follow,
+    /// Recreate the link itself, if its target stays inside the transferred
+    /// tree; other links are skipped.
+    Preserve,
+    /// Leave links out entirely.
+    #[default]
+    Skip,
+}
+
+/// Resolves `.` and `..` without touching the file system. `..` at the root
+/// stays at the root, like the kernel does.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                // Leading `..` of a relative path are kept.
+                _ => normalized.push(component),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Where `target`, read from a link inside `link_dir`, points, if that stays
+/// inside `root`. Both directories must be absolute and normalized.
+pub fn resolve_inside(root: &Path, link_dir: &Path, target: &Path) -> Option<PathBuf> {
+    let resolved = normalize(&link_dir.join(target));
+    resolved.starts_with(root).then_some(resolved)
+}
+
+/// The target to show for a link in a listing: as written when relative,
+/// rooted at `/` when absolute, and hidden when it leaves `root`, so a
+/// listing never reveals paths outside the share.
+pub fn display_target(root: &Path, link_dir: &Path, target: &Path) -> Option<String> {
+    let resolved = resolve_inside(root, link_dir, target)?;
+    if target.is_absolute() {
+        let rel = resolved.strip_prefix(root).ok()?;
+        Some(format!("/{}", to_slash(rel)))
+    } else {
+        Some(to_slash(target))
+    }
+}
+
+/// The target to recreate a link with on the other side: relative to the
+/// link's directory and `/`-separated. `None` when it leaves `root`.
+pub fn relative_target(root: &Path, link_dir: &Path, target: &Path) -> Option<String> {
+    let resolved = resolve_inside(root, link_dir, target)?;
+    if !target.is_absolute() {
+        return Some(to_slash(target));
+    }
+    let from = link_dir.strip_prefix(root).ok()?;
+    let to = resolved.strip_prefix(root).ok()?;
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let ups = from.components().count() - common;
+    let mut parts: Vec<String> = vec!["..".to_string(); ups];
+    parts.extend(
+        to.components()
+            .skip(common)
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    Some(if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    })
+}
+
+fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "/share";
+    const DOCS: &str = "/share/docs";
+
+    fn shown(target: &str) -> Option<String> {
+        display_target(Path::new(ROOT), Path::new(DOCS), Path::new(target))
+    }
+
+    fn recreated(target: &str) -> Option<String> {
+        relative_target(Path::new(ROOT), Path::new(DOCS), Path::new(target))
+    }
+
+    #[test]
+    fn normalize_is_lexical() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(normalize(Path::new("a/../../b")), Path::new("../b"));
+        assert_eq!(normalize(Path::new("../../a")), Path::new("../../a"));
+    }
+
+    #[test]
+    fn targets_inside_the_root() {
+        assert_eq!(shown("../img/a.png").as_deref(), Some("../img/a.png"));
+        assert_eq!(recreated("../img/a.png").as_deref(), Some("../img/a.png"));
+        // Absolute targets are shown from the share root but recreated relative.
+        assert_eq!(shown("/share/img/a.png").as_deref(), Some("/img/a.png"));
+        assert_eq!(
+            recreated("/share/img/a.png").as_deref(),
+            Some("../img/a.png")
+        );
+        assert_eq!(recreated("/share/docs/a/b").as_deref(), Some("a/b"));
+        // A link to its own directory.
+        assert_eq!(shown(".").as_deref(), Some("."));
+        assert_eq!(recreated("/share/docs").as_deref(), Some("."));
+        assert_eq!(recreated("..").as_deref(), Some(".."));
+    }
+
+    #[test]
+    fn targets_outside_the_root_are_hidden() {
+        for target in [
+            "../../etc/passwd",
+            "/etc/passwd",
+            "/sharex/a",
+            "../../share2",
+        ] {
+            assert_eq!(shown(target), None, "{}", target);
+            assert_eq!(recreated(target), None, "{}", target);
+        }
+        assert_eq!(
+            resolve_inside(Path::new(ROOT), Path::new(DOCS), Path::new("../a")),
+            Some(PathBuf::from("/share/a"))
+        );
+    }
+}
@@ -0,0 +1,990 @@
+// rustsend-core/src/sync.rs
+
+//! One-way folder sync: compares a local directory with a remote one, builds
+//! a plan of uploads, skips and (in mirror mode) remote deletions, and
+//! carries the plan out through the transfer queue.
+//!
+//! Only files (and, when preserved, symlinks) are transferred; directories
+//! are created on the server as a side effect of uploading into them, so
+//! empty local directories are not reproduced.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::client::{ConflictMode, RemoteClient, UploadMode};
+use crate::error::{Error, Result};
+use crate::symlink::{self, SymlinkPolicy};
+use crate::transfer::{TransferKind, TransferQueue, TransferState};
+use crate::{checksum, file_meta, protocol};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncOptions {
+    /// Compare files of equal size by SHA-256 instead of by mtime.
+    pub compare_hash: bool,
+    /// Delete remote entries that do not exist locally.
+    pub mirror: bool,
+    /// How changed files are uploaded.
+    pub mode: UploadMode,
+    /// What happens to symlinks on either side.
+    pub symlinks: SymlinkPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    Upload,
+    Skip,
+    Delete,
+}
+
+/// Why an item got its action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncReason {
+    /// Not on the server yet.
+    New,
+    SizeChanged,
+    /// The local copy has a later mtime.
+    Newer,
+    /// Same size, different SHA-256 (hash comparison only).
+    ContentChanged,
+    Unchanged,
+    /// A file on one side is a directory on the other.
+    TypeConflict,
+    /// Only exists on the server.
+    NotInSource,
+    /// A followed link leads back into a directory containing it.
+    SymlinkLoop,
+    /// A preserved link points outside the synced directory.
+    SymlinkOutside,
+    /// A followed link points to nothing.
+    BrokenSymlink,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncItem {
+    /// Path relative to both sync roots, with `/` separators.
+    pub path: String,
+    pub is_dir: bool,
+    pub action: SyncAction,
+    pub reason: SyncReason,
+    /// Local size for uploads, remote size otherwise.
+    pub size: u64,
+    /// Set when the upload recreates a symlink with this target.
+    pub symlink_target: Option<String>,
+}
+
+/// Result of comparing the two trees; nothing has been changed yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncPlan {
+    pub local_dir: PathBuf,
+    pub remote_dir: String,
+    pub mode: UploadMode,
+    pub items: Vec<SyncItem>,
+    pub upload_count: usize,
+    pub upload_bytes: u64,
+    pub delete_count: usize,
+    pub skip_count: usize,
+}
+
+/// Outcome of `execute`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub uploaded: usize,
+    pub deleted: usize,
+    pub failed: Vec<SyncFailure>,
+    pub cancelled: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncFailure {
+    pub path: String,
+    pub error_code: Option<&'static str>,
+    /// In the current locale, see `i18n`.
+    pub message: String,
+}
+
+pub(crate) struct Entry {
+    pub is_dir: bool,
+    pub size: u64,
+    pub mtime: i64,
+    /// Target of a link kept as a link (`SymlinkPolicy::Preserve`); empty
+    /// when the server does not show it.
+    pub symlink: Option<String>,
+}
+
+/// Result of walking the local tree.
+pub(crate) struct LocalTree {
+    pub entries: BTreeMap<String, Entry>,
+    /// Links left out, and why.
+    pub skipped: Vec<(String, SyncReason)>,
+}
+
+/// Builds the plan for pushing `local_dir` to `remote_dir`.
+pub async fn plan_push(
+    remote: &RemoteClient,
+    local_dir: &Path,
+    remote_dir: &str,
+    options: SyncOptions,
+) -> Result<SyncPlan> {
+    let remote_dir = remote_dir.trim_matches('/').to_string();
+    let root = local_dir.to_path_buf();
+    let tree = tokio::task::spawn_blocking(move || walk_local(&root, options.symlinks))
+        .await
+        .map_err(|e| Error::io(local_dir, std::io::Error::other(e)))??;
+    let remote_entries = walk_remote(remote, &remote_dir, options.symlinks).await?;
+    info!(
+        "Sync plan: {} local and {} remote entries",
+        tree.entries.len(),
+        remote_entries.len()
+    );
+
+    let mut identical = HashSet::new();
+    if options.compare_hash {
+        for (path, entry) in &tree.entries {
+            if remote_entries
+                .get(path)
+                .is_some_and(|existing| needs_hash(entry, existing))
+            {
+                let local_hash = hash_local(&local_dir.join(path)).await?;
+                let remote_hash = remote.hash_file(join_remote(&remote_dir, path)).await?;
+                if local_hash == remote_hash {
+                    identical.insert(path.clone());
+                }
+            }
+        }
+    }
+    Ok(compare_trees(
+        local_dir,
+        remote_dir,
+        tree,
+        &remote_entries,
+        options,
+        |path| identical.contains(path),
+    ))
+}
+
+/// Files of equal size are only told apart by hashing them.
+fn needs_hash(local: &Entry, remote: &Entry) -> bool {
+    !local.is_dir
+        && !remote.is_dir
+        && local.symlink.is_none()
+        && remote.symlink.is_none()
+        && local.size == remote.size
+}
+
+/// The plan for the walked trees. With `compare_hash`, `same_content` tells
+/// whether a file that `needs_hash` has the same SHA-256 on both sides.
+fn compare_trees(
+    local_dir: &Path,
+    remote_dir: String,
+    tree: LocalTree,
+    remote_entries: &BTreeMap<String, Entry>,
+    options: SyncOptions,
+    same_content: impl Fn(&str) -> bool,
+) -> SyncPlan {
+    let LocalTree {
+        entries: local,
+        skipped,
+    } = tree;
+    let mut items: Vec<SyncItem> = skipped
+        .iter()
+        .map(|(path, reason)| SyncItem {
+            path: path.clone(),
+            is_dir: false,
+            action: SyncAction::Skip,
+            reason: *reason,
+            size: 0,
+            symlink_target: None,
+        })
+        .collect();
+    for (path, entry) in &local {
+        if entry.is_dir {
+            if remote_entries.get(path).is_some_and(|r| !r.is_dir) {
+                items.push(conflict(path, true, options.mirror));
+            }
+            continue;
+        }
+        // Below a directory that is a file on the server; mirror mode
+        // deletes that file first, otherwise the upload could not succeed.
+        if ancestors(path).any(|dir| remote_entries.get(dir).is_some_and(|r| !r.is_dir)) {
+            items.push(if options.mirror {
+                upload(path, entry, SyncReason::New)
+            } else {
+                SyncItem {
+                    path: path.clone(),
+                    is_dir: false,
+                    action: SyncAction::Skip,
+                    reason: SyncReason::TypeConflict,
+                    size: entry.size,
+                    symlink_target: None,
+                }
+            });
+            continue;
+        }
+        let Some(existing) = remote_entries.get(path) else {
+            items.push(upload(path, entry, SyncReason::New));
+            continue;
+        };
+        if existing.is_dir {
+            items.push(conflict(path, false, options.mirror));
+            if options.mirror {
+                items.push(upload(path, entry, SyncReason::TypeConflict));
+            }
+            continue;
+        }
+        // Links are equal when their targets are; sizes and times say nothing.
+        let reason = if entry.symlink.is_some() || existing.symlink.is_some() {
+            if entry.symlink == existing.symlink {
+                SyncReason::Unchanged
+            } else {
+                SyncReason::ContentChanged
+            }
+        } else if existing.size != entry.size {
+            SyncReason::SizeChanged
+        } else if options.compare_hash {
+            if same_content(path) {
+                SyncReason::Unchanged
+            } else {
+                SyncReason::ContentChanged
+            }
+        } else if entry.mtime > existing.mtime {
+            SyncReason::Newer
+        } else {
+            SyncReason::Unchanged
+        };
+        if reason == SyncReason::Unchanged {
+            items.push(SyncItem {
+                path: path.clone(),
+                is_dir: false,
+                action: SyncAction::Skip,
+                reason,
+                size: existing.size,
+                symlink_target: None,
+            });
+        } else {
+            items.push(upload(path, entry, reason));
+        }
+    }
+
+    if options.mirror {
+        // Deleting a directory removes its content, so only the top-most
+        // remote-only path of each subtree is listed. Local links that were
+        // left out keep whatever is on the server in their place.
+        let mut deleted = HashSet::new();
+        let kept: HashSet<&str> = skipped.iter().map(|(path, _)| path.as_str()).collect();
+        for (path, entry) in remote_entries {
+            if ancestors(path).any(|dir| deleted.contains(dir) || kept.contains(dir))
+                || kept.contains(path.as_str())
+            {
+                continue;
+            }
+            match local.get(path) {
+                Some(local) if local.is_dir == entry.is_dir => continue,
+                // Type conflict, already planned above.
+                Some(_) => {
+                    deleted.insert(path.as_str());
+                    continue;
+                }
+                None => {}
+            }
+            deleted.insert(path.as_str());
+            items.push(SyncItem {
+                path: path.clone(),
+                is_dir: entry.is_dir,
+                action: SyncAction::Delete,
+                reason: SyncReason::NotInSource,
+                size: entry.size,
+                symlink_target: None,
+            });
+        }
+    }
+
+    items.sort_by(|a, b| a.path.cmp(&b.path));
+    let count = |action| items.iter().filter(|i| i.action == action).count();
+    SyncPlan {
+        local_dir: local_dir.to_path_buf(),
+        upload_count: count(SyncAction::Upload),
+        upload_bytes: items
+            .iter()
+            .filter(|i| i.action == SyncAction::Upload)
+            .map(|i| i.size)
+            .sum(),
+        delete_count: count(SyncAction::Delete),
+        skip_count: count(SyncAction::Skip),
+        remote_dir,
+        mode: options.mode,
+        items,
+    }
+}
+
+/// Carries out `plan`: deletions first, so a type conflict is cleared before
+/// the upload that replaces it, then all uploads in parallel on `queue`.
+pub async fn execute(plan: &SyncPlan, remote: &RemoteClient, queue: &TransferQueue) -> SyncReport {
+    let mut report = SyncReport::default();
+
+    let deletes: Vec<_> = plan
+        .items
+        .iter()
+        .filter(|item| item.action == SyncAction::Delete)
+        .map(|item| {
+            let kind = TransferKind::Delete {
+                remote_path: join_remote(&plan.remote_dir, &item.path),
+                recursive: true,
+            };
+            (item, queue.enqueue(remote.clone(), kind))
+        })
+        .collect();
+    for (item, handle) in deletes {
+        let info = handle.finished().await;
+        report.record(item, info.state, info.error_code, info.message);
+    }
+
+    let uploads: Vec<_> = plan
+        .items
+        .iter()
+        .filter(|item| item.action == SyncAction::Upload)
+        .map(|item| {
+            if let Some(target) = &item.symlink_target {
+                let kind = TransferKind::Symlink {
+                    remote_path: join_remote(&plan.remote_dir, &item.path),
+                    target: target.clone(),
+                };
+                return (item, queue.enqueue(remote.clone(), kind));
+            }
+            let parent = match item.path.rsplit_once('/') {
+                Some((parent, _)) => join_remote(&plan.remote_dir, parent),
+                None => plan.remote_dir.clone(),
+            };
+            let kind = TransferKind::Upload {
+                local_path: plan.local_dir.join(&item.path),
+                target_dir: parent,
+                conflict_policy: ConflictMode::Overwrite,
+                mode: plan.mode,
+            };
+            (item, queue.enqueue(remote.clone(), kind))
+        })
+        .collect();
+    for (item, handle) in uploads {
+        let info = handle.finished().await;
+        report.record(item, info.state, info.error_code, info.message);
+    }
+
+    info!(
+        "Sync finished: {} uploaded, {} deleted, {} failed, {} cancelled",
+        report.uploaded,
+        report.deleted,
+        report.failed.len(),
+        report.cancelled
+    );
+    report
+}
+
+impl SyncReport {
+    fn record(
+        &mut self,
+        item: &SyncItem,
+        state: TransferState,
+        error_code: Option<&'static str>,
+        message: Option<String>,
+    ) {
+        match state {
+            TransferState::Completed if item.action == SyncAction::Delete => self.deleted += 1,
+            TransferState::Completed => self.uploaded += 1,
+            TransferState::Cancelled => self.cancelled += 1,
+            _ => self.failed.push(SyncFailure {
+                path: item.path.clone(),
+                error_code,
+                message: message.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+fn upload(path: &str, entry: &Entry, reason: SyncReason) -> SyncItem {
+    SyncItem {
+        path: path.to_string(),
+        is_dir: false,
+        action: SyncAction::Upload,
+        reason,
+        size: entry.size,
+        symlink_target: entry.symlink.clone(),
+    }
+}
+
+/// A type conflict is resolved by deleting the remote side in mirror mode and
+/// skipped otherwise. Uploads into a local directory that is a remote file
+/// fail until it is resolved.
+fn conflict(path: &str, local_is_dir: bool, mirror: bool) -> SyncItem {
+    SyncItem {
+        path: path.to_string(),
+        is_dir: !local_is_dir,
+        action: if mirror {
+            SyncAction::Delete
+        } else {
+            SyncAction::Skip
+        },
+        reason: SyncReason::TypeConflict,
+        size: 0,
+        symlink_target: None,
+    }
+}
+
+/// `a/b/c` -> `a/b`, `a`.
+pub(crate) fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(|(i, _)| &path[..i])
+}
+
+pub(crate) fn join_remote(dir: &str, rel: &str) -> String {
+    match (dir.is_empty(), rel.is_empty()) {
+        (true, _) => rel.to_string(),
+        (false, true) => dir.to_string(),
+        (false, false) => format!("{}/{}", dir, rel),
+    }
+}
+
+pub(crate) async fn hash_local(path: &Path) -> Result<Vec<u8>> {
+    let owned = path.to_path_buf();
+    tokio::task::spawn_blocking(move || checksum::sha256_file(&owned))
+        .await
+        .map_err(|e| Error::io(path, std::io::Error::other(e)))?
+        .map_err(|e| Error::io(path, e))
+}
+
+/// Every file and directory under `root`, keyed by relative `/` path, with
+/// symlinks handled as `symlinks` says. Blocking.
+pub(crate) fn walk_local(root: &Path, symlinks: SymlinkPolicy) -> Result<LocalTree> {
+    let mut tree = LocalTree {
+        entries: BTreeMap::new(),
+        skipped: Vec::new(),
+    };
+    let lexical_root = std::path::absolute(root)
+        .map(|path| symlink::normalize(&path))
+        .map_err(|e| Error::io(root, e))?;
+    // Real paths of the directories from the root down to the one being
+    // read; a followed link into one of them would never end.
+    let real_root = root.canonicalize().map_err(|e| Error::io(root, e))?;
+    let mut pending = vec![(root.to_path_buf(), String::new(), vec![real_root])];
+    while let Some((dir, prefix, chain)) = pending.pop() {
+        let read_dir = std::fs::read_dir(&dir).map_err(|e| Error::io(&dir, e))?;
+        for entry in read_dir {
+            let entry = entry.map_err(|e| Error::io(&dir, e))?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                warn!("Skipping non-UTF-8 name in {}", dir.display());
+                continue;
+            };
+            // Half-written downloads of this client.
+            if protocol::is_partial(&name) {
+                continue;
+            }
+            let rel = join_remote(&prefix, &name);
+            let mut metadata = entry.metadata().map_err(|e| Error::io(entry.path(), e))?;
+            let mut real_dir = None;
+            if metadata.is_symlink() {
+                match symlinks {
+                    SymlinkPolicy::Skip => continue,
+                    SymlinkPolicy::Preserve => {
+                        let target = std::fs::read_link(entry.path())
+                            .map_err(|e| Error::io(entry.path(), e))?;
+                        let link_dir = lexical_root.join(&prefix);
+                        match symlink::relative_target(&lexical_root, &link_dir, &target) {
+                            Some(target) => {
+                                tree.entries.insert(
+                                    rel,
+                                    Entry {
+                                        is_dir: false,
+                                        size: 0,
+                                        mtime: file_meta::mtime_secs(&metadata),
+                                        symlink: Some(target),
+                                    },
+                                );
+                            }
+                            None => tree.skipped.push((rel, SyncReason::SymlinkOutside)),
+                        }
+                        continue;
+                    }
+                    SymlinkPolicy::Follow => {
+                        let Ok(target) = std::fs::metadata(entry.path()) else {
+                            tree.skipped.push((rel, SyncReason::BrokenSymlink));
+                            continue;
+                        };
+                        if target.is_dir() {
+                            let real = entry
+                                .path()
+                                .canonicalize()
+                                .map_err(|e| Error::io(entry.path(), e))?;
+                            real_dir = Some(real);
+                        }
+                        metadata = target;
+                    }
+                }
+            }
+            if metadata.is_dir() {
+                let real = real_dir.unwrap_or_else(|| chain[chain.len() - 1].join(&name));
+                // Also catches a plain directory below a followed link that
+                // leads back up.
+                if chain.contains(&real) {
+                    warn!("Skipping symlink loop at {}", entry.path().display());
+                    tree.skipped.push((rel, SyncReason::SymlinkLoop));
+                    continue;
+                }
+                let mut chain = chain.clone();
+                chain.push(real);
+                pending.push((entry.path(), rel.clone(), chain));
+            } else if !metadata.is_file() {
+                continue;
+            }
+            tree.entries.insert(
+                rel,
+                Entry {
+                    is_dir: metadata.is_dir(),
+                    size: if metadata.is_dir() { 0 } else { metadata.len() },
+                    mtime: file_meta::mtime_secs(&metadata),
+                    symlink: None,
+                },
+            );
+        }
+    }
+    Ok(tree)
+}
+
+/// Every file and directory under `remote_dir` on the server; a missing
+/// directory counts as empty. Followed links into a directory that is
+/// already being walked are left out.
+pub(crate) async fn walk_remote(
+    remote: &RemoteClient,
+    remote_dir: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<BTreeMap<String, Entry>> {
+    let mut entries = BTreeMap::new();
+    // Share paths of the directories from `remote_dir` down, as in walk_local.
+    let real_root = symlink::normalize(&Path::new("/").join(remote_dir));
+    let mut pending = VecDeque::from([(String::new(), vec![real_root])]);
+    while let Some((prefix, chain)) = pending.pop_front() {
+        let listing = match remote.list_dir(join_remote(remote_dir, &prefix)).await {
+            Ok(listing) => listing,
+            Err(Error::NotFound(_)) if prefix.is_empty() => break,
+            Err(e) => return Err(e),
+        };
+        let real_dir = &chain[chain.len() - 1];
+        for entry in listing {
+            // Uploads in progress from other clients; deleting them in
+            // mirror mode would break those uploads.
+            if protocol::is_partial(&entry.name) {
+                continue;
+            }
+            let rel = join_remote(&prefix, &entry.name);
+            let mut real = real_dir.join(&entry.name);
+            if entry.is_symlink {
+                match symlinks {
+                    SymlinkPolicy::Skip => continue,
+                    SymlinkPolicy::Preserve => {
+                        entries.insert(
+                            rel,
+                            Entry {
+                                is_dir: false,
+                                size: 0,
+                                mtime: entry.mtime,
+                                symlink: Some(entry.symlink_target.unwrap_or_default()),
+                            },
+                        );
+                        continue;
+                    }
+                    SymlinkPolicy::Follow if entry.is_dir => {
+                        // The server hides targets outside the share and
+                        // refuses to list them anyway.
+                        let Some(target) = &entry.symlink_target else {
+                            continue;
+                        };
+                        real = symlink::normalize(&real_dir.join(target));
+                    }
+                    SymlinkPolicy::Follow => {}
+                }
+            }
+            if entry.is_dir {
+                if chain.contains(&real) {
+                    warn!("Skipping remote symlink loop at {}", rel);
+                    continue;
+                }
+                let mut chain = chain.clone();
+                chain.push(real);
+                pending.push_back((rel.clone(), chain));
+            }
+            entries.insert(
+                rel,
+                Entry {
+                    is_dir: entry.is_dir,
+                    size: entry.size,
+                    mtime: entry.mtime,
+                    symlink: None,
+                },
+            );
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, mtime: i64) -> Entry {
+        Entry {
+            is_dir: false,
+            size,
+            mtime,
+            symlink: None,
+        }
+    }
+
+    fn dir() -> Entry {
+        Entry {
+            is_dir: true,
+            size: 0,
+            mtime: 0,
+            symlink: None,
+        }
+    }
+
+    fn link(target: &str) -> Entry {
+        Entry {
+            is_dir: false,
+            size: 0,
+            mtime: 0,
+            symlink: Some(target.to_string()),
+        }
+    }
+
+    fn tree(entries: Vec<(&str, Entry)>) -> BTreeMap<String, Entry> {
+        entries
+            .into_iter()
+            .map(|(path, entry)| (path.to_string(), entry))
+            .collect()
+    }
+
+    fn plan(
+        local: Vec<(&str, Entry)>,
+        skipped: Vec<(&str, SyncReason)>,
+        remote: Vec<(&str, Entry)>,
+        options: SyncOptions,
+        same_content: impl Fn(&str) -> bool,
+    ) -> Vec<(String, SyncAction, SyncReason)> {
+        let local = LocalTree {
+            entries: tree(local),
+            skipped: skipped
+                .into_iter()
+                .map(|(path, reason)| (path.to_string(), reason))
+                .collect(),
+        };
+        let plan = compare_trees(
+            Path::new("/src"),
+            "dst".to_string(),
+            local,
+            &tree(remote),
+            options,
+            same_content,
+        );
+        plan.items
+            .into_iter()
+            .map(|item| (item.path, item.action, item.reason))
+            .collect()
+    }
+
+    fn item(
+        path: &str,
+        action: SyncAction,
+        reason: SyncReason,
+    ) -> (String, SyncAction, SyncReason) {
+        (path.to_string(), action, reason)
+    }
+
+    #[test]
+    fn unchanged_files_are_skipped() {
+        use SyncAction::*;
+        use SyncReason::*;
+        let local = || {
+            vec![
+                ("new", file(1, 0)),
+                ("same", file(5, 10)),
+                ("older", file(5, 9)),
+                ("newer", file(5, 11)),
+                ("grown", file(6, 10)),
+            ]
+        };
+        let remote = || {
+            vec![
+                ("same", file(5, 10)),
+                ("older", file(5, 10)),
+                ("newer", file(5, 10)),
+                ("grown", file(5, 10)),
+            ]
+        };
+        assert_eq!(
+            plan(local(), vec![], remote(), SyncOptions::default(), |_| false),
+            [
+                item("grown", Upload, SizeChanged),
+                item("new", Upload, New),
+                item("newer", Upload, Newer),
+                item("older", Skip, Unchanged),
+                item("same", Skip, Unchanged),
+            ]
+        );
+
+        // By hash, mtimes do not matter and only equal sizes are compared.
+        let options = SyncOptions {
+            compare_hash: true,
+            ..SyncOptions::default()
+        };
+        assert_eq!(
+            plan(local(), vec![], remote(), options, |path| path != "older"),
+            [
+                item("grown", Upload, SizeChanged),
+                item("new", Upload, New),
+                item("newer", Skip, Unchanged),
+                item("older", Upload, ContentChanged),
+                item("same", Skip, Unchanged),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_deletes_top_most_remote_only_paths() {
+        use SyncAction::*;
+        use SyncReason::*;
+        let local = || vec![("a", file(1, 0)), ("d", dir()), ("d/x", file(1, 0))];
+        let remote = || {
+            vec![
+                ("a", file(1, 0)),
+                ("d", dir()),
+                ("d/x", file(1, 0)),
+                ("d/y", file(1, 0)),
+                ("old", dir()),
+                ("old/deep", dir()),
+                ("old/deep/z", file(1, 0)),
+            ]
+        };
+        let mirror = SyncOptions {
+            mirror: true,
+            ..SyncOptions::default()
+        };
+        assert_eq!(
+            plan(local(), vec![], remote(), mirror, |_| false),
+            [
+                item("a", Skip, Unchanged),
+                item("d/x", Skip, Unchanged),
+                item("d/y", Delete, NotInSource),
+                item("old", Delete, NotInSource),
+            ]
+        );
+        // Without mirroring, remote-only files are left alone.
+        let plan = plan(local(), vec![], remote(), SyncOptions::default(), |_| false);
+        assert!(plan.iter().all(|(_, action, _)| *action != Delete));
+    }
+
+    #[test]
+    fn type_conflicts_are_replaced_only_when_mirroring() {
+        use SyncAction::*;
+        use SyncReason::*;
+        let local = || vec![("f", file(1, 0)), ("d", dir()), ("d/x", file(1, 0))];
+        let remote = || vec![("f", dir()), ("d", file(3, 0))];
+        let mirror = SyncOptions {
+            mirror: true,
+            ..SyncOptions::default()
+        };
+        assert_eq!(
+            plan(local(), vec![], remote(), mirror, |_| false),
+            [
+                item("d", Delete, TypeConflict),
+                item("d/x", Upload, New),
+                item("f", Delete, TypeConflict),
+                item("f", Upload, TypeConflict),
+            ]
+        );
+        assert_eq!(
+            plan(local(), vec![], remote(), SyncOptions::default(), |_| false),
+            [
+                item("d", Skip, TypeConflict),
+                item("d/x", Skip, TypeConflict),
+                item("f", Skip, TypeConflict),
+            ]
+        );
+    }
+
+    #[test]
+    fn preserved_links_compare_by_target() {
+        use SyncAction::*;
+        use SyncReason::*;
+        let options = SyncOptions {
+            mirror: true,
+            symlinks: SymlinkPolicy::Preserve,
+            ..SyncOptions::default()
+        };
+        let local = vec![
+            ("same", link("a")),
+            ("moved", link("b")),
+            ("was_file", link("a")),
+        ];
+        let remote = vec![
+            ("same", link("a")),
+            ("moved", link("a")),
+            ("was_file", file(0, 0)),
+            // In place of a local link that leaves the synced directory.
+            ("outside", file(4, 0)),
+            ("outside_dir", dir()),
+            ("outside_dir/f", file(4, 0)),
+        ];
+        let skipped = vec![("outside", SymlinkOutside), ("outside_dir", SymlinkOutside)];
+        let compare = compare_trees(
+            Path::new("/src"),
+            "dst".to_string(),
+            LocalTree {
+                entries: tree(local),
+                skipped: skipped
+                    .into_iter()
+                    .map(|(path, reason)| (path.to_string(), reason))
+                    .collect(),
+            },
+            &tree(remote),
+            options,
+            |_| false,
+        );
+        let items: Vec<_> = compare
+            .items
+            .iter()
+            .map(|i| {
+                (
+                    i.path.as_str(),
+                    i.action,
+                    i.reason,
+                    i.symlink_target.as_deref(),
+                )
+            })
+            .collect();
+        // Skipped links keep whatever the server has in their place.
+        assert_eq!(
+            items,
+            [
+                ("moved", Upload, ContentChanged, Some("b")),
+                ("outside", Skip, SymlinkOutside, None),
+                ("outside_dir", Skip, SymlinkOutside, None),
+                ("same", Skip, Unchanged, None),
+                ("was_file", Upload, ContentChanged, Some("a")),
+            ]
+        );
+    }
+
+    #[test]
+    fn walks_leave_out_transfer_temp_files() {
+        let root = tempfile::tempdir().unwrap();
+        let temp = format!(".a.txt.1234{}", protocol::PARTIAL_SUFFIX);
+        std::fs::write(root.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(root.path().join(&temp), b"half").unwrap();
+        std::fs::create_dir(root.path().join("sub")).unwrap();
+        std::fs::write(root.path().join("sub").join(&temp), b"half").unwrap();
+
+        let tree = walk_local(root.path(), SymlinkPolicy::Skip).unwrap();
+        let paths: Vec<&str> = tree.entries.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["a.txt", "sub"]);
+    }
+
+    /// A tree with a link to itself, a link back up to the root, a link
+    /// leaving the root, a file link and a broken link. The second dir is
+    /// what the outside link points to.
+    #[cfg(unix)]
+    fn linked_tree() -> (tempfile::TempDir, tempfile::TempDir) {
+        use std::os::unix::fs::symlink;
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), b"s").unwrap();
+        let path = |rel: &str| root.path().join(rel);
+        std::fs::write(path("a.txt"), b"a").unwrap();
+        std::fs::create_dir(path("sub")).unwrap();
+        std::fs::write(path("sub/b.txt"), b"b").unwrap();
+        symlink(".", path("here")).unwrap();
+        symlink("..", path("sub/up")).unwrap();
+        symlink(outside.path(), path("out")).unwrap();
+        symlink("a.txt", path("alias.txt")).unwrap();
+        symlink("missing", path("broken")).unwrap();
+        (root, outside)
+    }
+
+    /// Walked paths with their preserved link targets.
+    fn walked(tree: &LocalTree) -> Vec<(&str, Option<&str>)> {
+        tree.entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry.symlink.as_deref()))
+            .collect()
+    }
+
+    fn skipped(tree: &LocalTree) -> Vec<(&str, SyncReason)> {
+        let mut skipped: Vec<_> = tree
+            .skipped
+            .iter()
+            .map(|(path, reason)| (path.as_str(), *reason))
+            .collect();
+        skipped.sort_by_key(|(path, _)| *path);
+        skipped
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_links_stop_at_loops() {
+        let (root, _outside) = linked_tree();
+        let tree = walk_local(root.path(), SymlinkPolicy::Follow).unwrap();
+        assert_eq!(
+            walked(&tree),
+            [
+                ("a.txt", None),
+                ("alias.txt", None),
+                ("out", None),
+                ("out/secret.txt", None),
+                ("sub", None),
+                ("sub/b.txt", None),
+            ]
+        );
+        assert_eq!(
+            skipped(&tree),
+            [
+                ("broken", SyncReason::BrokenSymlink),
+                ("here", SyncReason::SymlinkLoop),
+                ("sub/up", SyncReason::SymlinkLoop),
+            ]
+        );
+        assert!(tree.entries["out"].is_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserved_links_must_stay_inside_the_root() {
+        let (root, _outside) = linked_tree();
+        let tree = walk_local(root.path(), SymlinkPolicy::Preserve).unwrap();
+        assert_eq!(
+            walked(&tree),
+            [
+                ("a.txt", None),
+                ("alias.txt", Some("a.txt")),
+                ("broken", Some("missing")),
+                ("here", Some(".")),
+                ("sub", None),
+                ("sub/b.txt", None),
+                ("sub/up", Some("..")),
+            ]
+        );
+        assert_eq!(skipped(&tree), [("out", SyncReason::SymlinkOutside)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skipped_links_are_left_out() {
+        let (root, _outside) = linked_tree();
+        let tree = walk_local(root.path(), SymlinkPolicy::Skip).unwrap();
+        assert_eq!(
+            walked(&tree),
+            [("a.txt", None), ("sub", None), ("sub/b.txt", None)]
+        );
+        assert!(tree.skipped.is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+// rustsend-core/src/transfer.rs
+
+//! Transfer queue: runs uploads, downloads, remote deletions and symlink
+//! creation in the background with bounded concurrency and publishes every
+//! state change as an event.
+
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::{ConflictMode, RemoteClient, UploadMode};
+use crate::error::Error;
+use crate::i18n::{self, Message};
+
+/// Default number of transfers running at the same time.
+pub const DEFAULT_CONCURRENCY: usize = 3;
+
+/// Capacity of the event channel; slow subscribers miss intermediate events.
+const EVENT_CAPACITY: usize = 256;
+
+pub type TransferId = u64;
+
+/// What a transfer does.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransferKind {
+    Upload {
+        local_path: PathBuf,
+        target_dir: String,
+        conflict_policy: ConflictMode,
+        mode: UploadMode,
+    },
+    Download {
+        remote_path: String,
+        /// Destination file; missing parent directories are created.
+        local_path: PathBuf,
+    },
+    /// Deletes a remote file or directory.
+    Delete {
+        remote_path: String,
+        recursive: bool,
+    },
+    /// Creates or replaces a remote symlink.
+    Symlink {
+        remote_path: String,
+        /// Relative to the link's directory.
+        target: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TransferState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            TransferState::Completed | TransferState::Failed | TransferState::Cancelled
+        )
+    }
+}
+
+/// Snapshot of a transfer, also sent as the event payload.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: TransferId,
+    /// URL of the server the transfer goes to.
+    pub server: String,
+    pub kind: TransferKind,
+    pub state: TransferState,
+    /// Server reply on success, error text on failure.
+    pub message: Option<String>,
+    /// Stable `Error::code()` of a failure.
+    pub error_code: Option<&'static str>,
+}
+
+struct Job {
+    info: TransferInfo,
+    cancel: CancellationToken,
+}
+
+struct Inner {
+    semaphore: Arc<Semaphore>,
+    jobs: Mutex<BTreeMap<TransferId, Job>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<TransferInfo>,
+}
+
+impl Inner {
+    /// Applies `update` to a job and publishes the new snapshot.
+    fn update(&self, id: TransferId, update: impl FnOnce(&mut TransferInfo)) -> TransferInfo {
+        let info = {
+            let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+            let job = jobs.get_mut(&id).expect("transfer removed while running");
+            update(&mut job.info);
+            job.info.clone()
+        };
+        // No subscribers is fine.
+        let _ = self.events.send(info.clone());
+        info
+    }
+}
+
+/// Shared queue of transfers; clones refer to the same queue.
+#[derive(Clone)]
+pub struct TransferQueue {
+    inner: Arc<Inner>,
+}
+
+/// Returned by `enqueue`; awaiting it is optional.
+pub struct TransferHandle {
+    pub id: TransferId,
+    task: JoinHandle<TransferInfo>,
+}
+
+impl TransferHandle {
+    /// Waits until the transfer completed, failed or was cancelled.
+    pub async fn finished(self) -> TransferInfo {
+        self.task.await.expect("transfer task panicked")
+    }
+}
+
+impl Default for TransferQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONCURRENCY)
+    }
+}
+
+impl TransferQueue {
+    pub fn new(concurrency: usize) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        TransferQueue {
+            inner: Arc::new(Inner {
+                semaphore: Arc::new(Semaphore::new(concurrency.max(1))),
+                jobs: Mutex::new(BTreeMap::new()),
+                next_id: AtomicU64::new(1),
+                events,
+            }),
+        }
+    }
+
+    /// Receives a snapshot on every state change of any transfer.
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferInfo> {
+        self.inner.events.subscribe()
+    }
+
+    /// All transfers still in the list, oldest first.
+    pub fn list(&self) -> Vec<TransferInfo> {
+        let jobs = self.inner.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.values().map(|job| job.info.clone()).collect()
+    }
+
+    /// Cancels a queued or running transfer; returns false if it is unknown
+    /// or already finished.
+    pub fn cancel(&self, id: TransferId) -> bool {
+        let jobs = self.inner.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        match jobs.get(&id) {
+            Some(job) if !job.info.state.is_finished() => {
+                job.cancel.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes finished transfers from the list.
+    pub fn clear_finished(&self) {
+        let mut jobs = self.inner.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.retain(|_, job| !job.info.state.is_finished());
+    }
+
+    /// Queues a transfer on `remote`. It starts as soon as a slot is free.
+    pub fn enqueue(&self, remote: RemoteClient, kind: TransferKind) -> TransferHandle {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = CancellationToken::new();
+        let info = TransferInfo {
+            id,
+            server: remote.url().to_string(),
+            kind: kind.clone(),
+            state: TransferState::Queued,
+            message: None,
+            error_code: None,
+        };
+        self.inner
+            .jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                id,
+                Job {
+                    info: info.clone(),
+                    cancel: cancel.clone(),
+                },
+            );
+        let _ = self.inner.events.send(info);
+
+        let inner = self.inner.clone();
+        let task = tokio::spawn(async move {
+            let permit = tokio::select! {
+                _ = cancel.cancelled() => None,
+                permit = inner.semaphore.clone().acquire_owned() => permit.ok(),
+            };
+            let Some(_permit) = permit else {
+                return inner.update(id, |info| info.state = TransferState::Cancelled);
+            };
+            inner.update(id, |info| info.state = TransferState::Running);
+
+            let result = tokio::select! {
+                _ = cancel.cancelled() => None,
+                result = run(&remote, kind) => Some(result),
+            };
+            let info = inner.update(id, |info| match result {
+                None => info.state = TransferState::Cancelled,
+                Some(Ok(message)) => {
+                    info.state = TransferState::Completed;
+                    info.message = Some(message);
+                }
+                Some(Err(e)) => {
+                    info.state = TransferState::Failed;
+                    info.error_code = Some(e.code());
+                    info.message = Some(i18n::error_message(&e));
+                }
+            });
+            match info.state {
+                TransferState::Failed => warn!(
+                    "Transfer {} failed: {}",
+                    id,
+                    info.message.as_deref().unwrap_or_default()
+                ),
+                state => info!("Transfer {} {:?}", id, state),
+            }
+            info
+        });
+        TransferHandle { id, task }
+    }
+}
+
+/// Runs one transfer and returns the message to show on success, in the
+/// current locale.
+async fn run(remote: &RemoteClient, kind: TransferKind) -> Result<String, Error> {
+    match kind {
+        TransferKind::Upload {
+            local_path,
+            target_dir,
+            conflict_policy,
+            mode,
+        } => {
+            let status = remote
+                .upload(&local_path, target_dir, conflict_policy, mode)
+                .await?;
+            if status.success {
+                Ok(Message::Upload(&status).to_string())
+            } else {
+                Err(Error::Remote {
+                    code: tonic::Code::Unknown,
+                    message: status.message,
+                })
+            }
+        }
+        TransferKind::Download {
+            remote_path,
+            local_path,
+        } => {
+            if let Some(parent) = local_path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| Error::io(parent, e))?;
+            }
+            let (_, bytes) = remote.download_file(remote_path, &local_path).await?;
+            Ok(Message::Downloaded { bytes }.to_string())
+        }
+        TransferKind::Delete {
+            remote_path,
+            recursive,
+        } => {
+            remote.delete(remote_path.clone(), recursive).await?;
+            Ok(Message::Deleted { path: &remote_path }.to_string())
+        }
+        TransferKind::Symlink {
+            remote_path,
+            target,
+        } => {
+            remote
+                .create_symlink(remote_path.clone(), target.clone())
+                .await?;
+            Ok(Message::Linked {
+                path: &remote_path,
+                target: &target,
+            }
+            .to_string())
+        }
+    }
+}
@@ -1,8 +1,12 @@
 // src-tauri/src/commands.rs (新增)
 
 use log::{error, info};
+use rustsend_core::error::Error;
+use rustsend_core::file_meta;
+use rustsend_core::i18n::{self, Locale};
+use rustsend_core::listing::{DirLister, ListOptions, Listable};
+use rustsend_core::symlink;
 use std::path::PathBuf;
-use tauri::State;
 
 // 用于前端显示的文件/目录结构
 #[derive(serde::Serialize, Clone)]
@@ -10,18 +14,50 @@ pub struct LocalDirEntry {
     name: String,
     is_dir: bool,
     size: u64, // 新增文件大小字段
+    /// 修改时间 (Unix 秒)
+    mtime: i64,
+    is_symlink: bool,
+    /// 链接目标；指向 Home 目录以外时不显示
+    symlink_target: Option<String>,
 }
 
-// 假设我们使用 dirs 库获取 Home 目录，需要确保在 Cargo.toml 中已添加 dirs = "5.0"
-use dirs;
+impl Listable for LocalDirEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn size(&self) -> u64 {
+        if self.is_dir {
+            0
+        } else {
+            self.size
+        }
+    }
+
+    fn mtime(&self) -> i64 {
+        self.mtime
+    }
+}
 
 fn get_local_base_path() -> PathBuf {
     // 默认使用 Home 目录作为本地根目录
     dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
 }
 
+/// 列出本机目录；排序与过滤规则和远程目录相同 (未传 options 时按名称排序并跳过隐藏文件)
 #[tauri::command]
-pub async fn list_local_dir(path: String) -> Result<(Vec<LocalDirEntry>, String), String> {
+pub async fn list_local_dir(
+    path: String,
+    options: Option<ListOptions>,
+) -> Result<(Vec<LocalDirEntry>, String), Error> {
+    let options = options.unwrap_or_default();
+    let lister = DirLister::new(options.clone()).map_err(|e| {
+        Error::InvalidArgument(format!("Bad pattern \"{}\": {}", options.pattern, e))
+    })?;
     let base = get_local_base_path();
     let requested_path = path.trim_start_matches('/');
 
@@ -32,21 +68,19 @@ pub async fn list_local_dir(path: String) -> Result<(Vec<LocalDirEntry>, String)
 
     // 路径遍历检查 (防止访问 Home 目录以外的系统路径)
     // 规范化 base path
-    let canonical_base = base
-        .canonicalize()
-        .map_err(|e| format!("Invalid base path: {}", e))?;
+    let canonical_base = base.canonicalize().map_err(|e| Error::io(&base, e))?;
 
     // 规范化 requested path
     let canonical_path = full_path
         .canonicalize()
-        .map_err(|e| format!("Directory not found or inaccessible: {}", e))?;
+        .map_err(|e| Error::io(&full_path, e))?;
 
     if !canonical_path.starts_with(&canonical_base) {
         error!(
             "Local path traversal attempt detected: {}",
             canonical_path.display()
         );
-        return Err("Access to this local path is restricted.".to_string());
+        return Err(Error::PathOutsideSandbox(path));
     }
 
     let mut entries = Vec::new();
@@ -56,22 +90,49 @@ pub async fn list_local_dir(path: String) -> Result<(Vec<LocalDirEntry>, String)
             while let Some(entry_result) = dir.next_entry().await.transpose() {
                 match entry_result {
                     Ok(entry) => {
-                        let metadata = match entry.metadata().await {
+                        let mut metadata = match entry.metadata().await {
                             Ok(m) => m,
                             Err(_) => continue, // 无法获取元数据则跳过
                         };
 
                         let name = entry.file_name().to_string_lossy().into_owned();
-
-                        // 忽略隐藏文件/目录
-                        if name.starts_with('.') && name != "." && name != ".." {
+                        if !lister.accepts(&name) {
                             continue;
                         }
 
+                        // 符号链接：显示目标，类型和大小取自目标。与服务端的 symlink_entry 一致，
+                        // 只跟随指向 Home 目录内的链接；失效或指向外部的链接保留其自身 (lstat)
+                        // 的元数据，按大小为 0 的文件显示
+                        let is_symlink = metadata.is_symlink();
+                        let mut symlink_target = None;
+                        if is_symlink {
+                            if let Ok(target) = tokio::fs::read_link(entry.path()).await {
+                                symlink_target = symlink::display_target(
+                                    &canonical_base,
+                                    &canonical_path,
+                                    &target,
+                                );
+                            }
+                            if let Ok(resolved) = tokio::fs::canonicalize(entry.path()).await {
+                                if resolved.starts_with(&canonical_base) {
+                                    if let Ok(m) = tokio::fs::metadata(&resolved).await {
+                                        metadata = m;
+                                    }
+                                }
+                            }
+                        }
+
                         entries.push(LocalDirEntry {
                             name,
                             is_dir: metadata.is_dir(),
-                            size: metadata.len(),
+                            size: if metadata.is_symlink() {
+                                0
+                            } else {
+                                metadata.len()
+                            },
+                            mtime: file_meta::mtime_secs(&metadata),
+                            is_symlink,
+                            symlink_target,
                         });
                     }
                     Err(e) => {
@@ -81,10 +142,28 @@ pub async fn list_local_dir(path: String) -> Result<(Vec<LocalDirEntry>, String)
             }
         }
         Err(e) => {
-            return Err(format!("Could not read local directory: {}", e));
+            return Err(Error::io(&canonical_path, e));
         }
     }
 
     // 返回文件列表和当前规范化路径
-    Ok((entries, canonical_path.to_string_lossy().to_string()))
+    Ok((
+        lister.arrange(entries),
+        canonical_path.to_string_lossy().to_string(),
+    ))
+}
+
+/// 当前后端消息语言
+#[tauri::command]
+pub fn get_locale() -> Locale {
+    i18n::current()
+}
+
+/// 切换后端消息语言 ("zh-CN" / "en")，错误码不受影响
+#[tauri::command]
+pub fn set_locale(locale: String) -> Result<Locale, Error> {
+    let locale: Locale = locale.parse()?;
+    i18n::set_locale(locale);
+    info!("Backend locale set to {}", locale.tag());
+    Ok(locale)
 }
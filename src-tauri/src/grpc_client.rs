@@ -1,127 +1,326 @@
 // src/grpc_client.rs
 
-use anyhow::Context;
 use log::{error, info};
 use parking_lot::Mutex; // Used for fast, sync State management
+use rustsend_core::bisync::{self, BisyncPlan, SyncState};
+use rustsend_core::client::{
+    ConflictMode, RemoteClient, RemoteDirEntry, RemoteDirUsage, RemoteDiskSpace,
+    ServerCapabilities, UploadMode,
+};
+use rustsend_core::connection::ConnectionState;
+use rustsend_core::error::Error;
+use rustsend_core::i18n::Message;
+use rustsend_core::listing::ListOptions;
+use rustsend_core::profile::Profile;
+use rustsend_core::sync::{self, SyncOptions, SyncPlan};
 use serde::Serialize;
-use std::fs::File;
-use std::io::Read;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tauri::State;
-use tokio::sync::mpsc;
-use tokio::task;
-use tokio_stream::wrappers::ReceiverStream;
-use tonic::transport::Channel;
-
-// FIX: 引入 dirs crate 依赖
-use dirs;
-
-// 从原 src/client.rs 复制
-const CHUNK_SIZE: usize = 1024 * 64; // 64 KB
-
-// 引入 gRPC 结构 (确保 tonic::include_proto! 在某处被执行，通常在 build.rs 或 main.rs)
-pub mod filerpc {
-    tonic::include_proto!("filerpc");
-}
-use filerpc::{file_service_client::FileServiceClient, FileChunk, ListDirRequest};
-
-// --- GUI 数据结构 ---
+use tauri::async_runtime::{self, JoinHandle};
+use tauri::{AppHandle, Emitter, Manager, State};
 
-/// 用于在 Rust 后端和 Web 前端之间传输的文件/目录信息
-#[derive(Debug, Serialize)]
-pub struct GuiDirEntry {
-    pub name: String,
-    pub is_dir: bool,
-}
+use crate::profiles::ProfileState;
+use crate::task_registry::CancellableTaskRegistry;
+use crate::transfers::TransferState;
 
 // --- 客户端状态管理 ---
 
-/// 在 Tauri 运行时中共享的 gRPC 客户端连接状态
-pub struct ClientState(Mutex<Option<FileServiceClient<Channel>>>);
+/// 在 Tauri 运行时中共享的 gRPC 客户端连接，按连接配置 (profile) ID 区分
+pub struct ClientState {
+    clients: Mutex<HashMap<String, RemoteClient>>,
+    /// 每个连接的状态转发任务
+    forwarders: CancellableTaskRegistry<String>,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ClientState {
     pub fn new() -> Self {
-        ClientState(Mutex::new(None))
+        ClientState {
+            clients: Mutex::new(HashMap::new()),
+            forwarders: CancellableTaskRegistry::default(),
+        }
     }
 
-    /// Helper to get a clone of the client, returning a Tauri::Status error if disconnected.
-    fn get_client(&self) -> Result<FileServiceClient<Channel>, tonic::Status> {
-        let client_lock = self.0.lock();
+    /// Helper to get a clone of the client, returning `Error::NotConnected` if disconnected.
+    fn get_client(&self, profile_id: &str) -> Result<RemoteClient, Error> {
+        let client_lock = self.clients.lock();
         client_lock
-            .as_ref()
-            .cloned() // ClientServiceClient 实现了 Clone
-            .ok_or_else(|| tonic::Status::unavailable("Not connected to server."))
+            .get(profile_id)
+            .cloned() // RemoteClient 实现了 Clone，共享底层 Channel
+            .ok_or(Error::NotConnected)
+    }
+
+    /// 移除并断开一个连接，取消其进行中的传输；返回被断开的连接
+    pub fn disconnect(&self, profile_id: &str) -> Option<RemoteClient> {
+        let remote = self.clients.lock().remove(profile_id)?;
+        remote.disconnect();
+        Some(remote)
+    }
+
+    /// 登记新连接并开始转发其状态。被替换的旧连接先停止转发再断开，
+    /// 其监视任务不会再以同一配置 ID 发出过时的状态事件
+    fn register(&self, app: &AppHandle, profile_id: &str, remote: RemoteClient) {
+        let mut clients = self.clients.lock();
+        self.forwarders.start(profile_id.to_string(), |generation| {
+            forward_connection_state(app.clone(), profile_id.to_string(), &remote, generation)
+        });
+        if let Some(previous) = clients.insert(profile_id.to_string(), remote) {
+            previous.disconnect();
+        }
     }
 }
 
-// --- Tauri Commands (gRPC 包装器) ---
+/// 一个已建立连接的概要 (list_connections 返回)
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    profile_id: String,
+    url: String,
+    state: ConnectionState,
+}
 
-/// 1. 连接到服务器
-#[tauri::command]
-pub async fn connect_server(state: State<'_, ClientState>, url: String) -> Result<String, String> {
-    info!("Attempting to connect to {}", url);
+// --- 连接状态事件 ---
 
-    // Tonic connection requires a scheme (http:// or https://)
-    let server_url = if url.starts_with("http://") || url.starts_with("https://") {
-        url
-    } else {
-        format!("http://{}", url)
+/// `connection-state` 事件的负载
+#[derive(Clone, Serialize)]
+struct ConnectionStateEvent<'a> {
+    profile_id: &'a str,
+    url: &'a str,
+    state: ConnectionState,
+}
+
+fn emit_connection_state(app: &AppHandle, profile_id: &str, url: &str, state: ConnectionState) {
+    let event = ConnectionStateEvent {
+        profile_id,
+        url,
+        state,
     };
+    if let Err(e) = app.emit("connection-state", event) {
+        error!("Failed to emit connection-state event: {}", e);
+    }
+}
 
-    match FileServiceClient::connect(server_url.clone()).await {
-        Ok(client) => {
-            let mut client_lock = state.0.lock();
-            *client_lock = Some(client);
-            info!("Successfully connected to Server.");
-            Ok(format!("连接成功: {}", server_url))
+/// 将连接状态变化转发给前端；连接释放后自动结束，被替换时由 `ClientState` 中止
+fn forward_connection_state(
+    app: AppHandle,
+    profile_id: String,
+    remote: &RemoteClient,
+    generation: u64,
+) -> JoinHandle<()> {
+    let url = remote.url().to_string();
+    let mut rx = remote.subscribe_state();
+    async_runtime::spawn(async move {
+        while rx.changed().await.is_ok() {
+            let state = *rx.borrow_and_update();
+            emit_connection_state(&app, &profile_id, &url, state);
         }
+        app.state::<ClientState>()
+            .forwarders
+            .finish(&profile_id, generation);
+    })
+}
+
+/// 前端传来的本地路径均相对于 Home 目录 (前导的 '/' 或 '\\' 会被忽略)
+pub fn home_relative_path(local_path: &str) -> Result<PathBuf, Error> {
+    let home_dir = dirs::home_dir().ok_or_else(|| {
+        error!("Could not determine user home directory.");
+        Error::NotFound("home directory".to_string())
+    })?;
+
+    let relative_path = Path::new(local_path);
+
+    // 尝试移除路径前导的 '/'，如果存在的话，确保路径是相对于 Home 目录的。
+    let corrected_path = if let Ok(stripped) = relative_path.strip_prefix("/") {
+        stripped
+    } else if let Ok(stripped) = relative_path.strip_prefix("\\") {
+        // 兼容 Windows 路径
+        stripped
+    } else {
+        relative_path
+    };
+
+    // 最终的绝对路径 = Home 目录 + 修正后的相对路径
+    let actual_path = home_dir.join(corrected_path);
+    info!("Path constructed: {:?}", actual_path);
+    Ok(actual_path)
+}
+
+// --- Tauri Commands (gRPC 包装器) ---
+
+/// 按连接配置建立连接并登记；同一配置已有的连接先被断开
+async fn connect_profile(
+    app: &AppHandle,
+    state: &ClientState,
+    profiles: &ProfileState,
+    profile_id: &str,
+) -> Result<RemoteClient, Error> {
+    let profile: Profile = profiles.get(profile_id)?;
+    let url = &profile.address;
+
+    // 先停止旧连接的状态转发，旧连接断开时发出的状态不会混入新连接的事件
+    state.forwarders.cancel(profile_id);
+    state.disconnect(profile_id);
+    emit_connection_state(app, profile_id, url, ConnectionState::Connecting);
+    let remote = match RemoteClient::connect_profile(&profile).await {
+        Ok(remote) => remote,
         Err(e) => {
-            error!("Connection failed: {}", e);
-            Err(format!("连接失败: {}", e))
+            emit_connection_state(app, profile_id, url, ConnectionState::Disconnected);
+            return Err(e);
         }
+    };
+    emit_connection_state(app, profile_id, remote.url(), ConnectionState::Connected);
+    state.register(app, profile_id, remote.clone());
+    Ok(remote)
+}
+
+/// 返回已有的连接，未连接时按配置自动连接 (供热文件夹等后台任务使用)
+pub async fn ensure_connected(app: &AppHandle, profile_id: &str) -> Result<RemoteClient, Error> {
+    let state = app.state::<ClientState>();
+    if let Ok(remote) = state.get_client(profile_id) {
+        return Ok(remote);
+    }
+    connect_profile(app, &state, &app.state::<ProfileState>(), profile_id).await
+}
+
+/// 1. 按连接配置连接到服务器；同一配置已有的连接会被替换，其他配置的连接不受影响
+#[tauri::command]
+pub async fn connect_server(
+    app: AppHandle,
+    state: State<'_, ClientState>,
+    profiles: State<'_, ProfileState>,
+    profile_id: String,
+) -> Result<String, Error> {
+    let remote = connect_profile(&app, &state, &profiles, &profile_id).await?;
+    Ok(Message::Connected {
+        url: remote.url(),
+        device: &remote.capabilities().device_name,
     }
+    .to_string())
+}
+
+/// 断开指定连接：释放通道并取消该连接上进行中的上传/下载
+#[tauri::command]
+pub fn disconnect_server(state: State<'_, ClientState>, profile_id: String) -> Result<(), Error> {
+    // disconnected 事件由该连接的状态转发任务发出 (监视任务停止时报告)
+    state.disconnect(&profile_id).ok_or(Error::NotConnected)?;
+    Ok(())
+}
+
+/// 当前所有连接
+#[tauri::command]
+pub fn list_connections(state: State<'_, ClientState>) -> Vec<ConnectionInfo> {
+    state
+        .clients
+        .lock()
+        .iter()
+        .map(|(profile_id, remote)| ConnectionInfo {
+            profile_id: profile_id.clone(),
+            url: remote.url().to_string(),
+            state: remote.connection_state(),
+        })
+        .collect()
+}
+
+/// 查询指定连接的服务端能力
+#[tauri::command]
+pub async fn get_server_info(
+    state: State<'_, ClientState>,
+    profile_id: String,
+) -> Result<ServerCapabilities, Error> {
+    state
+        .get_client(&profile_id)
+        .map(|remote| remote.capabilities().clone())
+}
+
+/// 指定连接的当前状态，未连接时为 disconnected
+#[tauri::command]
+pub fn get_connection_state(state: State<'_, ClientState>, profile_id: String) -> ConnectionState {
+    state
+        .get_client(&profile_id)
+        .map(|remote| remote.connection_state())
+        .unwrap_or(ConnectionState::Disconnected)
 }
 
-/// 2. 列出远程目录内容
+/// 2. 列出远程目录内容 (排序与过滤规则和本机目录相同)，并记住该配置最后浏览的远程路径
 #[tauri::command]
 pub async fn list_remote_dir(
     state: State<'_, ClientState>,
+    profiles: State<'_, ProfileState>,
+    profile_id: String,
+    path: String,
+    options: Option<ListOptions>,
+) -> Result<Vec<RemoteDirEntry>, Error> {
+    let remote = state.get_client(&profile_id)?;
+    let entries = remote
+        .list_dir_with(path.clone(), &options.unwrap_or_default())
+        .await?;
+    profiles.update_paths(&profile_id, Some(path), None);
+    Ok(entries)
+}
+
+/// 远程路径所在文件系统的容量与共享目录配额 (上传大批数据前检查空间)
+#[tauri::command]
+pub async fn remote_disk_usage(
+    state: State<'_, ClientState>,
+    profile_id: String,
+    path: String,
+) -> Result<RemoteDiskSpace, Error> {
+    let remote = state.get_client(&profile_id)?;
+    remote.disk_usage(path).await
+}
+
+/// `dir-usage-progress` 事件的负载
+#[derive(Clone, Serialize)]
+struct DirUsageProgressEvent<'a> {
+    profile_id: &'a str,
+    path: &'a str,
+    usage: RemoteDirUsage,
+}
+
+/// 统计远程路径下的总大小 (类似 du -s)。
+/// 统计期间的累计值以 `dir-usage-progress` 事件推送，返回最终结果
+#[tauri::command]
+pub async fn remote_dir_usage(
+    app: AppHandle,
+    state: State<'_, ClientState>,
+    profile_id: String,
     path: String,
-) -> Result<Vec<GuiDirEntry>, String> {
-    let mut client = state.get_client().map_err(|e| e.message().to_string())?;
-
-    info!("Attempting to list remote directory: {}", path);
-
-    let request = tonic::Request::new(ListDirRequest { path: path });
-
-    match client.list_dir(request).await {
-        Ok(response) => {
-            let entries = response
-                .into_inner()
-                .entries
-                .into_iter()
-                .map(|e| GuiDirEntry {
-                    name: e.name,
-                    is_dir: e.is_dir,
-                })
-                .collect();
-            Ok(entries)
+) -> Result<RemoteDirUsage, Error> {
+    let remote = state.get_client(&profile_id)?;
+    let mut scan = remote.dir_usage(path.clone()).await?;
+    while let Some(usage) = scan.next().await? {
+        if usage.complete {
+            return Ok(usage);
         }
-        Err(e) => {
-            error!("Failed to list directory: {}", e.message());
-            Err(format!("列目录失败: {}", e.message()))
+        let event = DirUsageProgressEvent {
+            profile_id: &profile_id,
+            path: &path,
+            usage,
+        };
+        if let Err(e) = app.emit("dir-usage-progress", event) {
+            error!("Failed to emit dir-usage-progress event: {}", e);
         }
     }
+    Err(Error::Integrity(
+        "Size summary ended without a final result".to_string(),
+    ))
 }
 
-// 3. 上传文件 (核心逻辑源自原 src/client.rs::upload_file)
+// 3. 上传文件 (传输逻辑位于 rustsend_core::client)
 #[tauri::command]
 pub async fn upload_local_file(
     state: State<'_, ClientState>,
+    profile_id: String,
     local_path: String,
     target_dir: String,
-) -> Result<String, String> {
+    conflict_policy: Option<ConflictMode>,
+    mode: Option<UploadMode>,
+) -> Result<String, Error> {
+    let conflict_policy = conflict_policy.unwrap_or_default();
     // [LOG A: 初始日志]
     info!(
         "upload_local_file attempt from {:?} to {:?}",
@@ -129,153 +328,147 @@ pub async fn upload_local_file(
     );
 
     // 1. 获取 gRPC 客户端
-    let mut client = state.get_client().map_err(|e| {
-        error!(
-            "UPLOAD ERROR (Step 1): Failed to get gRPC client. Status: {}",
-            e.message()
-        );
-        e.message().to_string()
+    let remote = state.get_client(&profile_id).map_err(|e| {
+        error!("UPLOAD ERROR (Step 1): Failed to get gRPC client: {}", e);
+        e
     })?;
 
-    // 2. 验证本地文件路径和提取文件名
-
-    // FIX START: 强制修正路径逻辑
-    let home_dir = dirs::home_dir().ok_or_else(|| {
-        error!("UPLOAD ERROR (Step 2.1): Could not determine user home directory.");
-        "无法确定用户主目录".to_string()
-    })?;
-
-    let relative_path = Path::new(&local_path);
-
-    // 尝试移除路径前导的 '/'，如果存在的话，确保路径是相对于 Home 目录的。
-    let corrected_path = if let Ok(stripped) = relative_path.strip_prefix("/") {
-        stripped
-    } else if let Ok(stripped) = relative_path.strip_prefix("\\") {
-        // 兼容 Windows 路径
-        stripped
+    // 2. 验证本地文件路径
+    let actual_path = home_relative_path(&local_path)?;
+
+    // 增量 / 去重模式只发送服务器上还没有的数据
+    let inner = remote
+        .upload(
+            &actual_path,
+            target_dir,
+            conflict_policy,
+            mode.unwrap_or_default(),
+        )
+        .await?;
+    if inner.success {
+        info!("UPLOAD SUCCESS: Server returned success status.");
+        Ok(Message::Upload(&inner).to_string())
     } else {
-        relative_path
-    };
-
-    // 最终的绝对路径 = Home 目录 + 修正后的相对路径
-    let actual_path = home_dir.join(corrected_path);
-
-    info!("Path constructed: {:?}", actual_path);
-    // FIX END
-
-    let filename = actual_path
-        .file_name()
-        .ok_or_else(|| {
-            error!(
-                "UPLOAD ERROR (Step 2): Local path invalid or missing filename: {:?}",
-                actual_path
-            );
-            "本地文件路径无效或缺少文件名".to_string()
-        })?
-        .to_string_lossy()
-        .into_owned();
-
-    // 3. 打开本地文件
-    // 在主异步函数中打开文件，以进行错误处理
-    let file = std::fs::File::open(&actual_path).map_err(|e| {
+        // 如果服务器返回 success: false
         error!(
-            "UPLOAD ERROR (Step 3): Failed to open local file {:?}. Error: {}",
-            actual_path, e
+            "UPLOAD FAILED (Step 6.1): Server returned failure status: {}",
+            inner.message
         );
-        format!("打开本地文件失败: {}", e)
-    })?;
+        Err(Error::Remote {
+            code: tonic::Code::Unknown,
+            message: inner.message,
+        })
+    }
+}
 
-    // (tx_main, rx) - 主线程持有 tx_main
-    let (tx_main, rx) = mpsc::channel(4);
-
-    let filename_owned = filename.clone();
-    let target_dir_owned = target_dir.to_string();
-
-    // 将 tx_main 克隆给 spawn_blocking 任务
-    let tx_blocking = tx_main.clone();
-
-    let file_size = file.metadata().map(|m| m.len()).unwrap_or(0);
-
-    // [LOG B: 文件信息日志]
-    info!("Starting upload for: {} ({} bytes)", filename, file_size);
-
-    // 4. 启动阻塞任务进行 I/O
-    task::spawn_blocking(move || {
-        let mut file = file;
-        let mut buffer = vec![0u8; CHUNK_SIZE];
-        let mut eof = false;
-
-        loop {
-            let bytes_read = match file.read(&mut buffer) {
-                Ok(0) => {
-                    eof = true;
-                    0
-                }
-                Ok(n) => n,
-                Err(e) => {
-                    error!(
-                        "UPLOAD ERROR (Step 4.1): Failed to read local file chunk: {}",
-                        e
-                    );
-                    // 如果读取失败，也应该 break，让 tx_blocking 自动 drop
-                    break;
-                }
-            };
-
-            // ... (chunk 构造逻辑不变) ...
-
-            let chunk_data = if bytes_read > 0 {
-                &buffer[..bytes_read]
-            } else {
-                &[]
-            };
-
-            let chunk = FileChunk {
-                filename: filename_owned.clone(),
-                target_dir: target_dir_owned.clone(),
-                data: chunk_data.to_vec(),
-                eof,
-            };
-
-            // Use blocking_send inside spawn_blocking
-            if tx_blocking.blocking_send(chunk).is_err() {
-                // 这个错误通常是因为接收端 rx 提前关闭，这意味着 gRPC 调用已经失败或取消
-                error!("UPLOAD ERROR (Step 4.2): Failed to send chunk to gRPC stream (receiver closed)");
-                break;
-            }
-
-            if eof {
-                break;
-            }
+/// `sync-finished` / `two-way-sync-finished` 事件的负载
+#[derive(Clone, Serialize)]
+struct SyncFinishedEvent<'a, R> {
+    profile_id: &'a str,
+    remote_dir: &'a str,
+    report: &'a R,
+}
+
+/// 4. 单向同步：比较本地目录与远程目录并返回同步计划
+///
+/// `dry_run` 为 true 时只返回计划；否则计划通过传输队列在后台执行，
+/// 完成后发送 `sync-finished` 事件
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn sync_push(
+    app: AppHandle,
+    state: State<'_, ClientState>,
+    transfers: State<'_, TransferState>,
+    profile_id: String,
+    local_dir: String,
+    remote_dir: String,
+    options: Option<SyncOptions>,
+    dry_run: bool,
+) -> Result<SyncPlan, Error> {
+    let remote = state.get_client(&profile_id)?;
+    let local_dir = home_relative_path(&local_dir)?;
+    let plan = sync::plan_push(
+        &remote,
+        &local_dir,
+        &remote_dir,
+        options.unwrap_or_default(),
+    )
+    .await?;
+    if dry_run {
+        return Ok(plan);
+    }
+
+    let queue = transfers.0.clone();
+    let running = plan.clone();
+    async_runtime::spawn(async move {
+        let report = sync::execute(&running, &remote, &queue).await;
+        let event = SyncFinishedEvent {
+            profile_id: &profile_id,
+            remote_dir: &running.remote_dir,
+            report: &report,
+        };
+        if let Err(e) = app.emit("sync-finished", event) {
+            error!("Failed to emit sync-finished event: {}", e);
         }
-        // 当此 spawn_blocking 任务结束时，tx_blocking 被 drop
     });
+    Ok(plan)
+}
 
-    // 5. 丢弃主线程的 Sender，允许流终止
-    drop(tx_main);
-
-    let request_stream = tonic::Request::new(ReceiverStream::new(rx));
-
-    // 6. 发起 gRPC 调用
-    match client.upload_file(request_stream).await {
-        Ok(response) => {
-            let inner = response.into_inner();
-            if inner.success {
-                info!("UPLOAD SUCCESS: Server returned success status.");
-                Ok(format!("✅ 上传成功: {}", inner.message))
-            } else {
-                // 如果服务器返回 success: false
-                error!(
-                    "UPLOAD FAILED (Step 6.1): Server returned failure status: {}",
-                    inner.message
-                );
-                Err(format!("❌ 上传失败: {}", inner.message))
-            }
-        }
-        Err(e) => {
-            // gRPC 调用失败，可能是网络问题或服务器内部错误
-            error!("UPLOAD FAILED (Step 6.2): gRPC call failed. Error: {}", e);
-            Err(format!("gRPC 调用失败: {}", e.message()))
-        }
+/// `two-way-sync-failed` 事件的负载
+#[derive(Clone, Serialize)]
+struct SyncFailedEvent<'a> {
+    profile_id: &'a str,
+    remote_dir: &'a str,
+    error: &'a Error,
+}
+
+/// 5. 双向同步：按上次同步的状态比较两侧变化并返回同步计划
+///
+/// `dry_run` 为 false 时计划在后台执行，完成后发送 `two-way-sync-finished`
+/// 事件，保存同步状态失败时发送 `two-way-sync-failed`
+#[tauri::command]
+pub async fn sync_two_way(
+    app: AppHandle,
+    state: State<'_, ClientState>,
+    transfers: State<'_, TransferState>,
+    profile_id: String,
+    local_dir: String,
+    remote_dir: String,
+    dry_run: bool,
+) -> Result<BisyncPlan, Error> {
+    let remote = state.get_client(&profile_id)?;
+    let local_dir = home_relative_path(&local_dir)?;
+    let mut sync_state = SyncState::open(remote.url(), &local_dir, &remote_dir);
+    let plan = bisync::plan(&remote, &local_dir, &remote_dir, &sync_state).await?;
+    if dry_run {
+        return Ok(plan);
     }
+
+    let queue = transfers.0.clone();
+    let running = plan.clone();
+    async_runtime::spawn(async move {
+        let result = bisync::execute(&running, &remote, &queue, &mut sync_state).await;
+        let emitted = match &result {
+            Ok(report) => app.emit(
+                "two-way-sync-finished",
+                SyncFinishedEvent {
+                    profile_id: &profile_id,
+                    remote_dir: &running.remote_dir,
+                    report,
+                },
+            ),
+            Err(error) => app.emit(
+                "two-way-sync-failed",
+                SyncFailedEvent {
+                    profile_id: &profile_id,
+                    remote_dir: &running.remote_dir,
+                    error,
+                },
+            ),
+        };
+        if let Err(e) = emitted {
+            error!("Failed to emit two-way sync event: {}", e);
+        }
+    });
+    Ok(plan)
 }
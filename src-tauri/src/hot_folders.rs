@@ -0,0 +1,143 @@
+// src/hot_folders.rs
+
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use rustsend_core::error::Error;
+use rustsend_core::hot_folder::{self, FolderWatcher, HotFolder, HotFolderStore};
+use rustsend_core::transfer::TransferKind;
+use serde::Serialize;
+use std::collections::HashMap;
+use tauri::async_runtime::{self, JoinHandle};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::grpc_client::{ensure_connected, home_relative_path};
+use crate::transfers::TransferState;
+
+// --- 热文件夹状态 ---
+
+/// 已保存的热文件夹配置，以及正在运行的监视任务 (按 ID)
+pub struct HotFolderState {
+    store: Mutex<HotFolderStore>,
+    running: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl HotFolderState {
+    /// 从默认位置加载；文件损坏时保留原文件并使用旁边的新文件
+    pub fn load() -> Self {
+        let path = HotFolderStore::default_path();
+        let store = HotFolderStore::load(&path).unwrap_or_else(|e| {
+            error!("Failed to load hot folders from {}: {}", path.display(), e);
+            let fallback = path.with_extension("recovered.json");
+            HotFolderStore::load(&fallback).unwrap_or_else(|_| HotFolderStore::new(fallback))
+        });
+        HotFolderState {
+            store: Mutex::new(store),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn stop(&self, id: &str) {
+        if let Some(task) = self.running.lock().remove(id) {
+            // 任务持有监视器，中止即停止监视
+            task.abort();
+            info!("Stopped hot folder {}", id);
+        }
+    }
+}
+
+/// `hot-folder-error` 事件的负载
+#[derive(Clone, Serialize)]
+struct HotFolderErrorEvent<'a> {
+    id: &'a str,
+    error: &'a Error,
+}
+
+fn emit_error(app: &AppHandle, id: &str, error: &Error) {
+    if let Err(e) = app.emit("hot-folder-error", HotFolderErrorEvent { id, error }) {
+        error!("Failed to emit hot-folder-error event: {}", e);
+    }
+}
+
+/// 开始监视一个热文件夹 (目录不存在时自动创建)，替换它已有的监视任务。
+/// 写完的文件作为上传任务加入传输队列，未连接时按配置自动连接
+fn start(app: &AppHandle, folder: HotFolder) -> Result<(), Error> {
+    let hot_folders = app.state::<HotFolderState>();
+    hot_folders.stop(&folder.id);
+
+    let dir = home_relative_path(&folder.local_dir)?;
+    std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+    let mut watcher = FolderWatcher::start(&dir, folder.recursive)?;
+    info!(
+        "Watching hot folder {} -> {}",
+        dir.display(),
+        folder.target_dir
+    );
+
+    let app = app.clone();
+    let id = folder.id.clone();
+    let task = async_runtime::spawn(async move {
+        while let Some(file) = watcher.next().await {
+            let remote = match ensure_connected(&app, &folder.profile_id).await {
+                Ok(remote) => remote,
+                Err(e) => {
+                    warn!("Hot folder could not send {}: {}", file.display(), e);
+                    emit_error(&app, &folder.id, &e);
+                    continue;
+                }
+            };
+            let kind = TransferKind::Upload {
+                target_dir: hot_folder::target_dir_for(&dir, &folder.target_dir, &file),
+                local_path: file,
+                conflict_policy: folder.conflict_policy,
+                mode: folder.mode,
+            };
+            app.state::<TransferState>().0.enqueue(remote, kind);
+        }
+        warn!("Hot folder watch of {} ended", dir.display());
+    });
+    hot_folders.running.lock().insert(id, task);
+    Ok(())
+}
+
+/// 启动所有已启用的热文件夹；需在异步运行时中调用
+pub fn start_all(app: &AppHandle) {
+    let folders = app.state::<HotFolderState>().store.lock().list();
+    for folder in folders.into_iter().filter(|f| f.enabled) {
+        if let Err(e) = start(app, folder.clone()) {
+            error!("Failed to start hot folder {}: {}", folder.local_dir, e);
+            emit_error(app, &folder.id, &e);
+        }
+    }
+}
+
+// --- Tauri Commands ---
+
+/// 列出所有热文件夹配置
+#[tauri::command]
+pub fn list_hot_folders(hot_folders: State<'_, HotFolderState>) -> Vec<HotFolder> {
+    hot_folders.store.lock().list()
+}
+
+/// 新建或更新热文件夹；id 为空时自动生成。保存后按 enabled 立即开始或停止监视
+#[tauri::command]
+pub async fn save_hot_folder(
+    app: AppHandle,
+    hot_folders: State<'_, HotFolderState>,
+    folder: HotFolder,
+) -> Result<HotFolder, Error> {
+    let folder = hot_folders.store.lock().upsert(folder)?;
+    if folder.enabled {
+        start(&app, folder.clone())?;
+    } else {
+        hot_folders.stop(&folder.id);
+    }
+    Ok(folder)
+}
+
+/// 停止并删除热文件夹 (已加入队列的上传不受影响)
+#[tauri::command]
+pub fn delete_hot_folder(hot_folders: State<'_, HotFolderState>, id: String) -> Result<(), Error> {
+    hot_folders.stop(&id);
+    hot_folders.store.lock().remove(&id)?;
+    Ok(())
+}
@@ -5,18 +5,32 @@ use log::{error, info};
 // use tokio::runtime::Runtime;
 
 // 引入 ClientState 和 gRPC 命令
-use crate::grpc_client::{connect_server, list_remote_dir, upload_local_file, ClientState};
+use crate::grpc_client::{
+    connect_server, disconnect_server, get_connection_state, get_server_info, list_connections,
+    list_remote_dir, remote_dir_usage, remote_disk_usage, sync_push, sync_two_way,
+    upload_local_file, ClientState,
+};
+use crate::profiles::{
+    delete_profile, list_profiles, save_profile, set_profile_local_path, ProfileState,
+};
 // 引入 Tauri 的专用异步运行时
-use crate::commands::list_local_dir;
+use crate::commands::{get_locale, list_local_dir, set_locale};
+use crate::hot_folders::{delete_hot_folder, list_hot_folders, save_hot_folder, HotFolderState};
+use crate::remote_search::{cancel_remote_search, start_remote_search, RemoteSearchState};
+use crate::remote_watch::{unwatch_remote_dir, watch_remote_dir, RemoteWatchState};
+use crate::transfers::{
+    cancel_transfer, clear_transfers, forward_transfer_events, list_transfers, TransferState,
+};
+use rustsend_core::i18n::Message;
 use tauri::{async_runtime, Emitter};
 mod commands;
 mod grpc_client;
-mod server;
-mod server_starter;
-// 引入 gRPC 结构 (如果未通过 build.rs 引入)
-pub mod filerpc {
-    tonic::include_proto!("filerpc");
-}
+mod hot_folders;
+mod profiles;
+mod remote_search;
+mod remote_watch;
+mod task_registry;
+mod transfers;
 
 // 示例命令：保留 greet (可选)
 #[tauri::command]
@@ -30,19 +44,31 @@ pub fn run() {
     // 核心修改：使用 tauri::async_runtime::spawn 替换所有 tokio::spawn
     // ----------------------------------------------------------------------
 
+    let transfers = TransferState::default();
+    let transfer_queue = transfers.0.clone();
+
     tauri::Builder::default()
         // 核心：使用 setup hook
-        .setup(|app| {
+        .setup(move |app| {
             // 在这里，我们处于 Tauri 内部的 Tokio 运行时环境，可以安全地使用 async_runtime::spawn
             let handle = app.handle().clone();
+            forward_transfer_events(handle.clone(), &transfer_queue);
+
+            // 启动已启用的热文件夹 (监视器需要在异步运行时中创建)
+            let hot_folder_handle = handle.clone();
+            async_runtime::spawn(async move { hot_folders::start_all(&hot_folder_handle) });
 
             // 启动后台 gRPC Server
             async_runtime::spawn(async move {
                 // <-- 关键修改：使用 async_runtime::spawn
-                if let Err(e) = crate::server_starter::start_background_server().await {
+                if let Err(e) = rustsend_core::server_starter::start_background_server().await {
                     error!("Background gRPC server failed: {:?}", e);
                     // 理论上可以在这里发送事件通知前端
-                    let _ = handle.emit("server-error", format!("Server failed: {:?}", e));
+                    let reason = e.to_string();
+                    let _ = handle.emit(
+                        "server-error",
+                        Message::ServerFailed { reason: &reason }.to_string(),
+                    );
                 }
             });
             info!("Background gRPC server spawned successfully inside Tauri's setup hook.");
@@ -51,11 +77,40 @@ pub fn run() {
         })
         .plugin(tauri_plugin_opener::init())
         .manage(ClientState::new()) // 客户端状态管理
+        .manage(ProfileState::load()) // 已保存的连接配置
+        .manage(transfers) // 传输队列
+        .manage(HotFolderState::load()) // 热文件夹
+        .manage(RemoteWatchState::default()) // 远程目录监视
+        .manage(RemoteSearchState::default()) // 远程查找
         .invoke_handler(tauri::generate_handler![
             connect_server,
+            disconnect_server,
+            get_server_info,
+            get_connection_state,
+            list_connections,
+            list_profiles,
+            save_profile,
+            delete_profile,
+            set_profile_local_path,
             list_remote_dir,
+            remote_disk_usage,
+            remote_dir_usage,
             upload_local_file,
+            sync_push,
+            sync_two_way,
+            list_transfers,
+            cancel_transfer,
+            clear_transfers,
+            list_hot_folders,
+            save_hot_folder,
+            delete_hot_folder,
+            watch_remote_dir,
+            unwatch_remote_dir,
+            start_remote_search,
+            cancel_remote_search,
             list_local_dir,
+            get_locale,
+            set_locale,
             greet
         ])
         .run(tauri::generate_context!())
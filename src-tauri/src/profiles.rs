@@ -0,0 +1,77 @@
+// src-tauri/src/profiles.rs
+
+use log::{error, warn};
+use parking_lot::Mutex;
+use rustsend_core::error::Error;
+use rustsend_core::profile::{Profile, ProfileStore};
+use tauri::State;
+
+use crate::grpc_client::ClientState;
+
+// --- 连接配置状态 ---
+
+/// 已保存的连接配置，修改后立即写回磁盘
+pub struct ProfileState(Mutex<ProfileStore>);
+
+impl ProfileState {
+    /// 从默认位置加载；文件损坏时保留原文件并使用旁边的新文件
+    pub fn load() -> Self {
+        let path = ProfileStore::default_path();
+        let store = ProfileStore::load(&path).unwrap_or_else(|e| {
+            error!("Failed to load profiles from {}: {}", path.display(), e);
+            let fallback = path.with_extension("recovered.json");
+            ProfileStore::load(&fallback).unwrap_or_else(|_| ProfileStore::new(fallback))
+        });
+        ProfileState(Mutex::new(store))
+    }
+
+    pub fn get(&self, id: &str) -> Result<Profile, Error> {
+        self.0.lock().get(id).cloned()
+    }
+
+    /// 记录最后使用的路径；失败只记日志，不影响当前操作
+    pub fn update_paths(&self, id: &str, remote: Option<String>, local: Option<String>) {
+        if let Err(e) = self.0.lock().update_paths(id, remote, local) {
+            warn!("Failed to remember paths for profile {}: {}", id, e);
+        }
+    }
+}
+
+// --- Tauri Commands ---
+
+/// 列出所有已保存的连接配置
+#[tauri::command]
+pub fn list_profiles(profiles: State<'_, ProfileState>) -> Vec<Profile> {
+    profiles.0.lock().list()
+}
+
+/// 新建或更新连接配置；id 为空时自动生成，返回保存后的配置
+#[tauri::command]
+pub fn save_profile(profiles: State<'_, ProfileState>, profile: Profile) -> Result<Profile, Error> {
+    profiles.0.lock().upsert(profile)
+}
+
+/// 删除连接配置，同时断开它的连接
+#[tauri::command]
+pub fn delete_profile(
+    profiles: State<'_, ProfileState>,
+    clients: State<'_, ClientState>,
+    profile_id: String,
+) -> Result<(), Error> {
+    profiles.0.lock().remove(&profile_id)?;
+    clients.disconnect(&profile_id);
+    Ok(())
+}
+
+/// 记住某个配置最后浏览的本地路径
+#[tauri::command]
+pub fn set_profile_local_path(
+    profiles: State<'_, ProfileState>,
+    profile_id: String,
+    path: String,
+) -> Result<(), Error> {
+    profiles
+        .0
+        .lock()
+        .update_paths(&profile_id, None, Some(path))
+}
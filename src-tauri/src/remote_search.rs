@@ -0,0 +1,116 @@
+// src/remote_search.rs
+
+use log::{error, info, warn};
+use rustsend_core::client::{RemoteSearchMatch, SearchQuery};
+use rustsend_core::error::Error;
+use serde::Serialize;
+use std::time::Duration;
+use tauri::async_runtime;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::grpc_client::ensure_connected;
+use crate::task_registry::CancellableTaskRegistry;
+
+/// 结果攒批推送给前端的间隔，避免每条结果触发一次界面刷新
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+// --- 远程查找状态 ---
+
+/// 每个连接配置最多一个进行中的远程查找 (按配置 ID)
+#[derive(Default)]
+pub struct RemoteSearchState(CancellableTaskRegistry<String>);
+
+impl RemoteSearchState {
+    fn cancel(&self, profile_id: &str) {
+        // 任务持有结果流，中止即通知服务端停止查找
+        if self.0.cancel(profile_id) {
+            info!("Cancelled remote search of profile {}", profile_id);
+        }
+    }
+}
+
+/// `remote-search-results` 事件的负载
+#[derive(Clone, Serialize)]
+struct RemoteSearchResultsEvent<'a> {
+    profile_id: &'a str,
+    matches: &'a [RemoteSearchMatch],
+}
+
+/// `remote-search-ended` 事件的负载；查找完成时 error 为空
+#[derive(Clone, Serialize)]
+struct RemoteSearchEndedEvent<'a> {
+    profile_id: &'a str,
+    error: Option<&'a Error>,
+}
+
+fn emit_results(app: &AppHandle, profile_id: &str, matches: &mut Vec<RemoteSearchMatch>) {
+    if matches.is_empty() {
+        return;
+    }
+    let event = RemoteSearchResultsEvent {
+        profile_id,
+        matches,
+    };
+    if let Err(e) = app.emit("remote-search-results", event) {
+        error!("Failed to emit remote-search-results event: {}", e);
+    }
+    matches.clear();
+}
+
+// --- Tauri Commands ---
+
+/// 在远程目录树中查找，替换该配置已有的查找。
+/// 结果分批以 `remote-search-results` 事件推送，结束时发送 `remote-search-ended`
+#[tauri::command]
+pub async fn start_remote_search(
+    app: AppHandle,
+    searches: State<'_, RemoteSearchState>,
+    profile_id: String,
+    root: String,
+    query: SearchQuery,
+) -> Result<(), Error> {
+    searches.cancel(&profile_id);
+    let remote = ensure_connected(&app, &profile_id).await?;
+    let mut search = remote.search(root.clone(), query).await?;
+
+    let task_app = app.clone();
+    let id = profile_id.clone();
+    searches.0.start(profile_id, |generation| {
+        async_runtime::spawn(async move {
+            let mut pending = Vec::new();
+            let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+            let result = loop {
+                tokio::select! {
+                    found = search.next() => match found {
+                        Ok(Some(found)) => pending.push(found),
+                        Ok(None) => break Ok(()),
+                        Err(e) => break Err(e),
+                    },
+                    _ = flush.tick() => emit_results(&task_app, &id, &mut pending),
+                }
+            };
+            emit_results(&task_app, &id, &mut pending);
+            if let Err(e) = &result {
+                warn!("Remote search under {} failed: {}", root, e);
+            }
+            let event = RemoteSearchEndedEvent {
+                profile_id: &id,
+                error: result.as_ref().err(),
+            };
+            if let Err(e) = task_app.emit("remote-search-ended", event) {
+                error!("Failed to emit remote-search-ended event: {}", e);
+            }
+            task_app
+                .state::<RemoteSearchState>()
+                .0
+                .finish(&id, generation);
+        })
+    });
+    Ok(())
+}
+
+/// 取消该配置进行中的远程查找；没有查找时不做任何事
+#[tauri::command]
+pub fn cancel_remote_search(searches: State<'_, RemoteSearchState>, profile_id: String) {
+    searches.cancel(&profile_id);
+}
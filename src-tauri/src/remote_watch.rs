@@ -0,0 +1,103 @@
+// src/remote_watch.rs
+
+use log::{error, info, warn};
+use rustsend_core::client::RemoteDirChange;
+use rustsend_core::error::Error;
+use serde::Serialize;
+use tauri::async_runtime;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::grpc_client::ensure_connected;
+use crate::task_registry::CancellableTaskRegistry;
+
+// --- 远程目录监视状态 ---
+
+/// 每个连接配置最多一个进行中的远程目录监视 (按配置 ID)
+#[derive(Default)]
+pub struct RemoteWatchState(CancellableTaskRegistry<String>);
+
+impl RemoteWatchState {
+    fn stop(&self, profile_id: &str) {
+        // 任务持有监视流，中止即通知服务端停止监视
+        if self.0.cancel(profile_id) {
+            info!("Stopped remote watch of profile {}", profile_id);
+        }
+    }
+}
+
+/// `remote-dir-changed` 事件的负载
+#[derive(Clone, Serialize)]
+struct RemoteDirChangedEvent<'a> {
+    profile_id: &'a str,
+    path: &'a str,
+    changes: Vec<RemoteDirChange>,
+}
+
+/// `remote-watch-ended` 事件的负载；服务端正常结束时 error 为空
+#[derive(Clone, Serialize)]
+struct RemoteWatchEndedEvent<'a> {
+    profile_id: &'a str,
+    path: &'a str,
+    error: Option<&'a Error>,
+}
+
+// --- Tauri Commands ---
+
+/// 开始监视远程目录 (不含子目录)，替换该配置已有的监视。
+/// 变化以 `remote-dir-changed` 事件推送，监视结束时发送 `remote-watch-ended`
+#[tauri::command]
+pub async fn watch_remote_dir(
+    app: AppHandle,
+    watches: State<'_, RemoteWatchState>,
+    profile_id: String,
+    path: String,
+) -> Result<(), Error> {
+    watches.stop(&profile_id);
+    let remote = ensure_connected(&app, &profile_id).await?;
+    let mut watch = remote.watch_dir(path.clone(), false).await?;
+
+    let task_app = app.clone();
+    let id = profile_id.clone();
+    watches.0.start(profile_id, |generation| {
+        async_runtime::spawn(async move {
+            let result = loop {
+                match watch.next().await {
+                    Ok(Some(changes)) => {
+                        let event = RemoteDirChangedEvent {
+                            profile_id: &id,
+                            path: &path,
+                            changes,
+                        };
+                        if let Err(e) = task_app.emit("remote-dir-changed", event) {
+                            error!("Failed to emit remote-dir-changed event: {}", e);
+                        }
+                    }
+                    Ok(None) => break Ok(()),
+                    Err(e) => break Err(e),
+                }
+            };
+            if let Err(e) = &result {
+                warn!("Remote watch of {} ended: {}", path, e);
+            }
+            let event = RemoteWatchEndedEvent {
+                profile_id: &id,
+                path: &path,
+                error: result.as_ref().err(),
+            };
+            if let Err(e) = task_app.emit("remote-watch-ended", event) {
+                error!("Failed to emit remote-watch-ended event: {}", e);
+            }
+            task_app
+                .state::<RemoteWatchState>()
+                .0
+                .finish(&id, generation);
+        })
+    });
+    Ok(())
+}
+
+/// 停止该配置的远程目录监视；没有监视时不做任何事
+#[tauri::command]
+pub fn unwatch_remote_dir(watches: State<'_, RemoteWatchState>, profile_id: String) {
+    watches.stop(&profile_id);
+}
@@ -0,0 +1,64 @@
+// src/task_registry.rs
+
+use parking_lot::Mutex;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::async_runtime::JoinHandle;
+
+/// 按键登记的后台任务，每个键最多一个 (如每个连接配置一个远程监视)。
+/// 每个任务带一个代号，结束的任务只移除自己，不会误删替换它的新任务
+pub struct CancellableTaskRegistry<K> {
+    tasks: Mutex<HashMap<K, (u64, JoinHandle<()>)>>,
+    next_generation: AtomicU64,
+}
+
+impl<K> Default for CancellableTaskRegistry<K> {
+    fn default() -> Self {
+        CancellableTaskRegistry {
+            tasks: Mutex::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K: Eq + Hash> CancellableTaskRegistry<K> {
+    /// 以新代号启动任务并登记，替换 (中止) 该键已有的任务
+    pub fn start(&self, key: K, spawn: impl FnOnce(u64) -> JoinHandle<()>) {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        // 持锁期间启动，任务结束时的 finish 一定在登记之后执行
+        let mut tasks = self.tasks.lock();
+        let task = spawn(generation);
+        if let Some((_, previous)) = tasks.insert(key, (generation, task)) {
+            previous.abort();
+        }
+    }
+
+    /// 中止并移除该键的任务；有任务被中止时返回 true
+    pub fn cancel<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.tasks.lock().remove(key) {
+            Some((_, task)) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 任务结束时调用；只有登记的仍是这一代任务时才移除
+    pub fn finish<Q>(&self, key: &Q, generation: u64)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let mut tasks = self.tasks.lock();
+        if tasks.get(key).is_some_and(|(g, _)| *g == generation) {
+            tasks.remove(key);
+        }
+    }
+}
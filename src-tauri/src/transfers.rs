@@ -0,0 +1,53 @@
+// src/transfers.rs
+
+use log::{error, warn};
+use rustsend_core::transfer::{TransferId, TransferInfo, TransferQueue};
+use tauri::{async_runtime, AppHandle, Emitter, State};
+use tokio::sync::broadcast::error::RecvError;
+
+// --- 传输队列 ---
+
+/// 所有连接共用的传输队列 (同步等批量操作通过它执行)
+#[derive(Default)]
+pub struct TransferState(pub TransferQueue);
+
+/// 将队列中每次状态变化作为 `transfer-updated` 事件转发给前端
+pub fn forward_transfer_events(app: AppHandle, queue: &TransferQueue) {
+    let mut rx = queue.subscribe();
+    async_runtime::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(info) => {
+                    if let Err(e) = app.emit("transfer-updated", info) {
+                        error!("Failed to emit transfer-updated event: {}", e);
+                    }
+                }
+                // 前端可通过 list_transfers 重新同步
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Dropped {} transfer events", missed)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+// --- Tauri Commands ---
+
+/// 队列中的全部传输 (按加入顺序)
+#[tauri::command]
+pub fn list_transfers(transfers: State<'_, TransferState>) -> Vec<TransferInfo> {
+    transfers.0.list()
+}
+
+/// 取消排队中或进行中的传输；已结束或不存在时返回 false
+#[tauri::command]
+pub fn cancel_transfer(transfers: State<'_, TransferState>, id: TransferId) -> bool {
+    transfers.0.cancel(id)
+}
+
+/// 从列表中移除已结束的传输
+#[tauri::command]
+pub fn clear_transfers(transfers: State<'_, TransferState>) {
+    transfers.0.clear_finished()
+}